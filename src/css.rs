@@ -4,13 +4,67 @@ use std::fmt;
 #[derive(Debug)]
 pub struct Stylesheet {
     pub rules: Vec<Rule>,
+    /// URLs from `@import` rules, in order, as written
+    pub imports: Vec<String>,
 }
 
+impl Stylesheet {
+    /// A copy of the stylesheet keeping only the declarations whose property
+    /// name satisfies `predicate`, for example to strip `position` from
+    /// untrusted CSS. Rules and `@import`s are all kept.
+    pub fn filter_properties(&self, predicate: impl Fn(&str) -> bool) -> Stylesheet {
+        let rules = self
+            .rules
+            .iter()
+            .map(|rule| Rule {
+                selectors: rule.selectors.clone(),
+                declarations: rule
+                    .declarations
+                    .iter()
+                    .filter(|declaration| predicate(&declaration.name))
+                    .cloned()
+                    .collect(),
+                media: rule.media.clone(),
+            })
+            .collect();
+        Stylesheet {
+            rules,
+            imports: self.imports.clone(),
+        }
+    }
+}
+
+/// The properties the `background` shorthand sets, in the order
+/// `Parser::parse_background` sets them
+const BACKGROUND_LONGHANDS: [&str; 4] = [
+    "background-color",
+    "background-image",
+    "background-position",
+    "background-repeat",
+];
+
 /// A CSS rule
 #[derive(Debug)]
 pub struct Rule {
     pub selectors: Vec<Selector>,
     pub declarations: Vec<Declaration>,
+    /// Set for rules inside `@media`; the rule applies if any query matches
+    pub media: Option<Vec<MediaQuery>>,
+}
+
+/// One query of an `@media` list; it matches if all its conditions do
+pub type MediaQuery = Vec<MediaCondition>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MediaCondition {
+    /// A media type such as `screen` or `print`
+    Type(String),
+    MinWidth(Value),
+    MaxWidth(Value),
+    /// `prefers-color-scheme`, as `light` or `dark`
+    PrefersColorScheme(String),
+    /// A feature we can't evaluate, which never matches
+    Unsupported,
 }
 
 /// CSS selector (simplified - only supports simple selectors)
@@ -24,6 +78,52 @@ pub struct SimpleSelector {
     pub tag_name: Option<String>,
     pub id: Option<String>,
     pub classes: Vec<String>,
+    /// `:root` - only matches the document's root element
+    pub root: bool,
+    /// `:hover` - only matches while the pointer is over the element
+    pub hover: bool,
+    /// `:link` - only matches links to pages not yet visited
+    pub link: bool,
+    /// `:visited` - only matches links to visited pages
+    pub visited: bool,
+    /// The argument lists of each `:not(...)` - the element must match none
+    /// of the selectors in any of them
+    pub negations: Vec<Vec<SimpleSelector>>,
+    /// `[attr]`, `[attr=value]`, etc.
+    pub attributes: Vec<AttributeSelector>,
+    /// `::before`/`::after` - the rule styles that generated box rather than
+    /// the element itself
+    pub pseudo_element: Option<PseudoElement>,
+}
+
+/// A box generated next to an element's own content
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PseudoElement {
+    Before,
+    After,
+}
+
+/// A condition on one attribute, like `[href^="https"]`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AttributeSelector {
+    pub name: String,
+    pub operator: AttributeOperator,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttributeOperator {
+    /// `[attr]` - the attribute is present
+    Exists,
+    /// `[attr=value]`
+    Equals(String),
+    /// `[attr~=value]` - one of its whitespace-separated words is `value`
+    Includes(String),
+    /// `[attr^=value]`
+    Prefix(String),
+    /// `[attr$=value]`
+    Suffix(String),
+    /// `[attr*=value]`
+    Substring(String),
 }
 
 /// A CSS declaration (property: value)
@@ -31,6 +131,8 @@ pub struct SimpleSelector {
 pub struct Declaration {
     pub name: String,
     pub value: Value,
+    /// Marked `!important`
+    pub important: bool,
 }
 
 /// CSS values
@@ -38,12 +140,39 @@ pub struct Declaration {
 pub enum Value {
     Keyword(String),
     Length(f32, Unit),
+    /// A unitless number, as in `opacity: 0.5`
+    Number(f32),
     Color(Color),
+    /// A quoted string, as in `font-family: "Courier New"`
+    Str(String),
+    /// A `url(...)` reference; relative URLs are resolved against the
+    /// stylesheet's own URL when it's loaded
+    Url(String),
+    /// Comma-separated colors, as layered backgrounds; the first is on top
+    ColorList(Vec<Color>),
+    /// `translate(x, y)` transform offsets
+    Translate(Box<Value>, Box<Value>),
+    /// An `aspect-ratio` of width to height, as in `16 / 9`
+    Ratio(f32, f32),
+    /// A `background-position`, as horizontal and vertical offsets;
+    /// keywords become percentages, so `center` is `50% 50%`
+    Position(Box<Value>, Box<Value>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Unit {
     Px,
+    Em,
+    Rem,
+    /// The height of a lowercase `x`, taken as half an em
+    Ex,
+    /// The advance of a `0` in the element's font
+    Ch,
+    /// A point, 1/72 of an inch at 96 px per inch
+    Pt,
+    Percent,
+    Vw,
+    Vh,
 }
 
 #[derive(Debug, Clone, PartialEq, Copy)]
@@ -60,62 +189,418 @@ impl fmt::Display for Color {
     }
 }
 
+/// Convert a color from HSL to RGB. `hue` is in degrees (any value, taken
+/// modulo 360); `saturation` and `lightness` run from 0 to 1.
+pub fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> Color {
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let saturation = saturation.clamp(0.0, 1.0);
+    let lightness = lightness.clamp(0.0, 1.0);
+
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let second = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (r, g, b) = match hue as u32 {
+        0 => (chroma, second, 0.0),
+        1 => (second, chroma, 0.0),
+        2 => (0.0, chroma, second),
+        3 => (0.0, second, chroma),
+        4 => (second, 0.0, chroma),
+        _ => (chroma, 0.0, second),
+    };
+    let lightest = lightness - chroma / 2.0;
+    let channel = |n: f32| ((n + lightest) * 255.0).round().clamp(0.0, 255.0) as u8;
+    Color {
+        r: channel(r),
+        g: channel(g),
+        b: channel(b),
+        a: 255,
+    }
+}
+
 pub type Specificity = (usize, usize, usize);
 
 impl Selector {
     pub fn specificity(&self) -> Specificity {
         let Selector::Simple(ref simple) = *self;
-        let a = simple.id.iter().count();
-        let b = simple.classes.len();
-        let c = simple.tag_name.iter().count();
+        simple.specificity()
+    }
+}
+
+impl SimpleSelector {
+    fn specificity(&self) -> Specificity {
+        // :root ranks alongside an id
+        let mut a = self.id.iter().count() + self.root as usize;
+        let mut b = self.classes.len()
+            + self.attributes.len()
+            + self.hover as usize
+            + self.link as usize
+            + self.visited as usize;
+        let mut c = self.tag_name.iter().count() + self.pseudo_element.iter().count();
+        // :not() itself adds nothing, but its most specific argument counts
+        for arguments in &self.negations {
+            let (na, nb, nc) = arguments
+                .iter()
+                .map(SimpleSelector::specificity)
+                .max()
+                .unwrap_or((0, 0, 0));
+            a += na;
+            b += nb;
+            c += nc;
+        }
         (a, b, c)
     }
 }
 
+/// Why a piece of CSS couldn't be parsed
+#[derive(Clone, Debug, PartialEq)]
+pub struct CssParseError {
+    pub message: String,
+}
+
+impl fmt::Display for CssParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid CSS: {}", self.message)
+    }
+}
+
+impl std::error::Error for CssParseError {}
+
+impl CssParseError {
+    fn new(message: impl Into<String>) -> Self {
+        CssParseError {
+            message: message.into(),
+        }
+    }
+}
+
+/// Parse a bare declaration block, such as `color: red; width: 10px`, with
+/// no selector or braces around it. The semicolon after the last
+/// declaration is optional.
+pub fn parse_declarations(source: &str) -> Result<Vec<Declaration>, CssParseError> {
+    // Parse it as the body of a rule, so the value parsers always find a
+    // closing brace after the last declaration
+    let mut parser = Parser::new(format!("{{{}}}", source));
+    let declarations = parser.parse_declarations()?;
+    if !parser.eof() {
+        return Err(CssParseError::new("unexpected '}' in declarations"));
+    }
+    Ok(declarations)
+}
+
+/// The specificity of a selector written as a string, such as `div.note`,
+/// as `(ids, classes, tags)`
+pub fn selector_specificity(source: &str) -> Result<Specificity, CssParseError> {
+    Ok(Parser::parse_selector(source.to_string())?.specificity())
+}
+
 /// CSS parser
 pub struct Parser {
     pos: usize,
     input: String,
+    /// `@import` URLs seen so far
+    imports: Vec<String>,
+    /// Declarations that were skipped rather than failing the parse
+    warnings: Vec<String>,
 }
 
 impl Parser {
-    /// Parse a CSS stylesheet
-    pub fn parse(source: String) -> Stylesheet {
-        let mut parser = Parser {
+    fn new(input: String) -> Parser {
+        Parser {
             pos: 0,
-            input: source,
+            input,
+            imports: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Parse a CSS stylesheet, printing any declarations it skipped. Panics
+    /// on malformed input; see `try_parse`.
+    pub fn parse(source: String) -> Stylesheet {
+        match Parser::parse_with_warnings(source) {
+            Ok((stylesheet, warnings)) => {
+                for warning in warnings {
+                    eprintln!("Warning: {}", warning);
+                }
+                stylesheet
+            }
+            Err(error) => panic!("{}", error),
+        }
+    }
+
+    /// Parse a CSS stylesheet, failing on malformed input instead of
+    /// panicking. Skipped declarations aren't reported; see
+    /// `parse_with_warnings`.
+    pub fn try_parse(source: String) -> Result<Stylesheet, CssParseError> {
+        Parser::parse_with_warnings(source).map(|(stylesheet, _)| stylesheet)
+    }
+
+    /// Parse a CSS stylesheet, also returning the declarations that were
+    /// skipped (vendor-prefixed ones with values this parser can't read)
+    pub fn parse_with_warnings(source: String) -> Result<(Stylesheet, Vec<String>), CssParseError> {
+        let mut parser = Parser::new(source);
+        let rules = parser.parse_rules()?;
+        let stylesheet = Stylesheet {
+            rules,
+            imports: parser.imports,
         };
-        Stylesheet {
-            rules: parser.parse_rules(),
+        Ok((stylesheet, parser.warnings))
+    }
+
+    /// Parse a single selector, such as `div.note`, with nothing else
+    /// around it but whitespace
+    pub fn parse_selector(source: String) -> Result<Selector, CssParseError> {
+        let mut parser = Parser::new(source);
+        parser.consume_whitespace();
+        if parser.eof() {
+            return Err(CssParseError::new("empty selector"));
+        }
+        let selector = Selector::Simple(parser.parse_simple_selector()?);
+        parser.consume_whitespace();
+        if !parser.eof() {
+            return Err(CssParseError::new("unexpected characters after selector"));
         }
+        Ok(selector)
     }
 
     /// Parse a list of rules
-    fn parse_rules(&mut self) -> Vec<Rule> {
+    fn parse_rules(&mut self) -> Result<Vec<Rule>, CssParseError> {
         let mut rules = Vec::new();
         loop {
             self.consume_whitespace();
             if self.eof() {
                 break;
             }
-            rules.push(self.parse_rule());
+            if self.next_char() == '@' {
+                self.parse_at_rule(&mut rules)?;
+            } else {
+                rules.push(self.parse_rule()?);
+            }
         }
-        rules
+        Ok(rules)
     }
 
     /// Parse a rule
-    fn parse_rule(&mut self) -> Rule {
-        Rule {
-            selectors: self.parse_selectors(),
-            declarations: self.parse_declarations(),
+    fn parse_rule(&mut self) -> Result<Rule, CssParseError> {
+        Ok(Rule {
+            selectors: self.parse_selectors()?,
+            declarations: self.parse_declarations()?,
+            media: None,
+        })
+    }
+
+    /// Parse an at-rule, adding any rules it contains. Unknown at-rules are
+    /// skipped.
+    fn parse_at_rule(&mut self, rules: &mut Vec<Rule>) -> Result<(), CssParseError> {
+        self.expect_char('@')?;
+        let name = self.parse_identifier().to_lowercase();
+        self.consume_whitespace();
+        match name.as_str() {
+            "media" => {
+                let queries = self.parse_media_query_list()?;
+                rules.extend(self.parse_media_block(queries)?);
+            }
+            "import" => {
+                let url = self.parse_import_url()?;
+                self.imports.push(url);
+                // Media conditions on imports aren't supported; skip them
+                self.skip_at_rule();
+            }
+            _ => self.skip_at_rule(),
+        }
+        Ok(())
+    }
+
+    /// Parse the URL of an `@import`, either `url(...)` or a quoted string
+    fn parse_import_url(&mut self) -> Result<String, CssParseError> {
+        if self.next_char() == '"' || self.next_char() == '\'' {
+            return self.parse_string();
+        }
+        let function = self.parse_identifier();
+        if !function.eq_ignore_ascii_case("url") {
+            return Err(CssParseError::new(format!(
+                "Expected a URL after @import, found {}",
+                function
+            )));
+        }
+        self.expect_char('(')?;
+        self.consume_whitespace();
+        let url = self.parse_url_contents()?;
+        self.consume_whitespace();
+        self.expect_char(')')?;
+        Ok(url)
+    }
+
+    /// Parse the URL inside `url(...)`, quoted or not
+    fn parse_url_contents(&mut self) -> Result<String, CssParseError> {
+        if self.next_char() == '"' || self.next_char() == '\'' {
+            self.parse_string()
+        } else {
+            Ok(self.consume_while(|c| c != ')' && !c.is_whitespace()))
+        }
+    }
+
+    /// Parse a single- or double-quoted string, returning its contents with
+    /// escapes resolved: `\` followed by up to six hex digits (and an
+    /// optional space) is that code point, an escaped newline continues the
+    /// string on the next line, and any other escaped character stands for
+    /// itself
+    fn parse_string(&mut self) -> Result<String, CssParseError> {
+        let quote = self.consume_char();
+        let mut contents = String::new();
+        loop {
+            if self.eof() {
+                return Err(CssParseError::new("Unterminated string"));
+            }
+            match self.consume_char() {
+                c if c == quote => break,
+                '\\' => {
+                    if self.eof() {
+                        return Err(CssParseError::new("Unterminated string"));
+                    }
+                    if self.next_char().is_ascii_hexdigit() {
+                        contents.push(self.parse_hex_escape());
+                    } else {
+                        match self.consume_char() {
+                            '\n' => {}
+                            c => contents.push(c),
+                        }
+                    }
+                }
+                c => contents.push(c),
+            }
+        }
+        Ok(contents)
+    }
+
+    /// Parse the hex digits of an escape like `\201C `, including the single
+    /// whitespace character that may end it
+    fn parse_hex_escape(&mut self) -> char {
+        let mut digits = String::new();
+        while digits.len() < 6 && !self.eof() && self.next_char().is_ascii_hexdigit() {
+            digits.push(self.consume_char());
+        }
+        if !self.eof() && self.next_char().is_whitespace() {
+            self.consume_char();
+        }
+        // Null, surrogates, and out-of-range code points become U+FFFD
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .filter(|&code| code != 0)
+            .and_then(char::from_u32)
+            .unwrap_or(char::REPLACEMENT_CHARACTER)
+    }
+
+    /// Parse a comma-separated media query list, up to the opening brace
+    fn parse_media_query_list(&mut self) -> Result<Vec<MediaQuery>, CssParseError> {
+        let mut queries: Vec<MediaQuery> = vec![Vec::new()];
+        loop {
+            self.consume_whitespace();
+            match self.next_char() {
+                '{' => break,
+                ',' => {
+                    self.consume_char();
+                    queries.push(Vec::new());
+                }
+                '(' => {
+                    let condition = self.parse_media_feature()?;
+                    queries.last_mut().unwrap().push(condition);
+                }
+                _ => {
+                    let word = self.parse_identifier().to_lowercase();
+                    match word.as_str() {
+                        "and" | "only" => {}
+                        "" => {
+                            return Err(CssParseError::new(format!(
+                                "Unexpected character {} in media query",
+                                self.next_char()
+                            )))
+                        }
+                        _ => queries.last_mut().unwrap().push(MediaCondition::Type(word)),
+                    }
+                }
+            }
+        }
+        Ok(queries)
+    }
+
+    /// Parse a parenthesized media feature like `(max-width: 600px)`
+    fn parse_media_feature(&mut self) -> Result<MediaCondition, CssParseError> {
+        self.expect_char('(')?;
+        self.consume_whitespace();
+        let name = self.parse_identifier().to_lowercase();
+        self.consume_whitespace();
+        let condition = if self.next_char() == ':' {
+            self.consume_char();
+            self.consume_whitespace();
+            let value = self.parse_value()?;
+            match name.as_str() {
+                "min-width" => MediaCondition::MinWidth(value),
+                "max-width" => MediaCondition::MaxWidth(value),
+                "prefers-color-scheme" => match value {
+                    Value::Keyword(scheme) => {
+                        MediaCondition::PrefersColorScheme(scheme.to_lowercase())
+                    }
+                    _ => MediaCondition::Unsupported,
+                },
+                _ => MediaCondition::Unsupported,
+            }
+        } else {
+            MediaCondition::Unsupported
+        };
+        // Skip anything we didn't understand inside the parentheses
+        self.consume_while(|c| c != ')');
+        self.expect_char(')')?;
+        Ok(condition)
+    }
+
+    /// Parse the rules inside an `@media` block. Nested at-rules are skipped.
+    fn parse_media_block(&mut self, queries: Vec<MediaQuery>) -> Result<Vec<Rule>, CssParseError> {
+        self.expect_char('{')?;
+        let mut rules = Vec::new();
+        loop {
+            self.consume_whitespace();
+            match self.next_char() {
+                '}' => {
+                    self.consume_char();
+                    break;
+                }
+                '@' => {
+                    self.consume_char();
+                    self.parse_identifier();
+                    self.skip_at_rule();
+                }
+                _ => {
+                    let mut rule = self.parse_rule()?;
+                    rule.media = Some(queries.clone());
+                    rules.push(rule);
+                }
+            }
+        }
+        Ok(rules)
+    }
+
+    /// Skip the rest of an at-rule: up to a `;`, or past its `{...}` block
+    fn skip_at_rule(&mut self) {
+        let mut depth = 0;
+        while !self.eof() {
+            match self.consume_char() {
+                ';' if depth == 0 => return,
+                '{' => depth += 1,
+                '}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                _ => {}
+            }
         }
     }
 
     /// Parse selectors (comma-separated)
-    fn parse_selectors(&mut self) -> Vec<Selector> {
+    fn parse_selectors(&mut self) -> Result<Vec<Selector>, CssParseError> {
         let mut selectors = Vec::new();
         loop {
-            selectors.push(Selector::Simple(self.parse_simple_selector()));
+            selectors.push(Selector::Simple(self.parse_simple_selector()?));
             self.consume_whitespace();
             match self.next_char() {
                 ',' => {
@@ -123,19 +608,31 @@ impl Parser {
                     self.consume_whitespace();
                 }
                 '{' => break,
-                c => panic!("Unexpected character {} in selector list", c),
+                c => {
+                    return Err(CssParseError::new(format!(
+                        "Unexpected character {} in selector list",
+                        c
+                    )))
+                }
             }
         }
-        selectors.sort_by(|a, b| b.specificity().cmp(&a.specificity()));
-        selectors
+        selectors.sort_by_key(|s| std::cmp::Reverse(s.specificity()));
+        Ok(selectors)
     }
 
     /// Parse a simple selector
-    fn parse_simple_selector(&mut self) -> SimpleSelector {
+    fn parse_simple_selector(&mut self) -> Result<SimpleSelector, CssParseError> {
         let mut selector = SimpleSelector {
             tag_name: None,
             id: None,
             classes: Vec::new(),
+            root: false,
+            hover: false,
+            link: false,
+            visited: false,
+            negations: Vec::new(),
+            attributes: Vec::new(),
+            pseudo_element: None,
         };
         while !self.eof() {
             match self.next_char() {
@@ -150,18 +647,124 @@ impl Parser {
                 '*' => {
                     self.consume_char();
                 }
+                '[' => selector.attributes.push(self.parse_attribute_selector()?),
+                ':' => {
+                    self.consume_char();
+                    if self.next_char() == ':' {
+                        self.consume_char();
+                        selector.pseudo_element = Some(self.parse_pseudo_element()?);
+                        continue;
+                    }
+                    match self.parse_identifier().to_lowercase().as_str() {
+                        // The old single-colon spelling of pseudo-elements
+                        "before" => selector.pseudo_element = Some(PseudoElement::Before),
+                        "after" => selector.pseudo_element = Some(PseudoElement::After),
+                        "root" => selector.root = true,
+                        "hover" => selector.hover = true,
+                        "link" => selector.link = true,
+                        "visited" => selector.visited = true,
+                        "not" => {
+                            self.expect_char('(')?;
+                            let mut arguments = Vec::new();
+                            loop {
+                                self.consume_whitespace();
+                                if matches!(self.next_char(), ')' | ',') {
+                                    return Err(CssParseError::new("Empty :not argument"));
+                                }
+                                arguments.push(self.parse_simple_selector()?);
+                                self.consume_whitespace();
+                                match self.consume_char() {
+                                    ',' => continue,
+                                    ')' => break,
+                                    c => {
+                                        return Err(CssParseError::new(format!(
+                                            "Unexpected character {} in :not argument",
+                                            c
+                                        )))
+                                    }
+                                }
+                            }
+                            selector.negations.push(arguments);
+                        }
+                        name => {
+                            return Err(CssParseError::new(format!(
+                                "Unsupported pseudo-class :{}",
+                                name
+                            )))
+                        }
+                    }
+                }
                 c if valid_identifier_char(c) => {
                     selector.tag_name = Some(self.parse_identifier());
                 }
                 _ => break,
             }
         }
-        selector
+        Ok(selector)
+    }
+
+    /// Parse the name of a pseudo-element, after its `::`
+    fn parse_pseudo_element(&mut self) -> Result<PseudoElement, CssParseError> {
+        match self.parse_identifier().to_lowercase().as_str() {
+            "before" => Ok(PseudoElement::Before),
+            "after" => Ok(PseudoElement::After),
+            name => Err(CssParseError::new(format!(
+                "Unsupported pseudo-element ::{}",
+                name
+            ))),
+        }
+    }
+
+    /// Parse an attribute selector like `[name]` or `[name^="value"]`
+    fn parse_attribute_selector(&mut self) -> Result<AttributeSelector, CssParseError> {
+        self.expect_char('[')?;
+        self.consume_whitespace();
+        let name = self.parse_identifier().to_lowercase();
+        self.consume_whitespace();
+        let operator = match self.consume_char() {
+            ']' => {
+                return Ok(AttributeSelector {
+                    name,
+                    operator: AttributeOperator::Exists,
+                })
+            }
+            '=' => AttributeOperator::Equals,
+            c => {
+                if self.consume_char() != '=' {
+                    return Err(CssParseError::new("Expected = in attribute selector"));
+                }
+                match c {
+                    '~' => AttributeOperator::Includes,
+                    '^' => AttributeOperator::Prefix,
+                    '$' => AttributeOperator::Suffix,
+                    '*' => AttributeOperator::Substring,
+                    _ => {
+                        return Err(CssParseError::new(format!(
+                            "Unsupported attribute selector operator {}=",
+                            c
+                        )))
+                    }
+                }
+            }
+        };
+        self.consume_whitespace();
+        let value = match self.next_char() {
+            '"' | '\'' => self.parse_string()?,
+            _ => self.parse_identifier(),
+        };
+        self.consume_whitespace();
+        if self.consume_char() != ']' {
+            return Err(CssParseError::new("Expected ] after attribute selector"));
+        }
+        Ok(AttributeSelector {
+            name,
+            operator: operator(value),
+        })
     }
 
     /// Parse declarations inside {}
-    fn parse_declarations(&mut self) -> Vec<Declaration> {
-        assert_eq!(self.consume_char(), '{');
+    fn parse_declarations(&mut self) -> Result<Vec<Declaration>, CssParseError> {
+        self.expect_char('{')?;
         let mut declarations = Vec::new();
         loop {
             self.consume_whitespace();
@@ -169,71 +772,476 @@ impl Parser {
                 self.consume_char();
                 break;
             }
-            declarations.push(self.parse_declaration());
+            declarations.append(&mut self.parse_declaration()?);
         }
-        declarations
+        Ok(declarations)
     }
 
-    /// Parse a declaration
-    fn parse_declaration(&mut self) -> Declaration {
+    /// Parse a declaration, or the longhands a shorthand like `background`
+    /// expands into. A vendor-prefixed one (like `-webkit-transform`) whose
+    /// value can't be parsed is skipped with a warning, as those often use
+    /// syntax this parser doesn't support.
+    fn parse_declaration(&mut self) -> Result<Vec<Declaration>, CssParseError> {
         let property_name = self.parse_identifier();
         self.consume_whitespace();
-        assert_eq!(self.consume_char(), ':');
+        self.expect_char(':')?;
+        self.consume_whitespace();
+        let start = self.pos;
+        let (values, important) = match self.parse_declaration_value(&property_name) {
+            Ok(parsed) => parsed,
+            Err(error) if property_name.starts_with('-') => {
+                self.warnings
+                    .push(format!("Skipped {}: {}", property_name, error.message));
+                self.pos = start;
+                self.skip_declaration_value();
+                return Ok(Vec::new());
+            }
+            Err(error) => return Err(error),
+        };
+
+        Ok(values
+            .into_iter()
+            .map(|(name, value)| Declaration {
+                name,
+                value,
+                important,
+            })
+            .collect())
+    }
+
+    /// Parse the value of a declaration (as the longhands it sets, for a
+    /// shorthand), any `!important`, and the semicolon ending it
+    fn parse_declaration_value(
+        &mut self,
+        property_name: &str,
+    ) -> Result<(Vec<(String, Value)>, bool), CssParseError> {
+        let values = match property_name.to_lowercase().as_str() {
+            "background" => self.parse_background()?,
+            "background-position" => vec![(property_name.to_string(), self.parse_position()?)],
+            "aspect-ratio" => vec![(property_name.to_string(), self.parse_ratio()?)],
+            _ => vec![(property_name.to_string(), self.parse_value_list()?)],
+        };
+        self.consume_whitespace();
+        let important = self.parse_important()?;
+        // The last declaration in a block needn't end with a semicolon
+        if self.next_char() != '}' {
+            self.expect_char(';')?;
+        }
+        Ok((values, important))
+    }
+
+    /// Skip past a declaration's value to the semicolon ending it (or the
+    /// end of the block), ignoring any inside parentheses or strings
+    fn skip_declaration_value(&mut self) {
+        let (mut depth, mut quote) = (0, None);
+        while !self.eof() {
+            match (quote, self.next_char()) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, c @ ('"' | '\'')) => quote = Some(c),
+                (None, '(') => depth += 1,
+                (None, ')') => depth -= 1,
+                (None, ';') if depth <= 0 => {
+                    self.consume_char();
+                    return;
+                }
+                (None, '}') if depth <= 0 => return,
+                _ => {}
+            }
+            self.consume_char();
+        }
+    }
+
+    /// Parse an optional `!important` annotation
+    fn parse_important(&mut self) -> Result<bool, CssParseError> {
+        if self.next_char() != '!' {
+            return Ok(false);
+        }
+        self.consume_char();
+        self.consume_whitespace();
+        let keyword = self.parse_identifier();
+        self.consume_whitespace();
+        if !keyword.eq_ignore_ascii_case("important") {
+            return Err(CssParseError::new(format!(
+                "Unexpected !{} in declaration",
+                keyword
+            )));
+        }
+        Ok(true)
+    }
+
+    /// Parse a value, or a comma-separated list of colors. Other lists, like
+    /// font-family fallbacks, keep only their first value, as there's only
+    /// one font to choose from.
+    fn parse_value_list(&mut self) -> Result<Value, CssParseError> {
+        let first = self.parse_value()?;
         self.consume_whitespace();
-        let value = self.parse_value();
+        if self.next_char() != ',' {
+            return Ok(first);
+        }
+
+        let mut values = vec![first];
+        while self.next_char() == ',' {
+            self.consume_char();
+            self.consume_whitespace();
+            values.push(self.parse_value()?);
+            self.consume_whitespace();
+        }
+        if !matches!(values[0], Value::Color(_)) {
+            return Ok(values.swap_remove(0));
+        }
+        let colors = values
+            .into_iter()
+            .map(|value| match value {
+                Value::Color(color) => Ok(color),
+                other => Err(CssParseError::new(format!(
+                    "Unsupported value {:?} in list",
+                    other
+                ))),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Value::ColorList(colors))
+    }
+
+    /// Parse a `background-position` of one or two lengths, percentages, or
+    /// keywords. A missing second value is `center`, and keywords may come
+    /// in either order (`top left` is `left top`).
+    fn parse_position(&mut self) -> Result<Value, CssParseError> {
+        let first = self.parse_value()?;
         self.consume_whitespace();
-        assert_eq!(self.consume_char(), ';');
+        let second = match self.next_char() {
+            ';' | '}' | '!' => Value::Keyword("center".to_string()),
+            _ => self.parse_value()?,
+        };
+        position_value(first, second)
+    }
+
+    /// Parse the `background` shorthand into `background-color`,
+    /// `-image`, `-position`, and `-repeat`. Its parts may come in any order,
+    /// and any left out are reset to their initial values. A comma-separated
+    /// list of colors sets layered background colors.
+    fn parse_background(&mut self) -> Result<Vec<(String, Value)>, CssParseError> {
+        let keyword = |name: &str| Value::Keyword(name.to_string());
+        let mut color = Value::Color(Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        });
+        let mut image = keyword("none");
+        let mut repeat = keyword("repeat");
+        let mut position = Vec::new();
+        let mut css_wide = None;
+        loop {
+            let value = self.parse_value()?;
+            self.consume_whitespace();
+            let name = match &value {
+                Value::Keyword(name) => name.to_lowercase(),
+                _ => String::new(),
+            };
+            match name.as_str() {
+                "inherit" | "initial" | "unset" => css_wide = Some(value),
+                "none" => image = value,
+                "repeat" | "no-repeat" | "repeat-x" | "repeat-y" | "space" | "round" => {
+                    repeat = value
+                }
+                "left" | "right" | "top" | "bottom" | "center" => position.push(value),
+                _ => match value {
+                    Value::Url(_) => image = value,
+                    Value::Length(..) | Value::Number(_) => position.push(value),
+                    _ => color = value,
+                },
+            }
+            if self.eof() || matches!(self.next_char(), ';' | '}' | '!' | ',') {
+                break;
+            }
+        }
+        // A CSS-wide keyword applies to every longhand
+        if let Some(value) = css_wide {
+            return Ok(BACKGROUND_LONGHANDS
+                .iter()
+                .map(|name| (name.to_string(), value.clone()))
+                .collect());
+        }
+
+        if !self.eof() && self.next_char() == ',' {
+            let mut colors = Vec::new();
+            let mut layer = color;
+            loop {
+                match layer {
+                    Value::Color(layer) => colors.push(layer),
+                    other => {
+                        return Err(CssParseError::new(format!(
+                            "Unsupported value {:?} in list",
+                            other
+                        )))
+                    }
+                }
+                if self.next_char() != ',' {
+                    break;
+                }
+                self.consume_char();
+                self.consume_whitespace();
+                layer = self.parse_value()?;
+                self.consume_whitespace();
+            }
+            color = Value::ColorList(colors);
+        }
 
-        Declaration {
-            name: property_name,
-            value,
+        if position.len() > 2 {
+            return Err(CssParseError::new(
+                "Expected at most 2 background-position values",
+            ));
+        }
+        let mut parts = position.into_iter();
+        let position = match (parts.next(), parts.next()) {
+            (None, _) => position_value(keyword("left"), keyword("top"))?,
+            (Some(first), None) => position_value(first, keyword("center"))?,
+            (Some(first), Some(second)) => position_value(first, second)?,
+        };
+        let values = [color, image, position, repeat];
+        Ok(BACKGROUND_LONGHANDS
+            .iter()
+            .map(|name| name.to_string())
+            .zip(values)
+            .collect())
+    }
+
+    /// Parse an `aspect-ratio` of `W / H`, or a single number for `W / 1`.
+    /// Other values, like `auto`, are kept as they are.
+    fn parse_ratio(&mut self) -> Result<Value, CssParseError> {
+        let width = match self.parse_value()? {
+            Value::Number(width) => width,
+            other => return Ok(other),
+        };
+        self.consume_whitespace();
+        if self.next_char() != '/' {
+            return Ok(Value::Ratio(width, 1.0));
+        }
+        self.consume_char();
+        self.consume_whitespace();
+        match self.parse_value()? {
+            Value::Number(height) => Ok(Value::Ratio(width, height)),
+            other => Err(CssParseError::new(format!(
+                "Expected a number after / in aspect-ratio, found {:?}",
+                other
+            ))),
         }
     }
 
     /// Parse a value
-    fn parse_value(&mut self) -> Value {
+    fn parse_value(&mut self) -> Result<Value, CssParseError> {
         match self.next_char() {
-            '0'..='9' => self.parse_length(),
+            '0'..='9' | '.' => self.parse_length(),
+            // A sign starts a number only if a digit follows; otherwise it's
+            // part of an identifier like `-webkit-box`
+            '-' | '+' if self.sign_starts_number() => self.parse_length(),
             '#' => self.parse_color(),
-            _ => Value::Keyword(self.parse_identifier()),
+            '"' | '\'' => Ok(Value::Str(self.parse_string()?)),
+            _ => {
+                let keyword = self.parse_identifier();
+                if !self.eof() && self.next_char() == '(' {
+                    return self.parse_function(&keyword);
+                }
+                // Keywords are case-insensitive; keep one spelling for currentColor
+                if keyword.eq_ignore_ascii_case("currentcolor") {
+                    Ok(Value::Keyword("currentColor".to_string()))
+                } else {
+                    Ok(Value::Keyword(keyword))
+                }
+            }
         }
     }
 
-    /// Parse a length
-    fn parse_length(&mut self) -> Value {
-        Value::Length(self.parse_float(), self.parse_unit())
+    /// Parse a functional notation value such as `translate(10px, 5px)`
+    fn parse_function(&mut self, name: &str) -> Result<Value, CssParseError> {
+        self.expect_char('(')?;
+        self.consume_whitespace();
+        let value = match name.to_lowercase().as_str() {
+            "translate" => {
+                let x = self.parse_value()?;
+                self.consume_whitespace();
+                // The y offset is optional and defaults to zero
+                let y = if self.next_char() == ',' {
+                    self.consume_char();
+                    self.consume_whitespace();
+                    self.parse_value()?
+                } else {
+                    Value::Length(0.0, Unit::Px)
+                };
+                Value::Translate(Box::new(x), Box::new(y))
+            }
+            "rgb" | "rgba" => self.parse_rgb_function()?,
+            "hsl" | "hsla" => self.parse_hsl_function()?,
+            "url" => Value::Url(self.parse_url_contents()?),
+            _ => {
+                return Err(CssParseError::new(format!(
+                    "Unsupported function {}()",
+                    name
+                )))
+            }
+        };
+        self.consume_whitespace();
+        self.expect_char(')')?;
+        Ok(value)
+    }
+
+    /// Parse the arguments of `rgb()`/`rgba()`; alpha is a number from 0 to 1
+    fn parse_rgb_function(&mut self) -> Result<Value, CssParseError> {
+        let mut channels = Vec::new();
+        loop {
+            channels.push(self.parse_float()?);
+            self.consume_whitespace();
+            if self.next_char() != ',' {
+                break;
+            }
+            self.consume_char();
+            self.consume_whitespace();
+        }
+        let alpha = match channels.len() {
+            3 => 1.0,
+            4 => channels[3],
+            n => {
+                return Err(CssParseError::new(format!(
+                    "Expected 3 or 4 color channels, got {}",
+                    n
+                )))
+            }
+        };
+        let channel = |n: f32| n.round().clamp(0.0, 255.0) as u8;
+        Ok(Value::Color(Color {
+            r: channel(channels[0]),
+            g: channel(channels[1]),
+            b: channel(channels[2]),
+            a: channel(alpha * 255.0),
+        }))
+    }
+
+    /// Parse the arguments of `hsl()`/`hsla()`: a hue in degrees (`deg` is
+    /// optional), saturation and lightness percentages, and an optional
+    /// alpha from 0 to 1
+    fn parse_hsl_function(&mut self) -> Result<Value, CssParseError> {
+        let mut arguments = Vec::new();
+        loop {
+            arguments.push(self.parse_float()?);
+            // Units are implied by position, so just skip them
+            if self.next_char() == '%' {
+                self.consume_char();
+            } else {
+                self.parse_identifier();
+            }
+            self.consume_whitespace();
+            if self.next_char() != ',' {
+                break;
+            }
+            self.consume_char();
+            self.consume_whitespace();
+        }
+        let alpha = match arguments.len() {
+            3 => 1.0,
+            4 => arguments[3],
+            n => {
+                return Err(CssParseError::new(format!(
+                    "Expected 3 or 4 hsl() arguments, got {}",
+                    n
+                )))
+            }
+        };
+        let color = hsl_to_rgb(arguments[0], arguments[1] / 100.0, arguments[2] / 100.0);
+        Ok(Value::Color(Color {
+            a: (alpha * 255.0).round().clamp(0.0, 255.0) as u8,
+            ..color
+        }))
+    }
+
+    /// Parse a length, or a plain number if no unit follows
+    fn parse_length(&mut self) -> Result<Value, CssParseError> {
+        let n = self.parse_float()?;
+        if self.eof() || !(self.next_char() == '%' || valid_identifier_char(self.next_char())) {
+            return Ok(Value::Number(n));
+        }
+        Ok(Value::Length(n, self.parse_unit()?))
     }
 
     /// Parse a float
-    fn parse_float(&mut self) -> f32 {
+    fn parse_float(&mut self) -> Result<f32, CssParseError> {
+        let sign = match self.next_char() {
+            '-' => {
+                self.consume_char();
+                -1.0
+            }
+            '+' => {
+                self.consume_char();
+                1.0
+            }
+            _ => 1.0,
+        };
         let s = self.consume_while(|c| matches!(c, '0'..='9' | '.'));
-        s.parse().unwrap()
+        // A lone `.` or `-` isn't a number
+        if !s.contains(|c: char| c.is_ascii_digit()) {
+            return Err(CssParseError::new(format!(
+                "Expected a number, found {:?}",
+                s
+            )));
+        }
+        match s.parse::<f32>() {
+            Ok(n) => Ok(sign * n),
+            Err(_) => Err(CssParseError::new(format!("Invalid number {:?}", s))),
+        }
+    }
+
+    /// Whether the character after a leading `-`/`+` begins a number
+    fn sign_starts_number(&self) -> bool {
+        matches!(
+            self.input[self.pos + 1..].chars().next(),
+            Some('0'..='9' | '.')
+        )
     }
 
     /// Parse a unit
-    fn parse_unit(&mut self) -> Unit {
+    fn parse_unit(&mut self) -> Result<Unit, CssParseError> {
+        if !self.eof() && self.next_char() == '%' {
+            self.consume_char();
+            return Ok(Unit::Percent);
+        }
         match self.parse_identifier().to_lowercase().as_str() {
-            "px" => Unit::Px,
-            _ => panic!("Unrecognized unit"),
+            "px" => Ok(Unit::Px),
+            "em" => Ok(Unit::Em),
+            "rem" => Ok(Unit::Rem),
+            "ex" => Ok(Unit::Ex),
+            "ch" => Ok(Unit::Ch),
+            "pt" => Ok(Unit::Pt),
+            "vw" => Ok(Unit::Vw),
+            "vh" => Ok(Unit::Vh),
+            unit => Err(CssParseError::new(format!("Unrecognized unit {}", unit))),
         }
     }
 
-    /// Parse a hex color
-    fn parse_color(&mut self) -> Value {
-        assert_eq!(self.consume_char(), '#');
-        Value::Color(Color {
-            r: self.parse_hex_pair(),
-            g: self.parse_hex_pair(),
-            b: self.parse_hex_pair(),
+    /// Parse a hex color, `#rrggbb` or the short form `#rgb`
+    fn parse_color(&mut self) -> Result<Value, CssParseError> {
+        self.expect_char('#')?;
+        let digits = self.consume_while(|c| c.is_ascii_hexdigit());
+        let width = match digits.len() {
+            3 => 1,
+            6 => 2,
+            n => {
+                return Err(CssParseError::new(format!(
+                    "Expected 3 or 6 hex digits in color, got {}",
+                    n
+                )))
+            }
+        };
+        // A short-form digit stands for itself twice, so `f` is `ff`
+        let channel = |i: usize| {
+            let digit = &digits[i * width..(i + 1) * width];
+            u8::from_str_radix(&digit.repeat(3 - width), 16).unwrap()
+        };
+        Ok(Value::Color(Color {
+            r: channel(0),
+            g: channel(1),
+            b: channel(2),
             a: 255,
-        })
-    }
-
-    /// Parse two hex digits
-    fn parse_hex_pair(&mut self) -> u8 {
-        let s = &self.input[self.pos..self.pos + 2];
-        self.pos += 2;
-        u8::from_str_radix(s, 16).unwrap()
+        }))
     }
 
     /// Parse an identifier
@@ -258,9 +1266,27 @@ impl Parser {
         result
     }
 
-    /// Get next character without consuming
+    /// Consume the next character, failing unless it's `expected`
+    fn expect_char(&mut self, expected: char) -> Result<(), CssParseError> {
+        if self.eof() {
+            return Err(CssParseError::new(format!(
+                "Expected {} but reached the end of the input",
+                expected
+            )));
+        }
+        match self.consume_char() {
+            c if c == expected => Ok(()),
+            c => Err(CssParseError::new(format!(
+                "Expected {} but found {}",
+                expected, c
+            ))),
+        }
+    }
+
+    /// Get next character without consuming, or NUL at the end of the input
+    /// (which no caller accepts, so running out of input is a parse error)
     fn next_char(&self) -> char {
-        self.input[self.pos..].chars().next().unwrap()
+        self.input[self.pos..].chars().next().unwrap_or('\0')
     }
 
     /// Check if at end
@@ -268,16 +1294,247 @@ impl Parser {
         self.pos >= self.input.len()
     }
 
-    /// Consume and return next character
+    /// Consume and return next character, or NUL at the end of the input
     fn consume_char(&mut self) -> char {
         let mut iter = self.input[self.pos..].char_indices();
-        let (_, cur_char) = iter.next().unwrap();
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
+        let Some((_, cur_char)) = iter.next() else {
+            return '\0';
+        };
+        let (next_pos, _) = iter.next().unwrap_or((cur_char.len_utf8(), ' '));
         self.pos += next_pos;
         cur_char
     }
 }
 
+/// A `background-position` from its two components, which keywords let
+/// come in either order (`top left` is `left top`)
+fn position_value(first: Value, second: Value) -> Result<Value, CssParseError> {
+    let keyword = |value: &Value| match value {
+        Value::Keyword(keyword) => keyword.to_lowercase(),
+        _ => String::new(),
+    };
+    let (x, y) = if matches!(keyword(&first).as_str(), "top" | "bottom")
+        || matches!(keyword(&second).as_str(), "left" | "right")
+    {
+        (second, first)
+    } else {
+        (first, second)
+    };
+    Ok(Value::Position(
+        Box::new(position_offset(x)?),
+        Box::new(position_offset(y)?),
+    ))
+}
+
+/// A `background-position` component as an offset, with keywords turned
+/// into the percentages they stand for
+fn position_offset(value: Value) -> Result<Value, CssParseError> {
+    let percent = match &value {
+        Value::Keyword(keyword) => match keyword.to_lowercase().as_str() {
+            "left" | "top" => 0.0,
+            "center" => 50.0,
+            "right" | "bottom" => 100.0,
+            _ => {
+                return Err(CssParseError::new(format!(
+                    "Unsupported background-position keyword {}",
+                    keyword
+                )))
+            }
+        },
+        Value::Number(0.0) => 0.0,
+        _ => return Ok(value),
+    };
+    Ok(Value::Length(percent, Unit::Percent))
+}
+
 fn valid_identifier_char(c: char) -> bool {
     c.is_alphanumeric() || c == '-' || c == '_'
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Stylesheet {
+        Parser::parse(source.to_string())
+    }
+
+    #[test]
+    fn filter_properties_drops_only_rejected_declarations() {
+        let stylesheet = parse(
+            ".a { position: fixed; color: red; } \
+             .b { z-index: 3; position: absolute; width: 10px; }",
+        );
+        let filtered = stylesheet.filter_properties(|name| name != "position");
+
+        let names: Vec<Vec<&str>> = filtered
+            .rules
+            .iter()
+            .map(|rule| rule.declarations.iter().map(|d| d.name.as_str()).collect())
+            .collect();
+        assert_eq!(names, vec![vec!["color"], vec!["z-index", "width"]]);
+        assert_eq!(filtered.rules[0].selectors, stylesheet.rules[0].selectors);
+        assert_eq!(
+            filtered.rules[1].declarations[1],
+            stylesheet.rules[1].declarations[2]
+        );
+    }
+
+    #[test]
+    fn parse_declarations_reads_a_bare_block() {
+        let declarations = parse_declarations("color: red; width: 10px").unwrap();
+        assert_eq!(declarations.len(), 2);
+        assert_eq!(declarations[0].name, "color");
+        assert_eq!(declarations[1].value, Value::Length(10.0, Unit::Px));
+    }
+
+    #[test]
+    fn malformed_css_is_an_error_not_a_panic() {
+        assert!(parse_declarations("width: 10qq").is_err());
+        assert!(parse_declarations("color: red }").is_err());
+        assert!(Parser::try_parse("div { color: red".to_string()).is_err());
+        assert!(Parser::try_parse("div { content: \"open }".to_string()).is_err());
+    }
+
+    #[test]
+    fn selector_specificity_counts_ids_classes_and_tags() {
+        assert_eq!(selector_specificity("#id"), Ok((1, 0, 0)));
+        assert_eq!(selector_specificity(".class"), Ok((0, 1, 0)));
+        assert_eq!(selector_specificity("tag"), Ok((0, 0, 1)));
+        assert!(selector_specificity("div > p").is_err());
+        assert!(selector_specificity("").is_err());
+    }
+
+    #[test]
+    fn not_takes_a_list_of_selectors() {
+        let Ok(Selector::Simple(selector)) = Parser::parse_selector("p:not(.a, .b)".to_string())
+        else {
+            panic!("p:not(.a, .b) should parse");
+        };
+        let classes: Vec<&[String]> = selector.negations[0]
+            .iter()
+            .map(|argument| argument.classes.as_slice())
+            .collect();
+        assert_eq!(classes, vec![["a".to_string()], ["b".to_string()]]);
+        assert!(Parser::parse_selector("p:not()".to_string()).is_err());
+        assert!(Parser::parse_selector("p:not(.a,)".to_string()).is_err());
+        assert!(Parser::parse_selector("p:not( )".to_string()).is_err());
+    }
+
+    #[test]
+    fn unparseable_vendor_declarations_are_skipped_with_a_warning() {
+        let source = "div { color: red; -webkit-transform: rotate(45deg); width: 10px; }";
+        let (stylesheet, warnings) = Parser::parse_with_warnings(source.to_string()).unwrap();
+        let names: Vec<&str> = stylesheet.rules[0]
+            .declarations
+            .iter()
+            .map(|declaration| declaration.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["color", "width"]);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("-webkit-transform"));
+    }
+
+    #[test]
+    fn background_shorthand_expands_into_four_longhands() {
+        let declarations =
+            parse_declarations("background: #fff url(bg.png) no-repeat center").unwrap();
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        };
+        let center = || Box::new(Value::Length(50.0, Unit::Percent));
+        let longhands: Vec<(&str, &Value)> = declarations
+            .iter()
+            .map(|declaration| (declaration.name.as_str(), &declaration.value))
+            .collect();
+        assert_eq!(
+            longhands,
+            vec![
+                ("background-color", &Value::Color(white)),
+                ("background-image", &Value::Url("bg.png".to_string())),
+                ("background-position", &Value::Position(center(), center())),
+                (
+                    "background-repeat",
+                    &Value::Keyword("no-repeat".to_string())
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_background_shorthand_is_an_error() {
+        assert!(parse_declarations("background: #fff, url(a.ppm)").is_err());
+        assert!(parse_declarations("background: left top 10px 20px").is_err());
+    }
+
+    #[test]
+    fn unknown_background_position_keyword_is_an_error() {
+        assert!(parse_declarations("background-position: middle").is_err());
+        assert!(parse_declarations("background-position: right 10px").is_ok());
+    }
+
+    #[test]
+    fn aspect_ratio_needs_a_number_after_the_slash() {
+        let declarations = parse_declarations("aspect-ratio: 16 / 9").unwrap();
+        assert_eq!(declarations[0].value, Value::Ratio(16.0, 9.0));
+        assert!(parse_declarations("aspect-ratio: 16 / auto").is_err());
+    }
+
+    #[test]
+    fn signed_lengths_parse_and_a_bare_dot_is_an_error() {
+        let declarations =
+            parse_declarations("margin-left: -5px; margin-top: +3px; width: .5em").unwrap();
+        let values: Vec<&Value> = declarations
+            .iter()
+            .map(|declaration| &declaration.value)
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                &Value::Length(-5.0, Unit::Px),
+                &Value::Length(3.0, Unit::Px),
+                &Value::Length(0.5, Unit::Em),
+            ]
+        );
+        assert!(parse_declarations("width: .").is_err());
+        assert!(parse_declarations("width: rgb(.)").is_err());
+    }
+
+    #[test]
+    fn unitless_opacity_parses_to_a_number() {
+        let declarations = parse_declarations("opacity: 0.5").unwrap();
+        assert_eq!(declarations[0].value, Value::Number(0.5));
+    }
+
+    #[test]
+    fn hsl_colors_convert_to_rgb() {
+        let declarations = parse_declarations("color: hsl(120, 100%, 25%)").unwrap();
+        assert_eq!(
+            declarations[0].value,
+            Value::Color(Color {
+                r: 0,
+                g: 128,
+                b: 0,
+                a: 255
+            })
+        );
+    }
+
+    #[test]
+    fn string_escapes_are_resolved() {
+        let string = |source: &str| Parser::new(source.to_string()).parse_string();
+        assert_eq!(string(r#""a\"b""#), Ok("a\"b".to_string()));
+        assert_eq!(string(r"'it\'s'"), Ok("it's".to_string()));
+        assert_eq!(
+            string(r#""\201C quoted\201D""#),
+            Ok("\u{201c}quoted\u{201d}".to_string())
+        );
+        assert_eq!(string("\"\\26 B\""), Ok("&B".to_string()));
+        assert_eq!(string("\"one\\\ntwo\""), Ok("onetwo".to_string()));
+        assert_eq!(string(r#""\0""#), Ok("\u{fffd}".to_string()));
+        assert!(string(r#""open\"#).is_err());
+    }
+}
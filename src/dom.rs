@@ -5,6 +5,12 @@ use std::collections::HashMap;
 pub struct Node {
     pub node_type: NodeType,
     pub children: Vec<Node>,
+    /// Byte range `(start, end)` of the node in its source, when the parser
+    /// was asked to record it
+    pub span: Option<(usize, usize)>,
+    /// Text whose whitespace is rendered as written, as if by
+    /// `white-space: pre`, instead of being collapsed
+    pub whitespace_significant: bool,
 }
 
 /// Types of DOM nodes
@@ -17,7 +23,10 @@ pub enum NodeType {
 /// An HTML element with tag name and attributes
 #[derive(Debug, Clone, PartialEq)]
 pub struct ElementData {
+    /// Lowercased, for matching
     pub tag_name: String,
+    /// The tag name as written in the source, if its case differed
+    pub original_tag_name: Option<String>,
     pub attributes: AttrMap,
 }
 
@@ -29,6 +38,8 @@ impl Node {
         Node {
             node_type: NodeType::Text(data),
             children: Vec::new(),
+            span: None,
+            whitespace_significant: false,
         }
     }
 
@@ -37,13 +48,137 @@ impl Node {
         Node {
             node_type: NodeType::Element(ElementData {
                 tag_name: name,
+                original_tag_name: None,
                 attributes: attrs,
             }),
             children,
+            span: None,
+            whitespace_significant: false,
+        }
+    }
+
+    /// Visit this node and its descendants in document order, passing each
+    /// node with its depth (0 for this node)
+    pub fn walk(&self, visitor: &mut impl FnMut(&Node, usize)) {
+        self.walk_at_depth(0, visitor);
+    }
+
+    fn walk_at_depth(&self, depth: usize, visitor: &mut impl FnMut(&Node, usize)) {
+        visitor(self, depth);
+        for child in &self.children {
+            child.walk_at_depth(depth + 1, visitor);
+        }
+    }
+
+    /// The nodes from this one down to `target` (inclusive), if `target` is
+    /// this node or one of its descendants
+    pub fn path_to(&self, target: &Node) -> Option<Vec<&Node>> {
+        if std::ptr::eq(self, target) {
+            return Some(vec![self]);
+        }
+        self.children.iter().find_map(|child| {
+            let mut path = child.path_to(target)?;
+            path.insert(0, self);
+            Some(path)
+        })
+    }
+
+    /// Serialize this node and its descendants as HTML. Elements keep their
+    /// original tag name spelling, and attributes are sorted by name so the
+    /// output is stable.
+    pub fn to_html(&self) -> String {
+        let mut html = String::new();
+        self.write_html(&mut html);
+        html
+    }
+
+    fn write_html(&self, html: &mut String) {
+        let elem = match self.node_type {
+            NodeType::Text(ref text) => {
+                html.push_str(&escape_html(text, false));
+                return;
+            }
+            NodeType::Element(ref elem) => elem,
+        };
+        let name = elem.original_tag_name.as_ref().unwrap_or(&elem.tag_name);
+        html.push('<');
+        html.push_str(name);
+        let mut attributes: Vec<_> = elem.attributes.iter().collect();
+        attributes.sort();
+        for (attr_name, value) in attributes {
+            html.push_str(&format!(" {}=\"{}\"", attr_name, escape_html(value, true)));
+        }
+        html.push('>');
+        if crate::html::is_void_element(&elem.tag_name) {
+            return;
+        }
+        for child in &self.children {
+            child.write_html(html);
+        }
+        html.push_str("</");
+        html.push_str(name);
+        html.push('>');
+    }
+
+    /// An indented outline of this node and its descendants for reading, one
+    /// line per node: elements as `tag#id.class` with any other attribute
+    /// names in brackets, and text quoted with its whitespace collapsed and
+    /// long runs cut short. Whitespace-only text is left out. Unlike
+    /// `to_html`, this doesn't round-trip.
+    pub fn pretty_print(&self) -> String {
+        let mut outline = String::new();
+        self.walk(&mut |node, depth| {
+            let line = match node.node_type {
+                NodeType::Element(ref elem) => elem.summary(),
+                NodeType::Text(_) if node.is_whitespace_text() => return,
+                NodeType::Text(ref text) => summarize_text(text),
+            };
+            outline.push_str(&"  ".repeat(depth));
+            outline.push_str(&line);
+            outline.push('\n');
+        });
+        outline
+    }
+
+    /// Whether this is a text node containing only whitespace (or nothing)
+    pub fn is_whitespace_text(&self) -> bool {
+        match self.node_type {
+            NodeType::Text(ref text) => text.trim().is_empty(),
+            NodeType::Element(_) => false,
         }
     }
 }
 
+/// Longest text, in characters, that `pretty_print` shows in full
+const PRETTY_TEXT_LIMIT: usize = 40;
+
+/// Text as `pretty_print` shows it: quoted, with whitespace collapsed and
+/// anything past the limit replaced by an ellipsis
+fn summarize_text(text: &str) -> String {
+    let collapsed = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if collapsed.chars().count() <= PRETTY_TEXT_LIMIT {
+        return format!("\"{}\"", collapsed);
+    }
+    let truncated: String = collapsed.chars().take(PRETTY_TEXT_LIMIT).collect();
+    format!("\"{}…\"", truncated.trim_end())
+}
+
+/// Escape the characters that would otherwise be read as markup, plus
+/// double quotes when `in_attribute`
+fn escape_html(text: &str, in_attribute: bool) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' if in_attribute => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
 impl ElementData {
     /// Get the value of an attribute by name
     pub fn get_attribute(&self, name: &str) -> Option<&String> {
@@ -61,4 +196,72 @@ impl ElementData {
             .map(|s| s.split_whitespace().collect())
             .unwrap_or_default()
     }
+
+    /// The element as a selector-like `tag#id.class`, followed by the names
+    /// of its other attributes, like `a.external [href, title]`
+    fn summary(&self) -> String {
+        let mut summary = self.tag_name.clone();
+        if let Some(id) = self.id() {
+            summary.push('#');
+            summary.push_str(id);
+        }
+        for class in self.classes() {
+            summary.push('.');
+            summary.push_str(class);
+        }
+        let mut others: Vec<_> = self
+            .attributes
+            .keys()
+            .filter(|name| *name != "id" && *name != "class")
+            .map(String::as_str)
+            .collect();
+        if !others.is_empty() {
+            others.sort();
+            summary.push_str(&format!(" [{}]", others.join(", ")));
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::html::Parser;
+
+    #[test]
+    fn to_html_escapes_text_and_attributes() {
+        let document = Parser::parse(r#"<p title="a &quot;b&quot;">1 &lt; 2</p>"#.to_string());
+        assert_eq!(
+            document.to_html(),
+            r#"<p title="a &quot;b&quot;">1 &lt; 2</p>"#
+        );
+    }
+
+    #[test]
+    fn walk_visits_nodes_in_document_order_with_depth() {
+        let document = Parser::parse("<div><p>a</p><span></span></div>".to_string());
+        let mut visited = Vec::new();
+        document.walk(&mut |node, depth| visited.push((node.to_html(), depth)));
+        let depths: Vec<usize> = visited.iter().map(|(_, depth)| *depth).collect();
+        assert_eq!(depths, vec![0, 1, 2, 1]);
+        assert_eq!(visited[2].0, "a");
+    }
+
+    #[test]
+    fn pretty_print_indents_and_summarizes_each_node() {
+        let long = "word ".repeat(12);
+        let source = format!(
+            "<div id=\"main\" class=\"a b\" title=\"t\" data-x=\"1\">\n  \
+             <p>  short\n   text </p>\n  <span>{}</span>\n</div>",
+            long
+        );
+        let document = Parser::parse_preserving_whitespace(source);
+        assert_eq!(
+            document.pretty_print(),
+            "div#main.a.b [data-x, title]\n\
+             \x20 p\n\
+             \x20   \"short text\"\n\
+             \x20 span\n\
+             \x20   \"word word word word word word word word…\"\n"
+        );
+    }
 }
@@ -0,0 +1,60 @@
+use ab_glyph::{point, Font as _, FontArc, InvalidFont, PxScale};
+use std::fs;
+use std::sync::OnceLock;
+
+/// DejaVu Sans Mono, built in so text renders without a font being configured
+/// (see `fonts/LICENSE-DejaVu.txt`)
+const EMBEDDED_FONT: &[u8] = include_bytes!("../fonts/DejaVuSansMono.ttf");
+
+/// A TrueType or OpenType font that text is painted with. Layout assumes
+/// every character has the same advance, so a monospace font fits best.
+#[derive(Clone)]
+pub struct Font {
+    inner: FontArc,
+}
+
+impl Font {
+    /// The font built into the engine. It's parsed once and shared, since
+    /// every new canvas starts out with it.
+    pub fn embedded() -> Font {
+        static EMBEDDED: OnceLock<Font> = OnceLock::new();
+        EMBEDDED
+            .get_or_init(|| {
+                let inner = FontArc::try_from_slice(EMBEDDED_FONT).expect("embedded font is valid");
+                Font { inner }
+            })
+            .clone()
+    }
+
+    /// Parse a font from the contents of a font file
+    pub fn from_bytes(data: &[u8]) -> Result<Font, InvalidFont> {
+        let inner = FontArc::try_from_vec(data.to_vec())?;
+        Ok(Font { inner })
+    }
+
+    /// Read and parse a font file
+    pub fn load(path: &str) -> Result<Font, Box<dyn std::error::Error>> {
+        Ok(Font::from_bytes(&fs::read(path)?)?)
+    }
+
+    /// Rasterize `ch` at a height of `size` px, calling `plot` with each
+    /// pixel's offset from the glyph's origin on the baseline (negative `y`
+    /// is above it) and how much of it the glyph covers (0 to 1).
+    /// Characters without a glyph, like spaces, plot nothing.
+    pub fn draw_glyph(&self, ch: char, size: f32, mut plot: impl FnMut(i32, i32, f32)) {
+        let glyph = self
+            .inner
+            .glyph_id(ch)
+            .with_scale_and_position(PxScale::from(size), point(0.0, 0.0));
+        if let Some(outlined) = self.inner.outline_glyph(glyph) {
+            let bounds = outlined.px_bounds();
+            outlined.draw(|x, y, coverage| {
+                plot(
+                    bounds.min.x as i32 + x as i32,
+                    bounds.min.y as i32 + y as i32,
+                    coverage,
+                );
+            });
+        }
+    }
+}
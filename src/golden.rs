@@ -0,0 +1,334 @@
+use crate::dom::Node;
+use crate::font::Font;
+use crate::painting::Canvas;
+use crate::{css, html, layout, painting, ppm, style, ua};
+use ab_glyph::InvalidFont;
+use std::time::{Duration, Instant};
+use std::{fmt, fs};
+
+/// Render an HTML/CSS pair to a canvas of the given size
+pub fn render(html: &str, css: &str, width: usize, height: usize) -> Canvas {
+    render_onto(html, css, Canvas::new(width, height))
+}
+
+/// Like `render`, but paint text in the font parsed from `font` (the
+/// contents of a TrueType or OpenType file) instead of the embedded one
+pub fn render_html_with_font(
+    html: &str,
+    css: &str,
+    width: usize,
+    height: usize,
+    font: &[u8],
+) -> Result<Canvas, InvalidFont> {
+    let mut canvas = Canvas::new(width, height);
+    canvas.set_font(Font::from_bytes(font)?);
+    Ok(render_onto(html, css, canvas))
+}
+
+/// Render an HTML/CSS pair onto a blank canvas, laid out at its size
+fn render_onto(html: &str, css: &str, canvas: Canvas) -> Canvas {
+    let root_node = html::Parser::parse(html.to_string());
+    let stylesheet = css::Parser::parse(css.to_string());
+    paint_document(&root_node, &stylesheet, canvas)
+}
+
+/// Style, lay out, and paint a parsed document onto a blank canvas
+fn paint_document(root_node: &Node, stylesheet: &css::Stylesheet, mut canvas: Canvas) -> Canvas {
+    let (width, height) = (canvas.width, canvas.height);
+    let ua_stylesheet = ua::stylesheet();
+    let style_root = style::style_tree(root_node, &[stylesheet], &ua_stylesheet, width as f32);
+
+    let mut viewport = layout::Dimensions::default();
+    viewport.content.width = width as f32;
+    viewport.content.height = height as f32;
+    let layout_root = layout::layout_tree(&style_root, viewport);
+
+    canvas.paint(&painting::build_display_list(&layout_root));
+    canvas
+}
+
+/// Lay out a whole document in a `width` by `height` px viewport, but paint
+/// only the first element matching `selector` and its descendants, onto a
+/// canvas the size of its border box. `None` if nothing matches.
+pub fn render_element(
+    html: &str,
+    css: &str,
+    selector: &str,
+    width: usize,
+    height: usize,
+) -> Option<Canvas> {
+    let root_node = html::Parser::parse(html.to_string());
+    let stylesheet = css::Parser::parse(css.to_string());
+    let ua_stylesheet = ua::stylesheet();
+    let style_root = style::style_tree(&root_node, &[&stylesheet], &ua_stylesheet, width as f32);
+
+    let mut viewport = layout::Dimensions::default();
+    viewport.content.width = width as f32;
+    viewport.content.height = height as f32;
+    let layout_root = layout::layout_tree(&style_root, viewport);
+
+    let element = layout::query_selector(&layout_root, selector)?;
+    let bounds = element.dimensions.border_box();
+    let mut canvas = Canvas::new(bounds.width.ceil() as usize, bounds.height.ceil() as usize);
+    canvas.paint(&painting::build_subtree_display_list(
+        element,
+        (bounds.x, bounds.y),
+    ));
+    Some(canvas)
+}
+
+/// Like `render`, but give up with an error if parsing and layout aren't done
+/// within `timeout`, for input that can't be trusted to render quickly
+pub fn render_with_timeout(
+    html: &str,
+    css: &str,
+    width: usize,
+    height: usize,
+    timeout: Duration,
+) -> Result<Canvas, String> {
+    let deadline = Instant::now() + timeout;
+    let limits = html::ParseLimits {
+        deadline: Some(deadline),
+        ..Default::default()
+    };
+    let root_node = html::Parser::parse_with_limits(html.to_string(), limits)
+        .map_err(|e| format!("HTML parsing failed: {}", e))?;
+    let stylesheet = css::Parser::parse(css.to_string());
+    let ua_stylesheet = ua::stylesheet();
+    let style_root = style::style_tree(&root_node, &[&stylesheet], &ua_stylesheet, width as f32);
+
+    let mut viewport = layout::Dimensions::default();
+    viewport.content.width = width as f32;
+    viewport.content.height = height as f32;
+    let layout_root = layout::layout_tree_with_deadline(&style_root, viewport, deadline);
+    if Instant::now() >= deadline {
+        return Err("rendering took longer than the deadline".to_string());
+    }
+
+    let mut canvas = Canvas::new(width, height);
+    canvas.paint(&painting::build_display_list(&layout_root));
+    Ok(canvas)
+}
+
+/// Something wrong with the input that `try_render` worked around
+#[derive(Clone, Debug, PartialEq)]
+pub enum Diagnostic {
+    /// Malformed HTML, parsed as well as it could be
+    Html(String),
+    /// A CSS statement or declaration that couldn't be parsed and was skipped
+    Css(String),
+    /// A declaration of a property the engine ignores
+    UnknownProperty(String),
+    /// An `@import` that wasn't fetched, so its rules are missing
+    Import(String),
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Diagnostic::Html(message) => write!(f, "HTML: {}", message),
+            Diagnostic::Css(message) => write!(f, "CSS: {}", message),
+            Diagnostic::UnknownProperty(name) => write!(f, "unknown property {}", name),
+            Diagnostic::Import(url) => write!(f, "@import {} not fetched", url),
+        }
+    }
+}
+
+/// Like `render`, but for input that may be malformed: instead of
+/// panicking, work around whatever can't be parsed and report it. CSS is
+/// parsed a rule at a time, so one bad rule only loses itself.
+pub fn try_render(html: &str, css: &str, width: usize, height: usize) -> (Canvas, Vec<Diagnostic>) {
+    let mut diagnostics = Vec::new();
+    let (root_node, warnings) = html::Parser::parse_with_warnings(html.to_string());
+    diagnostics.extend(warnings.into_iter().map(Diagnostic::Html));
+
+    let stylesheet = parse_css_leniently(css, &mut diagnostics);
+    let mut unknown: Vec<&str> = Vec::new();
+    for declaration in stylesheet.rules.iter().flat_map(|rule| &rule.declarations) {
+        let name = declaration.name.as_str();
+        if !style::is_supported_property(name) && !unknown.contains(&name) {
+            unknown.push(name);
+            diagnostics.push(Diagnostic::UnknownProperty(name.to_string()));
+        }
+    }
+    diagnostics.extend(stylesheet.imports.iter().cloned().map(Diagnostic::Import));
+
+    let canvas = paint_document(&root_node, &stylesheet, Canvas::new(width, height));
+    (canvas, diagnostics)
+}
+
+/// Parse each top-level statement of a stylesheet on its own, skipping (and
+/// reporting) the ones that fail
+fn parse_css_leniently(css: &str, diagnostics: &mut Vec<Diagnostic>) -> css::Stylesheet {
+    let mut stylesheet = css::Stylesheet {
+        rules: Vec::new(),
+        imports: Vec::new(),
+    };
+    for statement in split_statements(css) {
+        match css::Parser::parse_with_warnings(statement.to_string()) {
+            Ok((parsed, warnings)) => {
+                stylesheet.rules.extend(parsed.rules);
+                stylesheet.imports.extend(parsed.imports);
+                diagnostics.extend(warnings.into_iter().map(Diagnostic::Css));
+            }
+            Err(error) => diagnostics.push(Diagnostic::Css(format!(
+                "{} in `{}`",
+                error.message,
+                statement.trim()
+            ))),
+        }
+    }
+    stylesheet
+}
+
+/// Split a stylesheet into its top-level statements: blocks ending at the
+/// brace that closes them, and statements like `@import` ending at a
+/// semicolon. Braces and semicolons inside strings don't count.
+fn split_statements(css: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let (mut start, mut depth, mut quote) = (0, 0, None);
+    for (i, c) in css.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '{') => depth += 1,
+            (None, '}') if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    statements.push(&css[start..=i]);
+                    start = i + 1;
+                }
+            }
+            (None, ';') if depth == 0 => {
+                statements.push(&css[start..=i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    // Whatever's left is unterminated, which the parser will report
+    if !css[start..].trim().is_empty() {
+        statements.push(&css[start..]);
+    }
+    statements
+}
+
+/// The first pixel, in row order, whose channels differ by more than
+/// `tolerance`, as `(x, y)`
+pub fn first_difference(
+    actual: &Canvas,
+    expected: &Canvas,
+    tolerance: u8,
+) -> Option<(usize, usize)> {
+    actual
+        .pixels
+        .iter()
+        .zip(&expected.pixels)
+        .position(|(a, e)| {
+            a.r.abs_diff(e.r) > tolerance
+                || a.g.abs_diff(e.g) > tolerance
+                || a.b.abs_diff(e.b) > tolerance
+        })
+        .map(|index| (index % actual.width, index / actual.width))
+}
+
+/// Render the fixture files at `html_path` and `css_path` at the golden
+/// image's size and compare them, describing the first mismatch
+pub fn check(
+    html_path: &str,
+    css_path: &str,
+    golden_path: &str,
+    tolerance: u8,
+) -> Result<(), String> {
+    let read = |path: &str| fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e));
+    let expected =
+        ppm::decode(&read(golden_path)?).map_err(|e| format!("{}: {}", golden_path, e))?;
+    let actual = render(
+        &read(html_path)?,
+        &read(css_path)?,
+        expected.width,
+        expected.height,
+    );
+
+    match first_difference(&actual, &expected, tolerance) {
+        None => Ok(()),
+        Some((x, y)) => {
+            let index = y * actual.width + x;
+            Err(format!(
+                "pixel ({}, {}) is {}, expected {}",
+                x, y, actual.pixels[index], expected.pixels[index]
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_with_timeout_matches_render_when_in_time() {
+        let (html, css) = (
+            "<div></div>",
+            "div { display: block; height: 10px; background: #ff0000; }",
+        );
+        let canvas = render_with_timeout(html, css, 20, 20, Duration::from_secs(60)).unwrap();
+        assert_eq!(canvas.pixels, render(html, css, 20, 20).pixels);
+    }
+
+    #[test]
+    fn render_with_timeout_fails_once_the_deadline_passes() {
+        let html = "<div>".repeat(200);
+        assert!(render_with_timeout(&html, "", 20, 20, Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn try_render_reports_malformed_input_and_still_paints() {
+        let html = "<div><p>text</span></div foo>";
+        let css = "div { color: red; width: 10qq; } p { display: block; background: #0000ff; }";
+        let (canvas, diagnostics) = try_render(html, css, 40, 40);
+        assert_eq!((canvas.width, canvas.height), (40, 40));
+        assert!(diagnostics.iter().any(|d| matches!(d, Diagnostic::Html(_))));
+        assert!(diagnostics.iter().any(|d| matches!(d, Diagnostic::Css(_))));
+        // The rule after the bad one still applies
+        assert_eq!((canvas.pixels[0].r, canvas.pixels[0].b), (0, 255));
+    }
+
+    #[test]
+    fn box_fixture_matches_its_golden_image() {
+        let path = |name: &str| format!("{}/examples/golden/{}", env!("CARGO_MANIFEST_DIR"), name);
+        check(&path("box.html"), &path("box.css"), &path("box.ppm"), 2).unwrap();
+    }
+
+    #[test]
+    fn render_html_with_font_matches_render_with_the_embedded_font() {
+        let (html, css) = ("<p>Hello</p>", "p { display: block; color: #000000; }");
+        let font = include_bytes!("../fonts/DejaVuSansMono.ttf");
+        let canvas = render_html_with_font(html, css, 60, 20, font).unwrap();
+        let expected = render(html, css, 60, 20);
+        assert!(canvas.pixels.iter().any(|pixel| pixel.r < 128));
+        assert_eq!(canvas.pixels, expected.pixels);
+    }
+
+    #[test]
+    fn render_element_paints_only_the_element_at_its_own_size() {
+        let html = "<div><p></p><div class=\"card\"><i></i></div></div>";
+        let css = "div, p { display: block; } p { height: 30px; background: #00ff00; } \
+                   .card { margin-left: 10px; width: 40px; height: 10vh; background: #ff0000; } \
+                   i { display: block; position: fixed; left: 15px; top: 35px; \
+                   width: 5px; height: 5px; background: #0000ff; }";
+        let canvas = render_element(html, css, ".card", 100, 200).unwrap();
+        assert_eq!((canvas.width, canvas.height), (40, 20));
+        let color = |x: usize, y: usize| {
+            let pixel = canvas.pixels[y * canvas.width + x];
+            (pixel.r, pixel.g, pixel.b)
+        };
+        assert_eq!(color(0, 0), (255, 0, 0));
+        assert_eq!(color(39, 19), (255, 0, 0));
+        // The fixed box keeps its place in the viewport
+        assert_eq!(color(5, 5), (0, 0, 255));
+        assert_eq!(color(9, 9), (0, 0, 255));
+        assert_eq!(color(10, 10), (255, 0, 0));
+    }
+}
@@ -1,15 +1,34 @@
 use crate::css::Color;
 use crate::painting::Canvas;
-use minifb::{Key, Window, WindowOptions};
+use minifb::{CursorStyle, InputCallback, Key, KeyRepeat, MouseMode, Window, WindowOptions};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+const TITLE: &str = "Browser Engine - Press ESC to exit";
 
 pub struct BrowserWindow {
     window: Window,
+    /// Characters typed since the last `poll_find`
+    typed: Rc<RefCell<Vec<char>>>,
+    /// The find-in-page query, while the find bar is open
+    find_query: Option<String>,
+}
+
+/// Collects typed characters so find-in-page can read them
+struct TypedChars(Rc<RefCell<Vec<char>>>);
+
+impl InputCallback for TypedChars {
+    fn add_char(&mut self, uni_char: u32) {
+        if let Some(c) = char::from_u32(uni_char).filter(|c| !c.is_control()) {
+            self.0.borrow_mut().push(c);
+        }
+    }
 }
 
 impl BrowserWindow {
     pub fn new(width: usize, height: usize) -> Result<Self, minifb::Error> {
-        let window = Window::new(
-            "Browser Engine - Press ESC to exit",
+        let mut window = Window::new(
+            TITLE,
             width,
             height,
             WindowOptions {
@@ -19,17 +38,20 @@ impl BrowserWindow {
             },
         )?;
 
-        Ok(BrowserWindow { window })
+        let typed = Rc::new(RefCell::new(Vec::new()));
+        window.set_input_callback(Box::new(TypedChars(typed.clone())));
+
+        Ok(BrowserWindow {
+            window,
+            typed,
+            find_query: None,
+        })
     }
 
     /// Display the canvas in the window
     pub fn display(&mut self, canvas: &Canvas) -> Result<(), minifb::Error> {
         // Convert our RGBA colors to u32 format that minifb expects (0xRRGGBB)
-        let buffer: Vec<u32> = canvas
-            .pixels
-            .iter()
-            .map(|color| Self::color_to_u32(color))
-            .collect();
+        let buffer: Vec<u32> = canvas.pixels.iter().map(Self::color_to_u32).collect();
 
         self.window
             .update_with_buffer(&buffer, canvas.width, canvas.height)?;
@@ -42,9 +64,71 @@ impl BrowserWindow {
         self.window.is_open() && !self.window.is_key_down(Key::Escape)
     }
 
+    /// Mouse position in canvas coordinates, if the cursor is over the window
+    pub fn mouse_pos(&self) -> Option<(f32, f32)> {
+        self.window.get_mouse_pos(MouseMode::Discard)
+    }
+
+    /// Show the link under the pointer: a hand cursor, and its URL in the
+    /// title bar
+    pub fn show_link(&mut self, href: Option<&str>) {
+        match href {
+            Some(href) => {
+                self.window.set_cursor_style(CursorStyle::ClosedHand);
+                self.window.set_title(&format!("{} - Browser Engine", href));
+            }
+            None => {
+                self.window.set_cursor_style(CursorStyle::Arrow);
+                self.window.set_title(TITLE);
+            }
+        }
+    }
+
+    /// Scroll wheel movement since the last update, if any
+    pub fn scroll_wheel(&self) -> Option<(f32, f32)> {
+        self.window.get_scroll_wheel()
+    }
+
+    /// The current find-in-page query, if find is open
+    pub fn find_query(&self) -> Option<&str> {
+        self.find_query.as_deref()
+    }
+
+    /// Handle find-in-page keys: Ctrl+F opens or closes find, typing edits
+    /// the query, and Backspace deletes from it. Returns whether the query
+    /// changed.
+    pub fn poll_find(&mut self) -> bool {
+        let ctrl =
+            self.window.is_key_down(Key::LeftCtrl) || self.window.is_key_down(Key::RightCtrl);
+        let typed: Vec<char> = self.typed.borrow_mut().drain(..).collect();
+
+        if ctrl && self.window.is_key_pressed(Key::F, KeyRepeat::No) {
+            self.find_query = match self.find_query {
+                Some(_) => None,
+                None => Some(String::new()),
+            };
+            return true;
+        }
+
+        let query = match self.find_query.as_mut() {
+            Some(query) => query,
+            None => return false,
+        };
+        let mut changed = false;
+        if !ctrl {
+            for c in typed {
+                query.push(c);
+                changed = true;
+            }
+        }
+        if self.window.is_key_pressed(Key::Backspace, KeyRepeat::Yes) {
+            changed |= query.pop().is_some();
+        }
+        changed
+    }
+
     /// Convert our Color struct to minifb's u32 format
     fn color_to_u32(color: &Color) -> u32 {
         ((color.r as u32) << 16) | ((color.g as u32) << 8) | (color.b as u32)
     }
 }
-
@@ -1,20 +1,171 @@
-use crate::dom::{AttrMap, Node};
+use crate::dom::{AttrMap, Node, NodeType};
 use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
 
-/// A simple HTML parser
+/// Bounds on the documents the parser accepts, so a hostile page can't
+/// overflow the stack or exhaust memory
+#[derive(Clone, Copy, Debug)]
+pub struct ParseLimits {
+    /// Deepest element nesting allowed
+    pub max_depth: usize,
+    /// Most nodes allowed in the whole document
+    pub max_nodes: usize,
+    /// When to give up on parsing
+    pub deadline: Option<Instant>,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        ParseLimits {
+            max_depth: 512,
+            max_nodes: 1_000_000,
+            deadline: None,
+        }
+    }
+}
+
+/// Why a document was rejected
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    /// Elements were nested deeper than the limit
+    TooDeep(usize),
+    /// The document had more nodes than the limit
+    TooManyNodes(usize),
+    /// Parsing was still going when the deadline passed
+    Timeout,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::TooDeep(limit) => write!(f, "elements nested deeper than {}", limit),
+            ParseError::TooManyNodes(limit) => write!(f, "more than {} nodes", limit),
+            ParseError::Timeout => write!(f, "parsing took too long"),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A simple HTML parser. It reads markup a token at a time, keeping the
+/// elements still open on a stack, so input can arrive in pieces.
 pub struct Parser {
     pos: usize,
     input: String,
+    /// Record each node's source byte range in `Node::span`
+    record_spans: bool,
+    /// Mark text nodes as `Node::whitespace_significant`
+    preserve_whitespace: bool,
+    /// Source offset of `input`, once consumed input has been discarded
+    base: usize,
+    /// Set when parsing ran into the end of the input before the current
+    /// token was finished, so a streaming parser knows to wait for more
+    incomplete: bool,
+    limits: ParseLimits,
+    /// Elements currently open, innermost last, each with the source
+    /// offset of its opening tag
+    open: Vec<(Node, usize)>,
+    /// Set right after a `<pre>` tag, whose first newline is dropped
+    after_pre: bool,
+    /// Nodes parsed so far
+    node_count: usize,
+    /// Set once a limit is exceeded; parsing stops where it is
+    error: Option<ParseError>,
+    /// Malformed markup that parsing recovered from
+    warnings: Vec<String>,
+}
+
+/// A piece of markup, as `Parser::next_token` reads it
+enum Token {
+    /// An opening tag: its name as written, its attributes, and whether it
+    /// closed itself with `/>`
+    StartTag(String, AttrMap, bool),
+    /// A closing tag's lowercased name and anything else written inside it
+    EndTag(String, String),
+    Text(Node),
+    /// A comment or doctype, which adds nothing to the tree
+    Skipped,
 }
 
 impl Parser {
-    /// Parse an HTML document
+    /// Parse an HTML document. A document beyond the default limits is cut
+    /// off where it exceeded them.
     pub fn parse(source: String) -> Node {
-        let mut parser = Parser {
+        let (document, warnings) = Parser::parse_with_warnings(source);
+        for warning in warnings {
+            eprintln!("Warning: {}", warning);
+        }
+        document
+    }
+
+    /// Parse an HTML document, returning what was wrong with it (mismatched
+    /// tags, or being cut off at the default limits) instead of printing it
+    pub fn parse_with_warnings(source: String) -> (Node, Vec<String>) {
+        let (document, error, mut warnings) =
+            Parser::parse_document(source, false, false, ParseLimits::default());
+        if let Some(error) = error {
+            warnings.push(format!("Document truncated: {}", error));
+        }
+        (document, warnings)
+    }
+
+    /// Parse an HTML document, failing if it exceeds `limits`
+    pub fn parse_with_limits(source: String, limits: ParseLimits) -> Result<Node, ParseError> {
+        match Parser::parse_document(source, false, false, limits) {
+            (document, None, _) => Ok(document),
+            (_, Some(error), _) => Err(error),
+        }
+    }
+
+    /// Parse an HTML document, recording where each node came from in the
+    /// source so rendered elements can be mapped back to it
+    pub fn parse_with_spans(source: String) -> Node {
+        Parser::parse_document(source, true, false, ParseLimits::default()).0
+    }
+
+    /// Parse an HTML document whose text keeps its whitespace when rendered,
+    /// for content (like email) laid out with spaces and newlines
+    pub fn parse_preserving_whitespace(source: String) -> Node {
+        Parser::parse_document(source, false, true, ParseLimits::default()).0
+    }
+
+    fn parse_document(
+        source: String,
+        record_spans: bool,
+        preserve_whitespace: bool,
+        limits: ParseLimits,
+    ) -> (Node, Option<ParseError>, Vec<String>) {
+        let mut parser = Parser::new(record_spans, preserve_whitespace, limits);
+        parser.input = source;
+        let nodes = parser.finish();
+        (Parser::document(nodes), parser.error, parser.warnings)
+    }
+
+    fn new(record_spans: bool, preserve_whitespace: bool, limits: ParseLimits) -> Parser {
+        Parser {
             pos: 0,
-            input: source,
-        };
-        let nodes = parser.parse_nodes();
+            input: String::new(),
+            record_spans,
+            preserve_whitespace,
+            base: 0,
+            incomplete: false,
+            limits,
+            open: Vec::new(),
+            after_pre: false,
+            node_count: 0,
+            error: None,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Assemble parsed top-level nodes into a document tree
+    pub fn document(nodes: Vec<Node>) -> Node {
+        // Whitespace between top-level nodes isn't part of the document
+        let nodes: Vec<Node> = nodes
+            .into_iter()
+            .filter(|node| !node.is_whitespace_text())
+            .collect();
 
         // If there's a single root element, return it. Otherwise wrap in a div.
         if nodes.len() == 1 {
@@ -24,86 +175,232 @@ impl Parser {
         }
     }
 
-    /// Parse a sequence of sibling nodes
-    fn parse_nodes(&mut self) -> Vec<Node> {
+    /// Create a parser for a document that arrives in pieces. Pass each piece
+    /// to `feed`, then call `finish`; `Parser::document` builds the same tree
+    /// from the collected nodes as `parse` does from the whole source.
+    pub fn streaming() -> Parser {
+        Parser::new(false, false, ParseLimits::default())
+    }
+
+    /// Append a chunk of input and return the top-level nodes it completed.
+    /// A token cut off by the end of the chunk (even mid-tag) is read again
+    /// once more input arrives; everything before it is parsed only once.
+    pub fn feed(&mut self, chunk: &str) -> Vec<Node> {
+        self.input.push_str(chunk);
+        let nodes = self.parse_tokens(false);
+
+        // Discard what's been consumed so the buffer only holds pending input
+        self.input.drain(..self.pos);
+        self.base += self.pos;
+        self.pos = 0;
+        nodes
+    }
+
+    /// Signal the end of input and return the remaining top-level nodes,
+    /// closing any elements left open
+    pub fn finish(&mut self) -> Vec<Node> {
+        let mut nodes = self.parse_tokens(true);
+        // Truncated documents close their open elements innermost first
+        while !self.open.is_empty() {
+            self.close_element(&mut nodes);
+        }
+        self.base += self.pos;
+        self.input.clear();
+        self.pos = 0;
+        nodes
+    }
+
+    /// The document as parsed so far from the top-level nodes `feed` has
+    /// returned, with the elements still open closed where the input ends,
+    /// so a page can be shown before all of it has arrived
+    pub fn partial_document(&self, nodes: &[Node]) -> Node {
+        let mut nodes = nodes.to_vec();
+        let mut open: Vec<Node> = self.open.iter().map(|(node, _)| node.clone()).collect();
+        while let Some(node) = open.pop() {
+            match open.last_mut() {
+                Some(parent) => parent.children.push(node),
+                None => nodes.push(node),
+            }
+        }
+        Parser::document(nodes)
+    }
+
+    /// Read and build tokens until the input runs out, returning the
+    /// top-level nodes completed. Unless `at_end`, a token cut off by the
+    /// end of the input is left for the next call.
+    fn parse_tokens(&mut self, at_end: bool) -> Vec<Node> {
         let mut nodes = Vec::new();
-        loop {
-            self.consume_whitespace();
-            if self.eof() || self.starts_with("</") {
+        while !self.eof() && self.error.is_none() {
+            // A newline right after <pre> is dropped, so content can start on
+            // the line after the tag
+            if std::mem::take(&mut self.after_pre) && self.starts_with("\n") {
+                self.consume_char();
+                continue;
+            }
+            let start = self.pos;
+            self.incomplete = false;
+            let token = self.next_token();
+            if self.incomplete && !at_end {
+                self.pos = start;
                 break;
             }
-            nodes.push(self.parse_node());
+            self.build(token, start, &mut nodes);
         }
         nodes
     }
 
-    /// Parse a single node
-    fn parse_node(&mut self) -> Node {
-        if self.next_char() == '<' {
-            if self.starts_with("<!--") {
-                self.parse_comment();
-                // After skipping comment, parse the next node
-                if !self.eof() && !self.starts_with("</") {
-                    self.parse_node()
-                } else {
-                    Node::text(String::new())
+    /// Read the next token
+    fn next_token(&mut self) -> Token {
+        if self.starts_with("<!--") {
+            self.parse_comment();
+            Token::Skipped
+        } else if self.starts_with("<![CDATA[") {
+            Token::Text(self.parse_cdata())
+        } else if self.starts_with("<!") {
+            self.parse_doctype();
+            Token::Skipped
+        } else if self.starts_with("</") {
+            self.parse_end_tag()
+        } else if self.next_char() == '<' {
+            self.parse_start_tag()
+        } else {
+            Token::Text(self.parse_text())
+        }
+    }
+
+    /// Add a token that started at `start` to the tree
+    fn build(&mut self, token: Token, start: usize, nodes: &mut Vec<Node>) {
+        match token {
+            Token::Skipped => {}
+            Token::Text(text) => {
+                if self.count_node() {
+                    self.append(text, nodes);
                 }
-            } else if self.starts_with("<!") {
-                self.parse_doctype();
-                // After skipping doctype, parse the next node
-                if !self.eof() && !self.starts_with("</") {
-                    self.parse_node()
+            }
+            // A lone `<` at the end of the input starts nothing
+            Token::StartTag(name, _, _) if name.is_empty() && self.incomplete => {}
+            Token::StartTag(name, attrs, self_closing) => {
+                if !self.count_node() {
+                    return;
+                }
+                let tag_name = name.to_lowercase();
+                let node = element(&name, attrs, Vec::new());
+                if self_closing || is_void_element(&tag_name) {
+                    let node = self.with_span(node, start);
+                    self.append(node, nodes);
+                } else if self.open.len() >= self.limits.max_depth {
+                    self.error = Some(ParseError::TooDeep(self.limits.max_depth));
+                    let node = self.with_span(node, start);
+                    self.append(node, nodes);
                 } else {
-                    Node::text(String::new())
+                    self.after_pre = tag_name == "pre";
+                    self.open.push((node, self.base + start));
                 }
-            } else {
-                self.parse_element()
             }
-        } else {
-            self.parse_text()
+            Token::EndTag(name, junk) => {
+                let open_name = match self.open.last() {
+                    Some((node, _)) => match node.node_type {
+                        NodeType::Element(ref elem) => elem.tag_name.clone(),
+                        NodeType::Text(_) => unreachable!("only elements are left open"),
+                    },
+                    None => {
+                        self.warnings.push(format!("Ignored stray </{name}>"));
+                        return;
+                    }
+                };
+                // Be lenient if closing tag doesn't match (malformed HTML)
+                if name != open_name {
+                    self.warnings.push(format!(
+                        "Mismatched tags: expected </{open_name}>, found </{name}>"
+                    ));
+                }
+                if !junk.trim().is_empty() {
+                    self.warnings
+                        .push(format!("Ignored `{}` in </{name}>", junk.trim()));
+                }
+                self.close_element(nodes);
+            }
+        }
+    }
+
+    /// Count a new node against the limits, returning false (and stopping
+    /// parsing) once they're exceeded
+    fn count_node(&mut self) -> bool {
+        self.node_count += 1;
+        if self.node_count > self.limits.max_nodes {
+            self.error = Some(ParseError::TooManyNodes(self.limits.max_nodes));
+            return false;
+        }
+        // Checking the clock is slow next to parsing a node, so only do it
+        // every so often
+        if self.node_count.is_multiple_of(256)
+            && self
+                .limits
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+        {
+            self.error = Some(ParseError::Timeout);
+            return false;
+        }
+        true
+    }
+
+    /// Add a finished node to the innermost open element, or to the
+    /// top-level `nodes` if there is none
+    fn append(&mut self, node: Node, nodes: &mut Vec<Node>) {
+        match self.open.last_mut() {
+            Some((parent, _)) => parent.children.push(node),
+            None => nodes.push(node),
+        }
+    }
+
+    /// Close the innermost open element, ending its span here
+    fn close_element(&mut self, nodes: &mut Vec<Node>) {
+        let (mut node, start) = self.open.pop().unwrap();
+        if self.record_spans {
+            node.span = Some((start, self.base + self.pos));
         }
+        self.append(node, nodes);
     }
 
-    /// Parse an element tag
-    fn parse_element(&mut self) -> Node {
-        // Opening tag
+    /// Parse an opening tag
+    fn parse_start_tag(&mut self) -> Token {
         assert_eq!(self.consume_char(), '<');
-        let tag_name = self.parse_tag_name();
+        // Tag names are case-insensitive; the original spelling is kept too
+        let name = self.parse_tag_name();
         let attrs = self.parse_attributes();
-        
-        // Check for self-closing tag
-        let self_closing = if self.starts_with("/>") {
-            self.consume_char(); // consume '/'
-            true
-        } else {
-            false
-        };
-        
-        assert_eq!(self.consume_char(), '>');
 
-        // For self-closing tags, return immediately with no children
+        // Check for self-closing tag
+        let self_closing = self.starts_with("/>");
         if self_closing {
-            return Node::element(tag_name, attrs, Vec::new());
+            self.consume_char(); // consume '/'
         }
 
-        // Contents
-        let children = self.parse_nodes();
-
-        // Closing tag (skip if EOF or we're at another opening tag for void elements)
-        if !self.eof() && self.starts_with("</") {
-            assert_eq!(self.consume_char(), '<');
-            assert_eq!(self.consume_char(), '/');
-            let close_tag = self.parse_tag_name();
-            // Be lenient if closing tag doesn't match (malformed HTML)
-            if close_tag != tag_name {
-                eprintln!("Warning: Mismatched tags: expected </{tag_name}>, found </{close_tag}>");
-            }
+        // Input that ends inside the opening tag still leaves the element
+        // open once there's no more to come
+        if self.eof() {
+            self.incomplete = true;
+        } else {
             assert_eq!(self.consume_char(), '>');
         }
+        Token::StartTag(name, attrs, self_closing)
+    }
 
-        Node::element(tag_name, attrs, children)
+    /// Parse a closing tag
+    fn parse_end_tag(&mut self) -> Token {
+        assert!(self.starts_with("</"));
+        self.pos += 2;
+        let name = self.parse_tag_name().to_lowercase();
+        // Anything else inside a closing tag, like `</div foo>`, is ignored
+        let junk = self.consume_while(|c| c != '>');
+        if self.eof() {
+            self.incomplete = true;
+        } else {
+            self.consume_char();
+        }
+        Token::EndTag(name, junk)
     }
-    
+
     /// Skip over a comment
     fn parse_comment(&mut self) {
         assert!(self.starts_with("<!--"));
@@ -116,26 +413,50 @@ impl Parser {
         // Skip -->
         if self.starts_with("-->") {
             self.pos += 3;
+        } else {
+            self.incomplete = true;
+        }
+    }
+
+    /// Parse a CDATA section, whose contents are text even if they look
+    /// like markup
+    fn parse_cdata(&mut self) -> Node {
+        let start = self.pos;
+        // Skip <![CDATA[
+        self.pos += 9;
+        let mut text = String::new();
+        while !self.eof() && !self.starts_with("]]>") {
+            text.push(self.consume_char());
+        }
+        // Skip ]]>
+        if self.starts_with("]]>") {
+            self.pos += 3;
+        } else {
+            self.incomplete = true;
         }
+        self.with_span(self.text(text), start)
     }
-    
+
     /// Skip over a DOCTYPE declaration
     fn parse_doctype(&mut self) {
         assert!(self.starts_with("<!"));
         self.consume_char(); // <
         self.consume_char(); // !
-        // Consume until we find >
+                             // Consume until we find >
         while !self.eof() && self.next_char() != '>' {
             self.consume_char();
         }
         if !self.eof() {
             self.consume_char(); // >
+        } else {
+            self.incomplete = true;
         }
     }
 
     /// Parse a tag or attribute name
     fn parse_tag_name(&mut self) -> String {
-        self.consume_while(|c| c.is_alphanumeric())
+        // Custom element names like `my-widget` have hyphens
+        self.consume_while(|c| c.is_alphanumeric() || c == '-')
     }
 
     /// Parse attributes inside an element tag
@@ -143,6 +464,10 @@ impl Parser {
         let mut attributes = HashMap::new();
         loop {
             self.consume_whitespace();
+            if self.eof() {
+                self.incomplete = true;
+                break;
+            }
             if self.next_char() == '>' || self.starts_with("/>") {
                 break;
             }
@@ -156,24 +481,32 @@ impl Parser {
     /// Parse a single attribute
     fn parse_attribute(&mut self) -> Option<(String, String)> {
         let name = self.parse_tag_name();
-        
+        if name.is_empty() {
+            // Skip a stray character that can't start an attribute
+            self.consume_char();
+            return None;
+        }
+
         // Handle boolean attributes (no value)
         self.consume_whitespace();
-        if self.next_char() != '=' {
+        if self.eof() || self.next_char() != '=' {
             return Some((name, String::new()));
         }
-        
+
         self.consume_char(); // consume '='
         self.consume_whitespace();
-        
+        if self.eof() {
+            return Some((name, String::new()));
+        }
+
         let value = if self.next_char() == '"' || self.next_char() == '\'' {
             self.parse_attr_value()
         } else {
             // Unquoted attribute value
             self.consume_while(|c| !c.is_whitespace() && c != '>' && c != '/')
         };
-        
-        Some((name, value))
+
+        Some((name, decode_entities(&value)))
     }
 
     /// Parse an attribute value
@@ -181,13 +514,38 @@ impl Parser {
         let open_quote = self.consume_char();
         assert!(open_quote == '"' || open_quote == '\'');
         let value = self.consume_while(|c| c != open_quote);
-        assert_eq!(self.consume_char(), open_quote);
+        if self.eof() {
+            self.incomplete = true;
+        } else {
+            assert_eq!(self.consume_char(), open_quote);
+        }
         value
     }
 
     /// Parse a text node
     fn parse_text(&mut self) -> Node {
-        Node::text(self.consume_while(|c| c != '<'))
+        let start = self.pos;
+        let text = self.consume_while(|c| c != '<');
+        let text = self.text(decode_entities(&text));
+        // The text may continue in input that hasn't arrived yet
+        if self.eof() {
+            self.incomplete = true;
+        }
+        self.with_span(text, start)
+    }
+
+    fn text(&self, text: String) -> Node {
+        let mut node = Node::text(text);
+        node.whitespace_significant = self.preserve_whitespace;
+        node
+    }
+
+    /// Record that a node spans from `start` to the current position
+    fn with_span(&self, mut node: Node, start: usize) -> Node {
+        if self.record_spans {
+            node.span = Some((self.base + start, self.base + self.pos));
+        }
+        node
     }
 
     /// Consume and discard whitespace characters
@@ -226,8 +584,246 @@ impl Parser {
     fn consume_char(&mut self) -> char {
         let mut iter = self.input[self.pos..].char_indices();
         let (_, cur_char) = iter.next().unwrap();
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
+        let (next_pos, _) = iter.next().unwrap_or((cur_char.len_utf8(), ' '));
         self.pos += next_pos;
         cur_char
     }
 }
+
+/// Create an element with a lowercased tag name, remembering how it was
+/// originally spelled if that differs
+fn element(original_name: &str, attrs: AttrMap, children: Vec<Node>) -> Node {
+    let tag_name = original_name.to_lowercase();
+    let original_tag_name = (tag_name != original_name).then(|| original_name.to_string());
+    let mut node = Node::element(tag_name, attrs, children);
+    if let NodeType::Element(ref mut elem) = node.node_type {
+        elem.original_tag_name = original_tag_name;
+    }
+    node
+}
+
+/// Named character references this parser knows, by name
+const NAMED_ENTITIES: &[(&str, char)] = &[
+    ("amp", '&'),
+    ("lt", '<'),
+    ("gt", '>'),
+    ("quot", '"'),
+    ("apos", '\''),
+    ("nbsp", '\u{a0}'),
+    ("shy", '\u{ad}'),
+    ("ndash", '\u{2013}'),
+    ("mdash", '\u{2014}'),
+    ("lsquo", '\u{2018}'),
+    ("rsquo", '\u{2019}'),
+    ("sbquo", '\u{201a}'),
+    ("ldquo", '\u{201c}'),
+    ("rdquo", '\u{201d}'),
+    ("bdquo", '\u{201e}'),
+    ("laquo", '\u{ab}'),
+    ("raquo", '\u{bb}'),
+    ("hellip", '\u{2026}'),
+    ("bull", '\u{2022}'),
+    ("middot", '\u{b7}'),
+    ("prime", '\u{2032}'),
+    ("copy", '\u{a9}'),
+    ("reg", '\u{ae}'),
+    ("trade", '\u{2122}'),
+    ("deg", '\u{b0}'),
+    ("times", '\u{d7}'),
+    ("divide", '\u{f7}'),
+    ("plusmn", '\u{b1}'),
+    ("sect", '\u{a7}'),
+    ("para", '\u{b6}'),
+    ("cent", '\u{a2}'),
+    ("pound", '\u{a3}'),
+    ("yen", '\u{a5}'),
+    ("euro", '\u{20ac}'),
+];
+
+/// Replace character references like `&amp;`, `&#169;`, and `&#xA9;` with
+/// the characters they stand for. Unknown or malformed references are left
+/// as written.
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest[1..].find(';').map(|end| &rest[1..end + 1]);
+        match reference.and_then(decode_reference) {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[reference.unwrap().len() + 2..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// The character a reference (without its `&` and `;`) stands for
+fn decode_reference(reference: &str) -> Option<char> {
+    let code_point = match reference.strip_prefix('#') {
+        Some(hex) if hex.starts_with(['x', 'X']) => u32::from_str_radix(&hex[1..], 16).ok()?,
+        Some(decimal) => decimal.parse().ok()?,
+        None => {
+            return NAMED_ENTITIES
+                .iter()
+                .find(|&&(name, _)| name == reference)
+                .map(|&(_, c)| c)
+        }
+    };
+    char::from_u32(code_point)
+}
+
+/// Elements that never have content or a closing tag
+pub fn is_void_element(tag_name: &str) -> bool {
+    matches!(
+        tag_name,
+        "area"
+            | "base"
+            | "br"
+            | "col"
+            | "embed"
+            | "hr"
+            | "img"
+            | "input"
+            | "link"
+            | "meta"
+            | "param"
+            | "source"
+            | "track"
+            | "wbr"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_with_limits_rejects_deep_nesting() {
+        let limits = ParseLimits {
+            max_depth: 2,
+            ..ParseLimits::default()
+        };
+        let source = "<div><div><div></div></div></div>".to_string();
+        assert_eq!(
+            Parser::parse_with_limits(source, limits).unwrap_err(),
+            ParseError::TooDeep(2)
+        );
+    }
+
+    #[test]
+    fn parse_with_spans_records_source_ranges() {
+        let source = "<div><p>hi</p></div>";
+        let document = Parser::parse_with_spans(source.to_string());
+        let (start, end) = document.children[0].span.unwrap();
+        assert_eq!(&source[start..end], "<p>hi</p>");
+    }
+
+    #[test]
+    fn parse_preserving_whitespace_marks_text() {
+        let document = Parser::parse_preserving_whitespace("<pre>a  b</pre>".to_string());
+        assert!(document.children[0].whitespace_significant);
+    }
+
+    /// Parse `chunks` one at a time with a streaming parser
+    fn parse_in_chunks(chunks: &[&str]) -> Node {
+        let mut parser = Parser::streaming();
+        let mut nodes = Vec::new();
+        for chunk in chunks {
+            nodes.extend(parser.feed(chunk));
+        }
+        nodes.extend(parser.finish());
+        Parser::document(nodes)
+    }
+
+    #[test]
+    fn chunks_split_mid_tag_parse_like_the_whole_document() {
+        let source = "<div class=\"a\"><p>one</p><p>two</p></div>";
+        let split = source.find("lass").unwrap();
+        let streamed = parse_in_chunks(&[&source[..split], &source[split..]]);
+        let whole = Parser::parse(source.to_string());
+        assert_eq!(format!("{:?}", streamed), format!("{:?}", whole));
+    }
+
+    #[test]
+    fn partial_document_shows_what_has_arrived() {
+        let mut parser = Parser::streaming();
+        assert!(parser.feed("<div><p>one</p><p>tw").is_empty());
+        let partial = parser.partial_document(&[]);
+        assert_eq!(partial.to_html(), "<div><p>one</p><p></p></div>");
+    }
+
+    #[test]
+    fn stray_closing_tags_are_skipped_whole_or_streamed() {
+        let source = "<p>a</p></div><p>b</p>";
+        let (whole, warnings) = Parser::parse_with_warnings(source.to_string());
+        assert_eq!(whole.children.len(), 2);
+        assert_eq!(warnings, vec!["Ignored stray </div>"]);
+        let streamed = parse_in_chunks(&[&source[..10], &source[10..]]);
+        assert_eq!(format!("{:?}", streamed), format!("{:?}", whole));
+    }
+
+    #[test]
+    fn entities_split_across_chunks_are_decoded() {
+        let streamed = parse_in_chunks(&["<p>a&nb", "sp;b</p>"]);
+        assert_eq!(streamed.children[0].to_html(), "a\u{a0}b");
+    }
+
+    #[test]
+    fn cdata_contents_are_text() {
+        let document = Parser::parse("<p><![CDATA[<b>no</b>]]></p>".to_string());
+        assert_eq!(document.children.len(), 1);
+        match document.children[0].node_type {
+            NodeType::Text(ref text) => assert_eq!(text, "<b>no</b>"),
+            NodeType::Element(_) => panic!("CDATA should parse to a text node"),
+        }
+    }
+
+    #[test]
+    fn custom_elements_parse_and_match_by_their_full_name() {
+        use crate::style::{self, CascadeContext, ColorScheme, Display, ElementDefaults};
+        let document = Parser::parse("<div><my-widget>hi</my-widget></div>".to_string());
+        let NodeType::Element(ref widget) = document.children[0].node_type else {
+            panic!("my-widget should parse to an element");
+        };
+        assert_eq!(widget.tag_name, "my-widget");
+
+        let author = crate::css::Parser::parse("my-widget { width: 50px; }".to_string());
+        let ua_stylesheet = crate::ua::stylesheet();
+        let mut element_defaults = ElementDefaults::default();
+        element_defaults.register("my-widget", "block", Default::default());
+        let cascade = CascadeContext {
+            user_agent: &ua_stylesheet,
+            user: None,
+            author: &[&author],
+            element_defaults: &element_defaults,
+            hovered: None,
+            viewport_width: 800.0,
+            color_scheme: ColorScheme::Light,
+            visited: None,
+            base_url: None,
+        };
+        let styled = style::style_tree_with_cascade(&document, &cascade);
+        let widget = &styled.children[0];
+        assert!(widget.display() == Display::Block);
+        assert_eq!(widget.computed_values()["width"], "50px");
+    }
+
+    #[test]
+    fn elements_left_open_at_the_end_are_closed() {
+        let document = Parser::parse("<div><p>hi".to_string());
+        assert_eq!(document.to_html(), "<div><p>hi</p></div>");
+        assert_eq!(document.children.len(), 1);
+        assert_eq!(document.children[0].children.len(), 1);
+    }
+}
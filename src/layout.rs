@@ -1,8 +1,15 @@
-use crate::css::{Unit, Value};
-use crate::style::{Display, StyledNode};
+use crate::css::{self, Selector, Unit, Value};
+use crate::dom::{Node, NodeType};
+use crate::network::WebFetcher;
+use crate::style::{
+    self, resolve_length, AlignItems, Display, ElementState, FlexDirection, LengthContext,
+    Overflow, OverflowWrap, Position, StyledNode, VerticalAlign, WhiteSpace,
+};
+use std::fmt;
+use std::time::Instant;
 
 /// CSS box model dimensions
-#[derive(Clone, Copy, Default, Debug)]
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
 pub struct Rect {
     pub x: f32,
     pub y: f32,
@@ -19,6 +26,46 @@ impl Rect {
             height: self.height + edge.top + edge.bottom,
         }
     }
+
+    pub fn translated(&self, dx: f32, dy: f32) -> Rect {
+        Rect {
+            x: self.x + dx,
+            y: self.y + dy,
+            ..*self
+        }
+    }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    /// The smallest rect containing both rects
+    pub fn union(&self, other: &Rect) -> Rect {
+        let x0 = self.x.min(other.x);
+        let y0 = self.y.min(other.y);
+        let x1 = (self.x + self.width).max(other.x + other.width);
+        let y1 = (self.y + self.height).max(other.y + other.height);
+        Rect {
+            x: x0,
+            y: y0,
+            width: x1 - x0,
+            height: y1 - y0,
+        }
+    }
+
+    /// The overlapping area of two rects (zero-sized if they don't overlap)
+    pub fn intersection(&self, other: &Rect) -> Rect {
+        let x0 = self.x.max(other.x);
+        let y0 = self.y.max(other.y);
+        let x1 = (self.x + self.width).min(other.x + other.width);
+        let y1 = (self.y + self.height).min(other.y + other.height);
+        Rect {
+            x: x0,
+            y: y0,
+            width: (x1 - x0).max(0.0),
+            height: (y1 - y0).max(0.0),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Default, Debug)]
@@ -51,62 +98,420 @@ impl Dimensions {
     }
 }
 
+/// Scroll position and scrollable extent of an `overflow: scroll`/`auto` box
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ScrollState {
+    pub offset_x: f32,
+    pub offset_y: f32,
+    /// Size of the content measured from the padding box origin
+    pub content_width: f32,
+    pub content_height: f32,
+}
+
+/// Simple text metrics: every character has the same advance. These are for
+/// text at the default font size; other sizes scale them (see `text_scale`).
+pub const CHAR_WIDTH: f32 = style::CHAR_ADVANCE * style::DEFAULT_FONT_SIZE;
+const LINE_HEIGHT: f32 = 14.0;
+/// Distance from the top of a line of text down to its baseline; the rest
+/// of the line height is below it
+pub const BASELINE: f32 = 10.0;
+
+/// How much larger text at `font_size` is than at the default size
+pub fn text_scale(font_size: f32) -> f32 {
+    font_size / style::DEFAULT_FONT_SIZE
+}
+
+/// Extra advance between characters (`letter-spacing`) and per space
+/// (`word-spacing`), in px
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TextSpacing {
+    pub letter: f32,
+    pub word: f32,
+}
+
+/// Width of a run of text at `font_size` laid out on one line. Letter
+/// spacing goes between characters, not after the last one.
+pub fn measure_text(text: &str, spacing: TextSpacing, font_size: f32) -> f32 {
+    let chars = text.chars().count();
+    let spaces = text.chars().filter(|&c| c == ' ').count();
+    chars as f32 * CHAR_WIDTH * text_scale(font_size)
+        + chars.saturating_sub(1) as f32 * spacing.letter
+        + spaces as f32 * spacing.word
+}
+
+/// The part of a text box's content that sits on a single line
+#[derive(Clone, Debug)]
+pub struct TextFragment {
+    pub text: String,
+    pub rect: Rect,
+}
+
 /// A box in the layout tree
 #[derive(Debug)]
 pub struct LayoutBox<'a> {
     pub dimensions: Dimensions,
     pub box_type: BoxType<'a>,
     pub children: Vec<LayoutBox<'a>>,
+    /// Set for boxes that scroll their own content
+    pub scroll: Option<ScrollState>,
+    /// Laid-out lines of a text box's content
+    pub fragments: Vec<TextFragment>,
+    /// Horizontal and vertical corner radii of the border box
+    pub border_radius: (f32, f32),
+    /// Resolved `outline-width`, drawn outside the border box
+    pub outline_width: f32,
+    /// Resolved `transform: translate(x, y)`, applied when painting
+    pub translation: (f32, f32),
+    /// A list item's marker, placed just left of its border box
+    pub marker: Option<TextFragment>,
+    /// Spacing a text box's fragments were measured with
+    pub text_spacing: TextSpacing,
 }
 
 #[derive(Debug)]
 pub enum BoxType<'a> {
     BlockNode(&'a StyledNode<'a>),
     InlineNode(&'a StyledNode<'a>),
+    /// Laid out like a block inside, placed on a line as a single unit
+    InlineBlockNode(&'a StyledNode<'a>),
     AnonymousBlock,
 }
 
+/// A snapshot of a laid-out box's geometry, detached from the styles and
+/// DOM it came from, for comparing layouts against fixtures. Its `Display`
+/// output is a stable, line-per-box text form.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RectTree {
+    /// The element's tag name, `#text` for text, or `None` for anonymous
+    /// blocks
+    pub tag_name: Option<String>,
+    pub content: Rect,
+    pub padding: Rect,
+    pub border: Rect,
+    pub margin: Rect,
+    pub children: Vec<RectTree>,
+}
+
+impl RectTree {
+    fn write(&self, f: &mut fmt::Formatter, depth: usize) -> fmt::Result {
+        let rect = |r: Rect| format!("{} {} {}x{}", r.x, r.y, r.width, r.height);
+        writeln!(
+            f,
+            "{:indent$}{} content=({}) padding=({}) border=({}) margin=({})",
+            "",
+            self.tag_name.as_deref().unwrap_or("(anonymous)"),
+            rect(self.content),
+            rect(self.padding),
+            rect(self.border),
+            rect(self.margin),
+            indent = depth * 2,
+        )?;
+        for child in &self.children {
+            child.write(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for RectTree {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
 impl<'a> LayoutBox<'a> {
     fn new(box_type: BoxType<'a>) -> LayoutBox<'a> {
         LayoutBox {
             box_type,
             dimensions: Default::default(),
             children: Vec::new(),
+            scroll: None,
+            fragments: Vec::new(),
+            border_radius: (0.0, 0.0),
+            outline_width: 0.0,
+            translation: (0.0, 0.0),
+            marker: None,
+            text_spacing: TextSpacing::default(),
+        }
+    }
+
+    /// Clear everything layout computes, keeping the boxes (and their
+    /// allocations) and scroll positions, so the tree can be laid out again
+    fn reset_geometry(&mut self) {
+        self.dimensions = Dimensions::default();
+        self.fragments.clear();
+        self.border_radius = (0.0, 0.0);
+        self.outline_width = 0.0;
+        self.translation = (0.0, 0.0);
+        self.text_spacing = TextSpacing::default();
+        if let Some(marker) = &mut self.marker {
+            marker.rect = Rect::default();
+        }
+        for child in &mut self.children {
+            child.reset_geometry();
+        }
+    }
+
+    /// The box's `position`; anonymous boxes are never positioned
+    pub fn position(&self) -> Position {
+        match self.box_type {
+            BoxType::BlockNode(node)
+            | BoxType::InlineNode(node)
+            | BoxType::InlineBlockNode(node) => node.position(),
+            BoxType::AnonymousBlock => Position::Static,
         }
     }
 
     fn get_style_node(&self) -> &'a StyledNode<'a> {
         match self.box_type {
-            BoxType::BlockNode(node) | BoxType::InlineNode(node) => node,
+            BoxType::BlockNode(node)
+            | BoxType::InlineNode(node)
+            | BoxType::InlineBlockNode(node) => node,
             BoxType::AnonymousBlock => panic!("Anonymous block has no style node"),
         }
     }
 }
 
-/// Build the layout tree
-pub fn layout_tree<'a>(
+/// Build the layout tree for a viewport of the size of `containing_block`'s
+/// content. This is a pure function of its inputs: the same styles and
+/// viewport always give the same tree.
+pub fn layout_tree<'a>(node: &'a StyledNode<'a>, containing_block: Dimensions) -> LayoutBox<'a> {
+    layout_tree_until(node, containing_block, None)
+}
+
+/// Build the layout tree, along with its `document_height`
+pub fn layout_tree_with_height<'a>(
     node: &'a StyledNode<'a>,
-    mut containing_block: Dimensions,
+    containing_block: Dimensions,
+) -> (LayoutBox<'a>, f32) {
+    let root_box = layout_tree(node, containing_block);
+    let height = document_height(&root_box);
+    (root_box, height)
+}
+
+/// Build the layout tree, giving up on boxes not yet laid out once
+/// `deadline` passes. If it has passed by the time this returns, the tree
+/// is incomplete.
+pub fn layout_tree_with_deadline<'a>(
+    node: &'a StyledNode<'a>,
+    containing_block: Dimensions,
+    deadline: Instant,
 ) -> LayoutBox<'a> {
-    containing_block.content.height = 0.0;
+    layout_tree_until(node, containing_block, Some(deadline))
+}
 
+/// Lay out a tree built by `layout_tree` again for a new viewport, reusing
+/// its boxes rather than rebuilding them. Only for when the style tree is
+/// unchanged; scroll positions are kept, clamped to the new content size.
+pub fn relayout(root_box: &mut LayoutBox, containing_block: Dimensions) {
+    root_box.reset_geometry();
+    layout_root(root_box, containing_block, None);
+}
+
+fn layout_tree_until<'a>(
+    node: &'a StyledNode<'a>,
+    containing_block: Dimensions,
+    deadline: Option<Instant>,
+) -> LayoutBox<'a> {
     let mut root_box = build_layout_tree(node);
-    root_box.layout(containing_block);
+    layout_root(&mut root_box, containing_block, deadline);
     root_box
 }
 
+/// Lay out a freshly built (or reset) tree in a viewport the size of
+/// `containing_block`'s content
+fn layout_root(
+    root_box: &mut LayoutBox,
+    mut containing_block: Dimensions,
+    deadline: Option<Instant>,
+) {
+    let viewport = Viewport {
+        width: containing_block.content.width,
+        height: containing_block.content.height,
+        root_font_size: root_box.get_style_node().font_size(),
+        deadline,
+    };
+    containing_block.content.height = 0.0;
+
+    root_box.layout(containing_block, viewport);
+
+    // Absolutely positioned boxes with no positioned ancestor are placed
+    // relative to the viewport, as fixed boxes always are
+    let initial_containing_block = Rect {
+        height: viewport.height,
+        ..containing_block.content
+    };
+    if root_box.position() == Position::Static {
+        root_box.layout_positioned_descendants(initial_containing_block, viewport);
+    }
+    root_box.layout_fixed_descendants(initial_containing_block, viewport);
+}
+
+/// Layout-wide values that relative lengths can refer to
+#[derive(Clone, Copy, Debug)]
+struct Viewport {
+    width: f32,
+    height: f32,
+    root_font_size: f32,
+    /// When to stop laying out more boxes
+    deadline: Option<Instant>,
+}
+
+impl Viewport {
+    fn expired(&self) -> bool {
+        self.deadline
+            .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
+
+/// Border-box rect of the first element matching `selector`, in document
+/// order (like `getBoundingClientRect`)
+pub fn bounding_rect(root: &LayoutBox, selector: &str) -> Option<Rect> {
+    query_selector(root, selector).map(|layout_box| layout_box.dimensions.border_box())
+}
+
+/// The box of the first element matching `selector`, in document order, or
+/// `None` if nothing matches or the selector is malformed
+pub fn query_selector<'b, 'a>(
+    root: &'b LayoutBox<'a>,
+    selector: &str,
+) -> Option<&'b LayoutBox<'a>> {
+    let selector = css::Parser::parse_selector(selector.to_string()).ok()?;
+    find_box(root, &selector, true)
+}
+
+/// How far down the page the layout reaches: the lowest margin-box edge of
+/// any box, for sizing full-page canvases and scrollbars. Content inside
+/// scroll containers is clipped to them, so only the container counts.
+pub fn document_height(root: &LayoutBox) -> f32 {
+    let margin_box = root.dimensions.margin_box();
+    let bottom = margin_box.y + margin_box.height;
+    if root.scroll.is_some() {
+        return bottom;
+    }
+    root.children
+        .iter()
+        .map(document_height)
+        .fold(bottom, f32::max)
+}
+
+/// The border-box rect (in canvas coordinates, after scrolling) and resolved
+/// URL of every link in document order, so automation can "click" a link by
+/// position without a window. An `href` that can't be resolved against
+/// `base_url` is returned as written.
+pub fn export_clickable_regions(root: &LayoutBox, base_url: &str) -> Vec<(Rect, String)> {
+    let mut regions = Vec::new();
+    collect_clickable_regions(root, base_url, (0.0, 0.0), &mut regions);
+    regions
+}
+
+fn collect_clickable_regions(
+    layout_box: &LayoutBox,
+    base_url: &str,
+    offset: (f32, f32),
+    regions: &mut Vec<(Rect, String)>,
+) {
+    if let BoxType::BlockNode(style)
+    | BoxType::InlineNode(style)
+    | BoxType::InlineBlockNode(style) = layout_box.box_type
+    {
+        // A link's `::before`/`::after` boxes are already inside its own
+        let generated = style.generated_text.is_some();
+        if let (NodeType::Element(ref elem), false) = (&style.node.node_type, generated) {
+            if let ("a", Some(href)) = (elem.tag_name.as_str(), elem.get_attribute("href")) {
+                let url = WebFetcher::resolve_url(base_url, href).unwrap_or_else(|_| href.clone());
+                let rect = layout_box
+                    .dimensions
+                    .border_box()
+                    .translated(offset.0, offset.1);
+                regions.push((rect, url));
+            }
+        }
+    }
+    let offset = match layout_box.scroll {
+        Some(scroll) => (offset.0 - scroll.offset_x, offset.1 - scroll.offset_y),
+        None => offset,
+    };
+    for child in &layout_box.children {
+        collect_clickable_regions(child, base_url, offset, regions);
+    }
+}
+
+/// Find the first box generated by an element matching `selector`
+fn find_box<'b, 'a>(
+    layout_box: &'b LayoutBox<'a>,
+    selector: &Selector,
+    is_root: bool,
+) -> Option<&'b LayoutBox<'a>> {
+    if let BoxType::BlockNode(style)
+    | BoxType::InlineNode(style)
+    | BoxType::InlineBlockNode(style) = layout_box.box_type
+    {
+        if let NodeType::Element(ref elem) = style.node.node_type {
+            let state = ElementState {
+                is_root,
+                ..Default::default()
+            };
+            if style::matches(elem, state, selector) {
+                return Some(layout_box);
+            }
+        }
+    }
+    layout_box
+        .children
+        .iter()
+        .find_map(|child| find_box(child, selector, false))
+}
+
 /// Build the tree of LayoutBoxes
 fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
-    let mut root = LayoutBox::new(match style_node.display() {
-        Display::Block => BoxType::BlockNode(style_node),
-        Display::Inline => BoxType::InlineNode(style_node),
-        Display::None => panic!("Root node has display: none"),
-    });
+    build_box(
+        style_node,
+        match style_node.display() {
+            Display::Block
+            | Display::ListItem
+            | Display::Flex
+            | Display::TableRow
+            | Display::TableCell => BoxType::BlockNode(style_node),
+            Display::Inline => BoxType::InlineNode(style_node),
+            Display::InlineBlock => BoxType::InlineBlockNode(style_node),
+            Display::None => panic!("Root node has display: none"),
+        },
+    )
+}
 
+/// Build a box of the given type for a node, and the boxes inside it
+fn build_box<'a>(style_node: &'a StyledNode<'a>, box_type: BoxType<'a>) -> LayoutBox<'a> {
+    // Table rows hold their cells much like a flex container holds items
+    let is_flex_container = matches!(style_node.display(), Display::Flex | Display::TableRow);
+    let mut root = LayoutBox::new(box_type);
+
+    let mut ordinal = 0;
     for child in &style_node.children {
         match child.display() {
-            Display::Block => root.children.push(build_layout_tree(child)),
-            Display::Inline => root
+            Display::Block | Display::Flex | Display::TableRow | Display::TableCell => {
+                root.children.push(build_layout_tree(child))
+            }
+            Display::ListItem => {
+                ordinal += 1;
+                let mut item = build_layout_tree(child);
+                item.marker = child.marker_text(ordinal).map(|text| TextFragment {
+                    text,
+                    rect: Rect::default(),
+                });
+                root.children.push(item);
+            }
+            // Whitespace between flex items isn't rendered
+            Display::Inline if is_flex_container && child.node.is_whitespace_text() => {}
+            // Each element in a flex container is an item of its own, laid
+            // out like a block; runs of text share an anonymous item
+            Display::Inline | Display::InlineBlock
+                if is_flex_container && matches!(child.node.node_type, NodeType::Element(_)) =>
+            {
+                root.children
+                    .push(build_box(child, BoxType::InlineBlockNode(child)));
+            }
+            Display::Inline | Display::InlineBlock => root
                 .get_inline_container()
                 .children
                 .push(build_layout_tree(child)),
@@ -120,7 +525,7 @@ impl<'a> LayoutBox<'a> {
     fn get_inline_container(&mut self) -> &mut LayoutBox<'a> {
         match self.box_type {
             BoxType::InlineNode(_) | BoxType::AnonymousBlock => self,
-            BoxType::BlockNode(_) => {
+            BoxType::BlockNode(_) | BoxType::InlineBlockNode(_) => {
                 match self.children.last() {
                     Some(&LayoutBox {
                         box_type: BoxType::AnonymousBlock,
@@ -133,83 +538,829 @@ impl<'a> LayoutBox<'a> {
         }
     }
 
-    fn layout(&mut self, containing_block: Dimensions) {
+    fn layout(&mut self, containing_block: Dimensions, viewport: Viewport) {
         match self.box_type {
-            BoxType::BlockNode(_) => self.layout_block(containing_block),
-            BoxType::InlineNode(_) => self.layout_inline(containing_block),
-            BoxType::AnonymousBlock => self.layout_anonymous_block(containing_block),
+            BoxType::BlockNode(_) | BoxType::InlineBlockNode(_) => {
+                self.layout_block(containing_block, viewport)
+            }
+            BoxType::InlineNode(_) => {
+                let mut cursor = InlineCursor::new(containing_block, viewport);
+                self.layout_inline(&mut cursor);
+                self.align_baselines(&cursor.finish_lines());
+            }
+            BoxType::AnonymousBlock => self.layout_anonymous_block(containing_block, viewport, 0.0),
+        }
+    }
+
+    /// Context for resolving this box's lengths, with percentages relative
+    /// to `percent_base`
+    fn length_context(&self, viewport: Viewport, percent_base: f32) -> LengthContext {
+        LengthContext {
+            font_size: self.get_style_node().font_size(),
+            root_font_size: viewport.root_font_size,
+            viewport_width: viewport.width,
+            viewport_height: viewport.height,
+            percent_base,
         }
     }
 
-    fn layout_anonymous_block(&mut self, containing_block: Dimensions) {
+    /// `text_indent` shifts the start of the first line, for an anonymous
+    /// block that begins its parent's content
+    fn layout_anonymous_block(
+        &mut self,
+        containing_block: Dimensions,
+        viewport: Viewport,
+        text_indent: f32,
+    ) {
         // Anonymous blocks contain inline children
         self.dimensions.content.x = containing_block.content.x;
         self.dimensions.content.y = containing_block.content.y + containing_block.content.height;
         self.dimensions.content.width = containing_block.content.width;
-        
-        // Layout all inline children
+
+        // Flow all inline children into line boxes. Whitespace at the edges
+        // of the block's content takes no room: a space is never placed at
+        // the start of a line, and one still pending at the end is dropped.
+        let mut cursor = InlineCursor::new(self.dimensions, viewport);
+        cursor.x += text_indent;
+        for i in 0..self.children.len() {
+            if viewport.expired() {
+                break;
+            }
+            cursor.word_after = following_word(&self.children[i + 1..], viewport).0;
+            self.children[i].layout_inline(&mut cursor);
+        }
+        let lines = cursor.finish_lines();
         for child in &mut self.children {
-            child.layout(self.dimensions);
-            // Update height to accommodate children
-            if child.dimensions.content.height > 0.0 {
-                self.dimensions.content.height = self.dimensions.content.height.max(
-                    child.dimensions.content.y + child.dimensions.content.height - self.dimensions.content.y
-                );
+            child.align_baselines(&lines);
+        }
+        self.dimensions.content.height = cursor.bottom - self.dimensions.content.y;
+    }
+
+    /// Resolved `letter-spacing` and `word-spacing`; `normal` is no extra
+    /// space
+    fn text_spacing(&self, viewport: Viewport) -> TextSpacing {
+        let style = self.get_style_node();
+        let lengths = self.length_context(viewport, style.font_size());
+        let resolve = |name| match style.value(name) {
+            Some(value) => resolve_length(&value, &lengths),
+            None => 0.0,
+        };
+        TextSpacing {
+            letter: resolve("letter-spacing"),
+            word: resolve("word-spacing"),
+        }
+    }
+
+    /// Lay out a box inside an inline formatting context, advancing the cursor
+    fn layout_inline(&mut self, cursor: &mut InlineCursor) {
+        let style_node = match self.box_type {
+            BoxType::InlineNode(style_node) => style_node,
+            BoxType::InlineBlockNode(_) => {
+                self.layout_inline_block(cursor);
+                return;
             }
+            BoxType::BlockNode(_) if self.position().is_out_of_flow() => {
+                self.record_static_position(cursor.x, cursor.y);
+                return;
+            }
+            BoxType::BlockNode(_) | BoxType::AnonymousBlock => {
+                // A block inside inline content sits on its own line
+                cursor.break_line();
+                self.layout(cursor.containing_block(), cursor.viewport);
+                cursor.advance_past_block(self.dimensions.margin_box().height);
+                return;
+            }
+        };
+
+        let start = Rect {
+            x: cursor.x,
+            y: cursor.y,
+            width: 0.0,
+            height: 0.0,
+        };
+
+        if let Some(text) = style_node.text() {
+            // Case changes only affect what's rendered, not the DOM
+            let text = style_node.text_transform().apply(text);
+            let font_size = style_node.font_size();
+            self.text_spacing = self.text_spacing(cursor.viewport);
+            self.fragments = match style_node.white_space() {
+                WhiteSpace::Normal => {
+                    let break_words = style_node.overflow_wrap() == OverflowWrap::BreakWord;
+                    cursor.place_text(&text, break_words, self.text_spacing, font_size)
+                }
+                WhiteSpace::Pre => cursor.place_preformatted(
+                    &text,
+                    style_node.tab_size(),
+                    self.text_spacing,
+                    font_size,
+                ),
+            };
+        } else if self.is_wbr() {
+            cursor.allow_break();
+        } else {
+            // A word ending in the last child may carry on past this box
+            let word_after = cursor.word_after;
+            for i in 0..self.children.len() {
+                let (width, carries_on) = following_word(&self.children[i + 1..], cursor.viewport);
+                cursor.word_after = width + if carries_on { word_after } else { 0.0 };
+                self.children[i].layout_inline(cursor);
+            }
+            cursor.word_after = word_after;
+        }
+        self.dimensions.content = self.inline_content_rect().unwrap_or(start);
+        self.calculate_paint_offsets(cursor.viewport);
+    }
+
+    /// `<wbr>` has no content; it only lets the line wrap where it is
+    fn is_wbr(&self) -> bool {
+        match self.box_type {
+            BoxType::InlineNode(style) => match style.node.node_type {
+                NodeType::Element(ref elem) => elem.tag_name == "wbr",
+                NodeType::Text(_) => false,
+            },
+            _ => false,
+        }
+    }
+
+    /// The width of the unbreakable text this inline-level box starts with,
+    /// which continues any word just before it, and whether that word
+    /// carries on past the box's end
+    fn leading_word(&self, viewport: Viewport) -> (f32, bool) {
+        let style = match self.box_type {
+            BoxType::InlineNode(style) => style,
+            BoxType::BlockNode(_) if self.position().is_out_of_flow() => return (0.0, true),
+            _ => return (0.0, false),
+        };
+        if self.is_wbr() {
+            return (0.0, false);
+        }
+        let Some(text) = style.text() else {
+            return following_word(&self.children, viewport);
+        };
+        if style.white_space() == WhiteSpace::Pre {
+            return (0.0, false);
+        }
+        let text = style.text_transform().apply(text);
+        let word = text.split(is_collapsible_space).next().unwrap_or_default();
+        let width = measure_text(word, self.text_spacing(viewport), style.font_size());
+        (width, word.len() == text.len())
+    }
+
+    /// The area covered by an inline box's text or children, if any
+    fn inline_content_rect(&self) -> Option<Rect> {
+        if !self.fragments.is_empty() {
+            return self
+                .fragments
+                .iter()
+                .map(|fragment| fragment.rect)
+                .reduce(|a, b| a.union(&b));
+        }
+        self.children
+            .iter()
+            .map(|child| child.dimensions.margin_box())
+            .filter(|rect| rect.width > 0.0 || rect.height > 0.0)
+            .reduce(|a, b| a.union(&b))
+    }
+
+    /// Move everything this inline-level box placed on a line down so its
+    /// baseline meets the line's. Text is placed with its top at the top of
+    /// the line, so smaller text moves down to line up with larger text;
+    /// inline-blocks sit on the baseline with their bottom margin edge.
+    /// `lines` holds the top and baseline offset of each line.
+    fn align_baselines(&mut self, lines: &[(f32, f32)]) {
+        let line_ascent = |top: f32| {
+            lines
+                .iter()
+                .find(|&&(line_top, _)| line_top == top)
+                .map(|&(_, ascent)| ascent)
+        };
+        match self.box_type {
+            BoxType::InlineNode(style) if style.text().is_some() => {
+                let ascent = BASELINE * text_scale(style.font_size());
+                for fragment in &mut self.fragments {
+                    if let Some(line_ascent) = line_ascent(fragment.rect.y) {
+                        fragment.rect.y += line_ascent - ascent;
+                    }
+                }
+            }
+            BoxType::InlineNode(_) => {
+                for child in &mut self.children {
+                    child.align_baselines(lines);
+                }
+            }
+            BoxType::InlineBlockNode(_) => {
+                let margin_box = self.dimensions.margin_box();
+                if let Some(line_ascent) = line_ascent(margin_box.y) {
+                    self.translate(0.0, line_ascent - margin_box.height);
+                }
+                return;
+            }
+            // Blocks sit between lines rather than on one
+            BoxType::BlockNode(_) | BoxType::AnonymousBlock => return,
+        }
+        if let Some(content) = self.inline_content_rect() {
+            self.dimensions.content = content;
+        }
+    }
+
+    /// Lay out an inline-block's contents like a block, then place its
+    /// margin box on the current line
+    fn layout_inline_block(&mut self, cursor: &mut InlineCursor) {
+        let containing_block = cursor.containing_block();
+        self.layout_block(containing_block, cursor.viewport);
+
+        // Unlike a block, the margins don't stretch to fill the line
+        let style = self.get_style_node();
+        let lengths = self.length_context(cursor.viewport, containing_block.content.width);
+        let zero = Value::Length(0.0, Unit::Px);
+        self.dimensions.margin.right =
+            resolve_length(&style.lookup("margin-right", "margin", &zero), &lengths);
+
+        let margin_box = self.dimensions.margin_box();
+        let (x, y) = cursor.place_box(margin_box.width, margin_box.height);
+        self.translate(x - margin_box.x, y - margin_box.y);
+    }
+
+    /// Move this box and everything in it
+    fn translate(&mut self, dx: f32, dy: f32) {
+        self.dimensions.content = self.dimensions.content.translated(dx, dy);
+        for fragment in self.fragments.iter_mut().chain(&mut self.marker) {
+            fragment.rect = fragment.rect.translated(dx, dy);
+        }
+        for child in &mut self.children {
+            child.translate(dx, dy);
         }
-        
-        // Ensure minimum height
-        if self.dimensions.content.height == 0.0 && !self.children.is_empty() {
-            self.dimensions.content.height = 14.0;
+    }
+
+    fn layout_block(&mut self, containing_block: Dimensions, viewport: Viewport) {
+        self.calculate_block_width(containing_block, viewport);
+        self.calculate_block_position(containing_block, viewport);
+        match self.get_style_node().display() {
+            Display::Flex => self.layout_flex_items(viewport),
+            Display::TableRow => self.layout_table_cells(viewport),
+            _ => self.layout_block_children(viewport),
+        }
+        let children_height = self.dimensions.content.height;
+        self.calculate_block_height(viewport);
+        self.calculate_border_radius(viewport);
+        self.calculate_paint_offsets(viewport);
+        self.calculate_scroll_extent(children_height);
+        self.place_marker();
+
+        if self.position() == Position::Relative {
+            let (dx, dy) = self.relative_offset(containing_block, viewport);
+            self.translate(dx, dy);
+        }
+        // A positioned box is the containing block for absolutely positioned
+        // descendants
+        if self.position() != Position::Static {
+            let padding_box = self.dimensions.padding_box();
+            self.layout_positioned_descendants(padding_box, viewport);
+        }
+    }
+
+    /// Put a list item's marker on its first line, ending one space left of
+    /// the border box
+    fn place_marker(&mut self) {
+        let border_box = self.dimensions.border_box();
+        if let Some(marker) = &mut self.marker {
+            let width = marker.text.chars().count() as f32 * CHAR_WIDTH;
+            marker.rect = Rect {
+                x: border_box.x - width - CHAR_WIDTH,
+                y: self.dimensions.content.y,
+                width,
+                height: LINE_HEIGHT,
+            };
         }
     }
 
-    fn layout_inline(&mut self, containing_block: Dimensions) {
-        use crate::dom::NodeType;
-        
-        // For inline text nodes, give them some basic dimensions
+    /// Resolve one of the `top`/`right`/`bottom`/`left` offsets, or `None`
+    /// if it's `auto`
+    fn inset(&self, name: &str, lengths: &LengthContext) -> Option<f32> {
+        match self.get_style_node().value(name) {
+            Some(value @ (Value::Length(..) | Value::Number(_))) => {
+                Some(resolve_length(&value, lengths))
+            }
+            _ => None,
+        }
+    }
+
+    /// How far `position: relative` shifts this box from where flow put it.
+    /// Vertical percentages would need a definite containing block height,
+    /// so they resolve to zero.
+    fn relative_offset(&self, containing_block: Dimensions, viewport: Viewport) -> (f32, f32) {
+        let horizontal = self.length_context(viewport, containing_block.content.width);
+        let vertical = self.length_context(viewport, 0.0);
+        let dx = match self.inset("left", &horizontal) {
+            Some(left) => left,
+            None => -self.inset("right", &horizontal).unwrap_or(0.0),
+        };
+        let dy = match self.inset("top", &vertical) {
+            Some(top) => top,
+            None => -self.inset("bottom", &vertical).unwrap_or(0.0),
+        };
+        (dx, dy)
+    }
+
+    /// Remember where an absolutely positioned box would have gone in normal
+    /// flow, for when its offsets are `auto`
+    fn record_static_position(&mut self, x: f32, y: f32) {
+        self.dimensions = Dimensions::default();
+        self.dimensions.content.x = x;
+        self.dimensions.content.y = y;
+    }
+
+    /// Lay out the absolutely positioned boxes whose containing block is
+    /// `containing_block`, now that its size is known
+    fn layout_positioned_descendants(&mut self, containing_block: Rect, viewport: Viewport) {
+        for child in &mut self.children {
+            match child.position() {
+                Position::Absolute => child.layout_absolute(containing_block, viewport),
+                // Positioned boxes handle their own descendants, and fixed
+                // boxes are placed once the rest of the page is
+                Position::Relative | Position::Fixed => {}
+                Position::Static => child.layout_positioned_descendants(containing_block, viewport),
+            }
+        }
+    }
+
+    /// Lay out every fixed box against the viewport, after everything else,
+    /// so no ancestor's relative offset moves it afterwards
+    fn layout_fixed_descendants(&mut self, viewport_rect: Rect, viewport: Viewport) {
+        for child in &mut self.children {
+            if child.position() == Position::Fixed {
+                child.layout_absolute(viewport_rect, viewport);
+            }
+            child.layout_fixed_descendants(viewport_rect, viewport);
+        }
+    }
+
+    /// Lay out an absolutely positioned box against the padding box of its
+    /// containing block. Percentage offsets are relative to the containing
+    /// block's width horizontally and its height vertically.
+    fn layout_absolute(&mut self, containing_block: Rect, viewport: Viewport) {
+        let static_position = (self.dimensions.content.x, self.dimensions.content.y);
+        let horizontal = self.length_context(viewport, containing_block.width);
+        let vertical = self.length_context(viewport, containing_block.height);
+        let left = self.inset("left", &horizontal);
+        let right = self.inset("right", &horizontal);
+        let top = self.inset("top", &vertical);
+        let bottom = self.inset("bottom", &vertical);
+
+        // Without a width, the box fills the space between its offsets
+        let block = Dimensions {
+            content: Rect {
+                width: containing_block.width - left.unwrap_or(0.0) - right.unwrap_or(0.0),
+                height: 0.0,
+                ..containing_block
+            },
+            ..Default::default()
+        };
+        self.layout_block(block, viewport);
+
+        let style = self.get_style_node();
+        // Unlike in flow, the margins don't stretch to fill the containing block
+        let zero = Value::Length(0.0, Unit::Px);
+        self.dimensions.margin.right =
+            resolve_length(&style.lookup("margin-right", "margin", &zero), &horizontal);
+        // With both vertical offsets and no height, it fills the space between
+        if let (Some(top), Some(bottom), None) = (top, bottom, style.value("height")) {
+            let margin_box = self.dimensions.margin_box();
+            let edges = margin_box.height - self.dimensions.content.height;
+            self.dimensions.content.height =
+                (containing_block.height - top - bottom - edges).max(0.0);
+        }
+
+        let margin_box = self.dimensions.margin_box();
+        let x = match (left, right) {
+            (Some(left), _) => containing_block.x + left,
+            (None, Some(right)) => {
+                containing_block.x + containing_block.width - right - margin_box.width
+            }
+            (None, None) => static_position.0,
+        };
+        let y = match (top, bottom) {
+            (Some(top), _) => containing_block.y + top,
+            (None, Some(bottom)) => {
+                containing_block.y + containing_block.height - bottom - margin_box.height
+            }
+            (None, None) => static_position.1,
+        };
+        self.translate(x - margin_box.x, y - margin_box.y);
+    }
+
+    /// Resolve `border-radius`; percentages are relative to the border box's
+    /// width horizontally and its height vertically
+    fn calculate_border_radius(&mut self, viewport: Viewport) {
+        let radius = match self.get_style_node().value("border-radius") {
+            Some(radius) => radius,
+            None => return,
+        };
+        let border_box = self.dimensions.border_box();
+        let horizontal = self.length_context(viewport, border_box.width);
+        let vertical = self.length_context(viewport, border_box.height);
+        self.border_radius = (
+            resolve_length(&radius, &horizontal),
+            resolve_length(&radius, &vertical),
+        );
+    }
+
+    /// Resolve `outline-width` and `transform: translate(x, y)`, which only
+    /// affect painting; translate percentages are of the border box's size
+    fn calculate_paint_offsets(&mut self, viewport: Viewport) {
         let style_node = self.get_style_node();
-        
-        if let NodeType::Text(text) = &style_node.node.node_type {
-            let text = text.trim();
-            if !text.is_empty() {
-                // Simple text metrics: ~8px per char, 14px height
-                const CHAR_WIDTH: f32 = 8.0;
-                const LINE_HEIGHT: f32 = 14.0;
-                
-                let width = (text.len() as f32 * CHAR_WIDTH).min(containing_block.content.width);
-                let height = LINE_HEIGHT;
-                
-                self.dimensions.content.x = containing_block.content.x;
-                self.dimensions.content.y = containing_block.content.y + containing_block.content.height;
-                self.dimensions.content.width = width;
-                self.dimensions.content.height = height;
+        let border_box = self.dimensions.border_box();
+        let horizontal = self.length_context(viewport, border_box.width);
+        let vertical = self.length_context(viewport, border_box.height);
+        if let Some(width) = style_node.value("outline-width") {
+            self.outline_width = resolve_length(&width, &horizontal);
+        }
+        if let Some(Value::Translate(x, y)) = style_node.value("transform") {
+            self.translation = (
+                resolve_length(&x, &horizontal),
+                resolve_length(&y, &vertical),
+            );
+        }
+    }
+
+    /// Mark `overflow: scroll`/`auto` boxes as scroll containers and record
+    /// how far their content extends, keeping any existing scroll position.
+    fn calculate_scroll_extent(&mut self, children_height: f32) {
+        match self.get_style_node().overflow() {
+            Overflow::Scroll | Overflow::Auto => {}
+            Overflow::Visible | Overflow::Hidden => {
+                self.scroll = None;
+                return;
             }
         }
+
+        let d = self.dimensions;
+        let padding_box = d.padding_box();
+        let content_right = self
+            .children
+            .iter()
+            .map(|child| {
+                let margin_box = child.dimensions.margin_box();
+                margin_box.x + margin_box.width
+            })
+            .fold(d.content.x + d.content.width, f32::max);
+
+        let mut scroll = self.scroll.unwrap_or_default();
+        scroll.content_width = content_right - padding_box.x + d.padding.right;
+        scroll.content_height = d.padding.top + children_height + d.padding.bottom;
+        self.scroll = Some(scroll);
+        self.scroll_by(0.0, 0.0);
+    }
+
+    /// Scroll this box's content, clamped so it can't scroll past its content
+    pub fn scroll_by(&mut self, dx: f32, dy: f32) {
+        let padding_box = self.dimensions.padding_box();
+        if let Some(scroll) = &mut self.scroll {
+            let max_x = (scroll.content_width - padding_box.width).max(0.0);
+            let max_y = (scroll.content_height - padding_box.height).max(0.0);
+            scroll.offset_x = (scroll.offset_x + dx).clamp(0.0, max_x);
+            scroll.offset_y = (scroll.offset_y + dy).clamp(0.0, max_y);
+        }
+    }
+
+    /// Find the innermost scroll container under a point in canvas coordinates
+    pub fn scroll_container_at(&mut self, x: f32, y: f32) -> Option<&mut LayoutBox<'a>> {
+        let mut path = Vec::new();
+        if !self.find_scroll_container(x, y, &mut path) {
+            return None;
+        }
+        let mut target = self;
+        for index in path {
+            target = &mut target.children[index];
+        }
+        Some(target)
     }
 
-    fn layout_block(&mut self, containing_block: Dimensions) {
-        self.calculate_block_width(containing_block);
-        self.calculate_block_position(containing_block);
-        self.layout_block_children();
-        self.calculate_block_height();
+    /// The innermost element whose box contains a point in canvas
+    /// coordinates, accounting for scrolled content (but not transforms)
+    pub fn element_at(&self, x: f32, y: f32) -> Option<&'a Node> {
+        let child_hit = match self.scroll {
+            // Scrolled content is clipped to the padding box
+            Some(_) if !self.dimensions.padding_box().contains(x, y) => None,
+            Some(scroll) => self
+                .children
+                .iter()
+                .rev()
+                .find_map(|child| child.element_at(x + scroll.offset_x, y + scroll.offset_y)),
+            None => self
+                .children
+                .iter()
+                .rev()
+                .find_map(|child| child.element_at(x, y)),
+        };
+        child_hit.or_else(|| match self.box_type {
+            BoxType::BlockNode(style)
+            | BoxType::InlineNode(style)
+            | BoxType::InlineBlockNode(style)
+                if self.dimensions.border_box().contains(x, y) =>
+            {
+                match style.node.node_type {
+                    NodeType::Element(_) => Some(style.node),
+                    NodeType::Text(_) => None,
+                }
+            }
+            _ => None,
+        })
+    }
+
+    /// The boxes under a point in canvas coordinates, from this one (the
+    /// outermost) down to the innermost, topmost one, or nothing if the
+    /// point misses this box and its descendants. An ancestor is in the
+    /// chain even if its descendant overflows it. Like `element_at`, this
+    /// accounts for scrolled content but not transforms.
+    pub fn hit_test(&self, x: f32, y: f32) -> Vec<&LayoutBox<'a>> {
+        let descendants = match self.scroll {
+            // Scrolled content is clipped to the padding box
+            Some(_) if !self.dimensions.padding_box().contains(x, y) => Vec::new(),
+            Some(scroll) => self.hit_test_children(x + scroll.offset_x, y + scroll.offset_y),
+            None => self.hit_test_children(x, y),
+        };
+        if descendants.is_empty() && !self.dimensions.border_box().contains(x, y) {
+            return descendants;
+        }
+        let mut chain = vec![self];
+        chain.extend(descendants);
+        chain
+    }
+
+    /// The hit-test chain of the topmost child under a point
+    fn hit_test_children(&self, x: f32, y: f32) -> Vec<&LayoutBox<'a>> {
+        self.children
+            .iter()
+            .rev()
+            .map(|child| child.hit_test(x, y))
+            .find(|chain| !chain.is_empty())
+            .unwrap_or_default()
+    }
+
+    /// The scroll state of every box, in tree order
+    pub fn scroll_states(&self) -> Vec<Option<ScrollState>> {
+        let mut states = vec![self.scroll];
+        for child in &self.children {
+            states.extend(child.scroll_states());
+        }
+        states
+    }
+
+    /// Reapply scroll offsets saved by `scroll_states` after a relayout of
+    /// the same tree, clamped to the new content size
+    pub fn restore_scroll_states(
+        &mut self,
+        states: &mut impl Iterator<Item = Option<ScrollState>>,
+    ) {
+        if let (Some(scroll), Some(Some(saved))) = (&mut self.scroll, states.next()) {
+            scroll.offset_x = saved.offset_x;
+            scroll.offset_y = saved.offset_y;
+        }
+        self.scroll_by(0.0, 0.0);
+        for child in &mut self.children {
+            child.restore_scroll_states(states);
+        }
+    }
+
+    /// Snapshot the geometry of this box and its descendants
+    pub fn to_rect_tree(&self) -> RectTree {
+        let tag_name = match self.box_type {
+            BoxType::BlockNode(style)
+            | BoxType::InlineNode(style)
+            | BoxType::InlineBlockNode(style) => match style.node.node_type {
+                NodeType::Element(ref elem) => Some(elem.tag_name.clone()),
+                NodeType::Text(_) => Some(String::from("#text")),
+            },
+            BoxType::AnonymousBlock => None,
+        };
+        let d = &self.dimensions;
+        RectTree {
+            tag_name,
+            content: d.content,
+            padding: d.padding_box(),
+            border: d.border_box(),
+            margin: d.margin_box(),
+            children: self.children.iter().map(LayoutBox::to_rect_tree).collect(),
+        }
+    }
+
+    /// Record the child indices leading to the innermost scroll container at
+    /// (x, y), with the point already adjusted for ancestor scroll offsets.
+    fn find_scroll_container(&self, x: f32, y: f32, path: &mut Vec<usize>) -> bool {
+        let (child_x, child_y) = match self.scroll {
+            Some(scroll) => {
+                if !self.dimensions.padding_box().contains(x, y) {
+                    return false;
+                }
+                (x + scroll.offset_x, y + scroll.offset_y)
+            }
+            None => (x, y),
+        };
+
+        for (index, child) in self.children.iter().enumerate().rev() {
+            path.push(index);
+            if child.find_scroll_container(child_x, child_y, path) {
+                return true;
+            }
+            path.pop();
+        }
+
+        self.scroll.is_some()
     }
 
-    fn calculate_block_width(&mut self, containing_block: Dimensions) {
+    /// The content width `min-content` or `max-content` asks for, if the
+    /// box's width is one of those keywords
+    fn content_keyword_width(&self, viewport: Viewport) -> Option<f32> {
+        match self.get_style_node().value("width") {
+            Some(Value::Keyword(keyword)) if keyword == "min-content" => {
+                Some(self.intrinsic_widths(viewport).0)
+            }
+            Some(Value::Keyword(keyword)) if keyword == "max-content" => {
+                Some(self.intrinsic_widths(viewport).1)
+            }
+            _ => None,
+        }
+    }
+
+    /// The `(min-content, max-content)` widths of this box's content: the
+    /// narrowest it can be without overflowing (its longest word, roughly),
+    /// and how wide it would be if nothing wrapped
+    fn intrinsic_widths(&self, viewport: Viewport) -> (f32, f32) {
+        match self.box_type {
+            BoxType::AnonymousBlock | BoxType::InlineNode(_) => {
+                let unbounded = Dimensions {
+                    content: Rect {
+                        width: f32::INFINITY,
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                };
+                let mut cursor = InlineCursor::new(unbounded, viewport);
+                let mut widths = (0.0, 0.0);
+                match self.box_type {
+                    BoxType::AnonymousBlock => {
+                        for child in &self.children {
+                            child.inline_intrinsic_widths(&mut cursor, &mut widths);
+                        }
+                    }
+                    _ => self.inline_intrinsic_widths(&mut cursor, &mut widths),
+                }
+                widths
+            }
+            BoxType::BlockNode(style) | BoxType::InlineBlockNode(style) => {
+                let children = self
+                    .children
+                    .iter()
+                    .filter(|child| !child.position().is_out_of_flow())
+                    .map(|child| child.outer_intrinsic_widths(viewport));
+                let is_row = match style.display() {
+                    Display::Flex => style.flex_direction() == FlexDirection::Row,
+                    Display::TableRow => true,
+                    _ => false,
+                };
+                if is_row {
+                    // Flex items and table cells sit side by side without
+                    // wrapping
+                    children.fold((0.0, 0.0), |(min, max), (child_min, child_max)| {
+                        (min + child_min, max + child_max)
+                    })
+                } else {
+                    // Block-level children each sit on their own lines
+                    children.fold((0.0, 0.0), |(min, max), (child_min, child_max)| {
+                        (f32::max(min, child_min), f32::max(max, child_max))
+                    })
+                }
+            }
+        }
+    }
+
+    /// Intrinsic widths of this box's margin box, honoring a fixed `width`
+    fn outer_intrinsic_widths(&self, viewport: Viewport) -> (f32, f32) {
+        let style = match self.box_type {
+            BoxType::BlockNode(style) | BoxType::InlineBlockNode(style) => style,
+            BoxType::AnonymousBlock | BoxType::InlineNode(_) => {
+                return self.intrinsic_widths(viewport)
+            }
+        };
+        // Percentages have nothing to resolve against here
+        let lengths = self.length_context(viewport, 0.0);
+        let (min, max) = match style.value("width") {
+            Some(Value::Length(_, unit)) if unit != Unit::Percent => {
+                let width = resolve_length(&style.value("width").unwrap(), &lengths);
+                (width, width)
+            }
+            _ => match self.content_keyword_width(viewport) {
+                Some(width) => (width, width),
+                None => self.intrinsic_widths(viewport),
+            },
+        };
+
+        let zero = Value::Length(0.0, Unit::Px);
+        let edges: f32 = [
+            ("margin-left", "margin"),
+            ("margin-right", "margin"),
+            ("border-left-width", "border-width"),
+            ("border-right-width", "border-width"),
+            ("padding-left", "padding"),
+            ("padding-right", "padding"),
+        ]
+        .iter()
+        .map(|&(name, fallback_name)| {
+            resolve_length(&style.lookup(name, fallback_name, &zero), &lengths)
+        })
+        .sum();
+        (min + edges, max + edges)
+    }
+
+    /// Flow this box's inline content onto an unbounded line to measure it,
+    /// raising `widths` to cover the longest unbreakable piece and the
+    /// furthest the line reaches
+    fn inline_intrinsic_widths(&self, cursor: &mut InlineCursor, widths: &mut (f32, f32)) {
+        let style = match self.box_type {
+            BoxType::InlineNode(style) => style,
+            BoxType::InlineBlockNode(_) => {
+                let (min, max) = self.outer_intrinsic_widths(cursor.viewport);
+                cursor.place_box(max, 0.0);
+                widths.0 = widths.0.max(min);
+                widths.1 = widths.1.max(cursor.x - cursor.left);
+                return;
+            }
+            BoxType::BlockNode(_) if self.position().is_out_of_flow() => return,
+            BoxType::BlockNode(_) | BoxType::AnonymousBlock => {
+                let (min, max) = self.outer_intrinsic_widths(cursor.viewport);
+                cursor.break_line();
+                cursor.advance_past_block(0.0);
+                widths.0 = widths.0.max(min);
+                widths.1 = widths.1.max(max);
+                return;
+            }
+        };
+
+        let Some(text) = style.text() else {
+            for child in &self.children {
+                child.inline_intrinsic_widths(cursor, widths);
+            }
+            return;
+        };
+        let text = style.text_transform().apply(text);
+        let font_size = style.font_size();
+        let spacing = self.text_spacing(cursor.viewport);
+        let fragments = match style.white_space() {
+            WhiteSpace::Normal => {
+                let longest_word = text
+                    .split(is_collapsible_space)
+                    .map(|word| measure_text(word, spacing, font_size))
+                    .fold(0.0, f32::max);
+                widths.0 = widths.0.max(longest_word);
+                cursor.place_text(&text, false, spacing, font_size)
+            }
+            WhiteSpace::Pre => {
+                let fragments =
+                    cursor.place_preformatted(&text, style.tab_size(), spacing, font_size);
+                // Preformatted lines can't wrap at all
+                for fragment in &fragments {
+                    widths.0 = widths
+                        .0
+                        .max(fragment.rect.x + fragment.rect.width - cursor.left);
+                }
+                fragments
+            }
+        };
+        for fragment in &fragments {
+            widths.1 = widths
+                .1
+                .max(fragment.rect.x + fragment.rect.width - cursor.left);
+        }
+    }
+
+    fn calculate_block_width(&mut self, containing_block: Dimensions, viewport: Viewport) {
         let style = self.get_style_node();
+        let lengths = self.length_context(viewport, containing_block.content.width);
         let auto = Value::Keyword("auto".to_string());
-        let mut width = style.value("width").unwrap_or(auto.clone());
+        // With no width, an aspect ratio can size the box from its height
+        let width = self
+            .content_keyword_width(viewport)
+            .or_else(|| lengths.size(style.value("width")))
+            .or_else(|| Some(self.height_property(viewport)? * self.aspect_ratio()?));
+        let mut width = match width {
+            Some(width) => Value::Length(width, Unit::Px),
+            None => auto.clone(),
+        };
 
         let zero = Value::Length(0.0, Unit::Px);
+        let lookup =
+            |name, fallback_name| lengths.absolute(style.lookup(name, fallback_name, &zero));
 
-        let mut margin_left = style.lookup("margin-left", "margin", &zero);
-        let mut margin_right = style.lookup("margin-right", "margin", &zero);
+        let mut margin_left = lookup("margin-left", "margin");
+        let mut margin_right = lookup("margin-right", "margin");
 
-        let border_left = style.lookup("border-left-width", "border-width", &zero);
-        let border_right = style.lookup("border-right-width", "border-width", &zero);
+        let border_left = lookup("border-left-width", "border-width");
+        let border_right = lookup("border-right-width", "border-width");
 
-        let padding_left = style.lookup("padding-left", "padding", &zero);
-        let padding_right = style.lookup("padding-right", "padding", &zero);
+        let padding_left = lookup("padding-left", "padding");
+        let padding_right = lookup("padding-right", "padding");
 
         let total: f32 = [
             &margin_left,
@@ -279,24 +1430,26 @@ impl<'a> LayoutBox<'a> {
         d.margin.right = margin_right.to_px();
     }
 
-    fn calculate_block_position(&mut self, containing_block: Dimensions) {
+    fn calculate_block_position(&mut self, containing_block: Dimensions, viewport: Viewport) {
         let style = self.get_style_node();
+        // Vertical margin and padding percentages are of the containing
+        // block's width too, as its height usually isn't known yet
+        let lengths = self.length_context(viewport, containing_block.content.width);
         let d = &mut self.dimensions;
 
         let zero = Value::Length(0.0, Unit::Px);
+        let lookup = |name, fallback_name| {
+            resolve_length(&style.lookup(name, fallback_name, &zero), &lengths)
+        };
 
-        d.margin.top = style.lookup("margin-top", "margin", &zero).to_px();
-        d.margin.bottom = style.lookup("margin-bottom", "margin", &zero).to_px();
+        d.margin.top = lookup("margin-top", "margin");
+        d.margin.bottom = lookup("margin-bottom", "margin");
 
-        d.border.top = style
-            .lookup("border-top-width", "border-width", &zero)
-            .to_px();
-        d.border.bottom = style
-            .lookup("border-bottom-width", "border-width", &zero)
-            .to_px();
+        d.border.top = lookup("border-top-width", "border-width");
+        d.border.bottom = lookup("border-bottom-width", "border-width");
 
-        d.padding.top = style.lookup("padding-top", "padding", &zero).to_px();
-        d.padding.bottom = style.lookup("padding-bottom", "padding", &zero).to_px();
+        d.padding.top = lookup("padding-top", "padding");
+        d.padding.bottom = lookup("padding-bottom", "padding");
 
         d.content.x = containing_block.content.x + d.margin.left + d.border.left + d.padding.left;
 
@@ -307,19 +1460,634 @@ impl<'a> LayoutBox<'a> {
             + d.padding.top;
     }
 
-    fn layout_block_children(&mut self) {
+    fn layout_block_children(&mut self, viewport: Viewport) {
+        // `text-indent` applies to the first line of this block's content;
+        // a block child's first line picks it up through inheritance instead
+        let text_indent = match self.get_style_node().value("text-indent") {
+            Some(value) => resolve_length(
+                &value,
+                &self.length_context(viewport, self.dimensions.content.width),
+            ),
+            None => 0.0,
+        };
+        let mut first_line = true;
         let d = &mut self.dimensions;
         for child in &mut self.children {
-            child.layout(*d);
-            d.content.height = d.content.height + child.dimensions.margin_box().height;
+            if viewport.expired() {
+                break;
+            }
+            // Absolutely positioned boxes are taken out of flow and laid out
+            // once their containing block is done
+            if child.position().is_out_of_flow() {
+                child.record_static_position(d.content.x, d.content.y + d.content.height);
+                continue;
+            }
+            match child.box_type {
+                BoxType::AnonymousBlock if first_line => {
+                    child.layout_anonymous_block(*d, viewport, text_indent)
+                }
+                _ => child.layout(*d, viewport),
+            }
+            first_line = false;
+            d.content.height += child.dimensions.margin_box().height;
+        }
+    }
+
+    fn calculate_block_height(&mut self, viewport: Viewport) {
+        if let Some(height) = self.specified_height(viewport) {
+            self.dimensions.content.height = height;
+        }
+    }
+
+    /// The content height set by the `height` property or, failing that, by
+    /// an `aspect-ratio` from the content width, if the box isn't sized by
+    /// its content
+    fn specified_height(&self, viewport: Viewport) -> Option<f32> {
+        self.height_property(viewport)
+            .or_else(|| Some(self.dimensions.content.width / self.aspect_ratio()?))
+    }
+
+    /// Width divided by height from `aspect-ratio`, unless it's `auto` (or
+    /// degenerate)
+    fn aspect_ratio(&self) -> Option<f32> {
+        let style = match self.box_type {
+            BoxType::BlockNode(style)
+            | BoxType::InlineNode(style)
+            | BoxType::InlineBlockNode(style) => style,
+            BoxType::AnonymousBlock => return None,
+        };
+        match style.value("aspect-ratio") {
+            Some(Value::Ratio(width, height)) if width > 0.0 && height > 0.0 => {
+                Some(width / height)
+            }
+            _ => None,
+        }
+    }
+
+    /// The content height set by the `height` property, unless it's `auto`.
+    /// Percentage heights would need a definite containing block height, so
+    /// they behave like `auto`.
+    fn height_property(&self, viewport: Viewport) -> Option<f32> {
+        let style = match self.box_type {
+            BoxType::BlockNode(style)
+            | BoxType::InlineNode(style)
+            | BoxType::InlineBlockNode(style) => style,
+            BoxType::AnonymousBlock => return None,
+        };
+        match style.value("height") {
+            Some(Value::Length(_, Unit::Percent)) => None,
+            height => self.length_context(viewport, 0.0).size(height),
+        }
+    }
+
+    /// Lay out a flex container's items one after another along its main
+    /// axis (no wrapping, growing, or shrinking), then align them across it
+    fn layout_flex_items(&mut self, viewport: Viewport) {
+        let style = self.get_style_node();
+        let direction = style.flex_direction();
+        let align = style.align_items();
+        let content = self.dimensions.content;
+
+        let mut main_size = 0.0;
+        let mut cross_size: f32 = 0.0;
+        for child in &mut self.children {
+            if child.position().is_out_of_flow() {
+                child.record_static_position(content.x, content.y);
+                continue;
+            }
+            // Items are as wide as their content, except when stretched
+            // across a column
+            let (_, max_content) = child.outer_intrinsic_widths(viewport);
+            let (x, y, width) = match direction {
+                FlexDirection::Row => (
+                    content.x + main_size,
+                    content.y,
+                    max_content.min((content.width - main_size).max(0.0)),
+                ),
+                FlexDirection::Column if align == AlignItems::Stretch => {
+                    (content.x, content.y + main_size, content.width)
+                }
+                FlexDirection::Column => (
+                    content.x,
+                    content.y + main_size,
+                    max_content.min(content.width),
+                ),
+            };
+            let containing_block = Dimensions {
+                content: Rect {
+                    x,
+                    y,
+                    width,
+                    height: 0.0,
+                },
+                ..Default::default()
+            };
+            child.layout(containing_block, viewport);
+
+            let margin_box = child.dimensions.margin_box();
+            match direction {
+                FlexDirection::Row => {
+                    main_size += margin_box.width;
+                    cross_size = cross_size.max(margin_box.height);
+                }
+                FlexDirection::Column => {
+                    main_size += margin_box.height;
+                    cross_size = cross_size.max(margin_box.width);
+                }
+            }
+        }
+
+        let (height, cross_size) = match direction {
+            FlexDirection::Row => {
+                let height = self.specified_height(viewport).unwrap_or(cross_size);
+                (height, height)
+            }
+            FlexDirection::Column => (main_size, content.width),
+        };
+        self.dimensions.content.height = height;
+
+        for child in &mut self.children {
+            if child.position().is_out_of_flow() {
+                continue;
+            }
+            let margin_box = child.dimensions.margin_box();
+            let free_space = match direction {
+                FlexDirection::Row => cross_size - margin_box.height,
+                FlexDirection::Column => cross_size - margin_box.width,
+            };
+            let offset = match align {
+                AlignItems::Center => free_space / 2.0,
+                AlignItems::FlexEnd => free_space,
+                AlignItems::FlexStart => 0.0,
+                // Columns stretch items as they're laid out
+                AlignItems::Stretch => {
+                    if direction == FlexDirection::Row && child.specified_height(viewport).is_none()
+                    {
+                        child.dimensions.content.height += free_space.max(0.0);
+                    }
+                    0.0
+                }
+            };
+            match direction {
+                FlexDirection::Row => child.translate(0.0, offset),
+                FlexDirection::Column => child.translate(offset, 0.0),
+            }
+        }
+    }
+
+    /// Lay out a table row's cells side by side, each as wide as its content
+    /// asks for, then stretch them all to the tallest and place each one's
+    /// content by its `vertical-align`. Without a table to line up columns
+    /// across rows, each row sizes its cells on its own.
+    fn layout_table_cells(&mut self, viewport: Viewport) {
+        let content = self.dimensions.content;
+        let intrinsic: Vec<(f32, f32)> = self
+            .children
+            .iter()
+            .filter(|child| !child.position().is_out_of_flow())
+            .map(|child| child.outer_intrinsic_widths(viewport))
+            .collect();
+        let mut widths = table_cell_widths(&intrinsic, content.width).into_iter();
+
+        let mut x = content.x;
+        let mut row_height: f32 = 0.0;
+        for child in &mut self.children {
+            if child.position().is_out_of_flow() {
+                child.record_static_position(x, content.y);
+                continue;
+            }
+            let containing_block = Dimensions {
+                content: Rect {
+                    x,
+                    y: content.y,
+                    width: widths.next().unwrap_or(0.0),
+                    height: 0.0,
+                },
+                ..Default::default()
+            };
+            child.layout(containing_block, viewport);
+            let margin_box = child.dimensions.margin_box();
+            x += margin_box.width;
+            row_height = row_height.max(margin_box.height);
+        }
+        let row_height = self.specified_height(viewport).unwrap_or(row_height);
+        self.dimensions.content.height = row_height;
+
+        for child in &mut self.children {
+            if child.position().is_out_of_flow() {
+                continue;
+            }
+            let free_space = (row_height - child.dimensions.margin_box().height).max(0.0);
+            child.dimensions.content.height += free_space;
+            let offset = match child.get_style_node().vertical_align() {
+                VerticalAlign::Top => 0.0,
+                VerticalAlign::Middle => free_space / 2.0,
+                VerticalAlign::Bottom => free_space,
+            };
+            for content in &mut child.children {
+                content.translate(0.0, offset);
+            }
+        }
+    }
+}
+
+/// Widths for a row's cells, from their `(min-content, max-content)` widths,
+/// that fill `available`: every cell gets its max-content width and a share
+/// of the space left over in proportion to it, or if that doesn't fit, its
+/// min-content width and a share of the space between that and max-content.
+/// Cells too wide even at min-content overflow the row.
+fn table_cell_widths(intrinsic: &[(f32, f32)], available: f32) -> Vec<f32> {
+    let min_total: f32 = intrinsic.iter().map(|&(min, _)| min).sum();
+    let max_total: f32 = intrinsic.iter().map(|&(_, max)| max).sum();
+    intrinsic
+        .iter()
+        .map(|&(min, max)| {
+            if max_total <= available {
+                let extra = available - max_total;
+                match max_total {
+                    0.0 => extra / intrinsic.len() as f32,
+                    _ => max + extra * max / max_total,
+                }
+            } else if min_total >= available {
+                min
+            } else {
+                min + (max - min) * (available - min_total) / (max_total - min_total)
+            }
+        })
+        .collect()
+}
+
+/// Pen position while flowing inline content into line boxes
+struct InlineCursor {
+    left: f32,
+    right: f32,
+    x: f32,
+    y: f32,
+    /// Bottom edge of everything placed so far
+    bottom: f32,
+    viewport: Viewport,
+    line_has_content: bool,
+    /// How far the current line's items reach above and below its baseline
+    line_ascent: f32,
+    line_descent: f32,
+    /// Top and baseline offset of each finished line
+    lines: Vec<(f32, f32)>,
+    /// A collapsed space from earlier text is waiting to be placed before the
+    /// next word, so whitespace collapses across inline element boundaries
+    pending_space: bool,
+    /// The last thing placed was a word with nothing after it, so text that
+    /// follows (say, after an inline element boundary) continues the word
+    /// and can't wrap onto a new line by itself, unless a `<wbr>` allows it
+    in_word: bool,
+    /// Width of the text in later boxes that continues the last word of the
+    /// box being placed, which has to fit on that word's line too
+    word_after: f32,
+}
+
+impl InlineCursor {
+    /// Start at the top of the unused area of a containing block
+    fn new(containing_block: Dimensions, viewport: Viewport) -> InlineCursor {
+        let content = containing_block.content;
+        let top = content.y + content.height;
+        InlineCursor {
+            left: content.x,
+            right: content.x + content.width,
+            x: content.x,
+            y: top,
+            bottom: top,
+            viewport,
+            line_has_content: false,
+            line_ascent: 0.0,
+            line_descent: 0.0,
+            lines: Vec::new(),
+            pending_space: false,
+            in_word: false,
+            word_after: 0.0,
+        }
+    }
+
+    /// Move to the start of the next line, unless the current one is empty
+    fn break_line(&mut self) {
+        if self.line_has_content {
+            self.lines.push((self.y, self.line_ascent));
+            self.x = self.left;
+            self.y += self.line_ascent + self.line_descent;
+            self.line_has_content = false;
+            self.line_ascent = 0.0;
+            self.line_descent = 0.0;
+        }
+        self.pending_space = false;
+        self.in_word = false;
+    }
+
+    /// Allow the line to wrap before the next word, even without a space
+    /// (for `<wbr>`)
+    fn allow_break(&mut self) {
+        self.in_word = false;
+    }
+
+    /// Make room on the current line for an item reaching `ascent` above
+    /// the baseline and `descent` below it. The line is as tall as its
+    /// highest ascent plus its deepest descent.
+    fn extend_line(&mut self, ascent: f32, descent: f32) {
+        self.line_has_content = true;
+        self.line_ascent = self.line_ascent.max(ascent);
+        self.line_descent = self.line_descent.max(descent);
+        self.bottom = self
+            .bottom
+            .max(self.y + self.line_ascent + self.line_descent);
+    }
+
+    /// Make room for a line of text at `font_size`
+    fn extend_line_for_text(&mut self, font_size: f32) {
+        let scale = text_scale(font_size);
+        self.extend_line(BASELINE * scale, (LINE_HEIGHT - BASELINE) * scale);
+    }
+
+    /// Finish the last line, dropping any space left pending after its last
+    /// word, and return the top and baseline offset of every line, for
+    /// `LayoutBox::align_baselines`
+    fn finish_lines(&mut self) -> Vec<(f32, f32)> {
+        if self.line_has_content {
+            self.lines.push((self.y, self.line_ascent));
+        }
+        self.pending_space = false;
+        self.in_word = false;
+        std::mem::take(&mut self.lines)
+    }
+
+    /// Area available to a block placed at the cursor
+    fn containing_block(&self) -> Dimensions {
+        Dimensions {
+            content: Rect {
+                x: self.left,
+                y: self.y,
+                width: self.right - self.left,
+                height: 0.0,
+            },
+            ..Default::default()
+        }
+    }
+
+    fn advance_past_block(&mut self, height: f32) {
+        self.x = self.left;
+        self.y += height;
+        self.bottom = self.bottom.max(self.y);
+    }
+
+    /// Place an atomic box of the given size on the line (after any pending
+    /// space), wrapping first if it doesn't fit, and return its position
+    fn place_box(&mut self, width: f32, height: f32) -> (f32, f32) {
+        let space_width = if self.pending_space { CHAR_WIDTH } else { 0.0 };
+        if self.line_has_content && self.x + space_width + width > self.right {
+            self.break_line();
+        }
+        if self.pending_space && self.line_has_content {
+            self.x += CHAR_WIDTH;
+        }
+
+        let position = (self.x, self.y);
+        self.x += width;
+        self.extend_line(height, 0.0);
+        self.pending_space = false;
+        self.in_word = false;
+        position
+    }
+
+    /// Flow a run of text into line boxes and return the fragment placed on
+    /// each line. Whitespace collapses to single spaces, including against
+    /// the text before this run, and is dropped at the start of a line.
+    /// With `break_words`, a word too long for a line of its own is split
+    /// at the line's edge instead of overflowing it. Words and the spaces
+    /// between them are widened by `spacing`.
+    fn place_text(
+        &mut self,
+        text: &str,
+        break_words: bool,
+        spacing: TextSpacing,
+        font_size: f32,
+    ) -> Vec<TextFragment> {
+        let scale = text_scale(font_size);
+        let mut fragments: Vec<TextFragment> = Vec::new();
+        if text.starts_with(is_collapsible_space) {
+            self.pending_space = true;
+        }
+
+        let words: Vec<&str> = text
+            .split(is_collapsible_space)
+            .filter(|word| !word.is_empty())
+            .collect();
+        let ends_in_word = !text.ends_with(is_collapsible_space);
+        for (i, word) in words.iter().enumerate() {
+            if i > 0 {
+                self.pending_space = true;
+            }
+            let word_width = measure_text(word, spacing, font_size);
+            // A word running on into the next box wraps with its continuation
+            let fit_width = if ends_in_word && i + 1 == words.len() {
+                word_width + self.word_after
+            } else {
+                word_width
+            };
+            // The space itself, plus letter spacing on either side of it
+            let space_advance = CHAR_WIDTH * scale + spacing.word + 2.0 * spacing.letter;
+            let space_width = if self.pending_space {
+                space_advance
+            } else {
+                0.0
+            };
+            // The rest of a word begun by earlier text stays on its line
+            let continues_word = self.in_word && !self.pending_space;
+            if self.line_has_content
+                && !continues_word
+                && self.x + space_width + fit_width > self.right
+            {
+                self.break_line();
+            }
+
+            let space = self.pending_space && self.line_has_content;
+            if space {
+                self.x += space_advance;
+            }
+
+            if break_words && self.x + word_width > self.right {
+                // Only the first piece can join this run's fragment
+                for piece in self.place_broken_word(word, spacing, font_size) {
+                    match fragments.last_mut() {
+                        Some(fragment) if fragment.rect.y == piece.rect.y => {
+                            if space {
+                                fragment.text.push(' ');
+                            }
+                            fragment.text.push_str(&piece.text);
+                            fragment.rect = fragment.rect.union(&piece.rect);
+                        }
+                        _ => fragments.push(piece),
+                    }
+                }
+                continue;
+            }
+
+            // Extend this run's fragment on the current line, or start one
+            match fragments.last_mut() {
+                Some(fragment) if fragment.rect.y == self.y => {
+                    if space {
+                        fragment.text.push(' ');
+                        fragment.rect.width += space_advance;
+                    }
+                    fragment.text.push_str(word);
+                    fragment.rect.width += word_width;
+                }
+                _ => fragments.push(TextFragment {
+                    text: word.to_string(),
+                    rect: Rect {
+                        x: self.x,
+                        y: self.y,
+                        width: word_width,
+                        height: LINE_HEIGHT * scale,
+                    },
+                }),
+            }
+
+            self.x += word_width;
+            self.extend_line_for_text(font_size);
+            self.pending_space = false;
+            self.in_word = true;
+        }
+
+        if text.ends_with(is_collapsible_space) {
+            self.pending_space = true;
+        }
+        fragments
+    }
+
+    /// Place a word that doesn't fit on the current line, which is empty or
+    /// ends in a space, a line's worth of characters at a time. At least one
+    /// character goes on each line, however narrow.
+    fn place_broken_word(
+        &mut self,
+        word: &str,
+        spacing: TextSpacing,
+        font_size: f32,
+    ) -> Vec<TextFragment> {
+        let scale = text_scale(font_size);
+        let mut fragments = Vec::new();
+        let mut rest: Vec<char> = word.chars().collect();
+        while !rest.is_empty() {
+            // n characters take n advances, less the trailing letter spacing
+            let advance = CHAR_WIDTH * scale + spacing.letter;
+            let fits = ((self.right - self.x + spacing.letter) / advance)
+                .floor()
+                .max(1.0) as usize;
+            let piece: String = rest.drain(..fits.min(rest.len())).collect();
+            let width = measure_text(&piece, spacing, font_size);
+            fragments.push(TextFragment {
+                text: piece,
+                rect: Rect {
+                    x: self.x,
+                    y: self.y,
+                    width,
+                    height: LINE_HEIGHT * scale,
+                },
+            });
+            self.x += width;
+            self.extend_line_for_text(font_size);
+            if !rest.is_empty() {
+                self.break_line();
+            }
+        }
+        self.pending_space = false;
+        self.in_word = true;
+        fragments
+    }
+
+    /// Flow text with its whitespace preserved, returning a fragment per
+    /// line. Newlines break lines, tabs advance to the next multiple of
+    /// `tab_size` spaces from the line start, and lines never wrap.
+    /// Characters and spaces are widened by `spacing`, as in `place_text`.
+    fn place_preformatted(
+        &mut self,
+        text: &str,
+        tab_size: f32,
+        spacing: TextSpacing,
+        font_size: f32,
+    ) -> Vec<TextFragment> {
+        let char_width = CHAR_WIDTH * text_scale(font_size);
+        let space_advance = char_width + spacing.letter + spacing.word;
+        let tab_width = tab_size * space_advance;
+        let mut fragments = Vec::new();
+        if self.pending_space && self.line_has_content {
+            self.x += char_width;
+        }
+        self.pending_space = false;
+
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                // Even an empty line takes up a line's height
+                self.extend_line_for_text(font_size);
+                self.break_line();
+            }
+
+            // Tabs become spaces so the painted text lines up with the layout
+            let start = self.x;
+            let mut expanded = String::new();
+            for c in line.chars() {
+                match c {
+                    '\r' => {}
+                    '\t' if tab_width > 0.0 => {
+                        let column = self.x - self.left;
+                        let next_stop = ((column / tab_width).floor() + 1.0) * tab_width;
+                        let spaces = ((next_stop - column) / space_advance).round() as usize;
+                        expanded.extend(std::iter::repeat_n(' ', spaces));
+                        self.x = self.left + next_stop;
+                    }
+                    '\t' => {}
+                    ' ' => {
+                        expanded.push(' ');
+                        self.x += space_advance;
+                    }
+                    c => {
+                        expanded.push(c);
+                        self.x += char_width + spacing.letter;
+                    }
+                }
+            }
+
+            if !expanded.is_empty() {
+                // No letter spacing after the last character
+                self.x = start + measure_text(&expanded, spacing, font_size);
+                fragments.push(TextFragment {
+                    text: expanded,
+                    rect: Rect {
+                        x: start,
+                        y: self.y,
+                        width: self.x - start,
+                        height: LINE_HEIGHT * text_scale(font_size),
+                    },
+                });
+                self.extend_line_for_text(font_size);
+            }
         }
+        fragments
     }
+}
 
-    fn calculate_block_height(&mut self) {
-        if let Some(Value::Length(h, Unit::Px)) = self.get_style_node().value("height") {
-            self.dimensions.content.height = h;
+/// `LayoutBox::leading_word` for a run of sibling boxes: the width of the
+/// unbreakable text they start with, and whether it runs past their end
+fn following_word(boxes: &[LayoutBox], viewport: Viewport) -> (f32, bool) {
+    let mut total = 0.0;
+    for layout_box in boxes {
+        let (width, carries_on) = layout_box.leading_word(viewport);
+        total += width;
+        if !carries_on {
+            return (total, false);
         }
     }
+    (total, true)
+}
+
+/// Whitespace that collapses in normal text (unlike e.g. non-breaking spaces)
+fn is_collapsible_space(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n' | '\r' | '\x0c')
 }
 
 impl Value {
@@ -330,3 +2098,506 @@ impl Value {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{html, style, ua};
+
+    /// Lay out `html` styled by `css` in an 800x600 viewport, and hand the
+    /// root box to `check`
+    fn with_layout(html: &str, css: &str, check: impl FnOnce(&mut LayoutBox)) {
+        let document = html::Parser::parse(html.to_string());
+        let stylesheet = css::Parser::parse(css.to_string());
+        let ua_stylesheet = ua::stylesheet();
+        let styled = style::style_tree(&document, &[&stylesheet], &ua_stylesheet, 800.0);
+        check(&mut layout_tree(&styled, viewport(800.0, 600.0)));
+    }
+
+    fn viewport(width: f32, height: f32) -> Dimensions {
+        let mut viewport = Dimensions::default();
+        viewport.content.width = width;
+        viewport.content.height = height;
+        viewport
+    }
+
+    #[test]
+    fn query_selector_and_bounding_rect_find_the_first_match() {
+        let css = "div { display: block; } .a { height: 10px; } \
+                   .box { width: 50px; height: 30px; padding: 5px; margin-left: 20px; }";
+        with_layout(
+            r#"<div><div class="a"></div><div class="box"></div><div class="box"></div></div>"#,
+            css,
+            |root| {
+                let rect = bounding_rect(root, ".box").unwrap();
+                assert_eq!(rect, root.children[1].dimensions.border_box());
+                assert_eq!(
+                    (rect.x, rect.y, rect.width, rect.height),
+                    (20.0, 10.0, 60.0, 40.0)
+                );
+                assert!(query_selector(root, "span").is_none());
+            },
+        );
+    }
+
+    #[test]
+    fn document_height_reaches_the_lowest_margin_edge() {
+        let css =
+            "div { display: block; } p { display: block; height: 50px; margin-bottom: 20px; }";
+        with_layout("<div><p></p></div>", css, |root| {
+            assert_eq!(document_height(root), 70.0)
+        });
+    }
+
+    #[test]
+    fn layout_tree_with_height_matches_document_height() {
+        let document = html::Parser::parse("<div></div>".to_string());
+        let stylesheet = css::Parser::parse("div { display: block; height: 40px; }".to_string());
+        let ua_stylesheet = ua::stylesheet();
+        let styled = style::style_tree(&document, &[&stylesheet], &ua_stylesheet, 800.0);
+        let (root, height) = layout_tree_with_height(&styled, viewport(800.0, 600.0));
+        assert_eq!(height, document_height(&root));
+        assert_eq!(height, 40.0);
+    }
+
+    #[test]
+    fn layout_tree_with_deadline_in_the_future_matches_layout_tree() {
+        let document = html::Parser::parse("<div><p>text</p></div>".to_string());
+        let stylesheet = css::Parser::parse("div, p { display: block; }".to_string());
+        let ua_stylesheet = ua::stylesheet();
+        let styled = style::style_tree(&document, &[&stylesheet], &ua_stylesheet, 800.0);
+        let deadline = Instant::now() + std::time::Duration::from_secs(60);
+        let with_deadline = layout_tree_with_deadline(&styled, viewport(800.0, 600.0), deadline);
+        let without = layout_tree(&styled, viewport(800.0, 600.0));
+        assert_eq!(with_deadline.to_rect_tree(), without.to_rect_tree());
+    }
+
+    #[test]
+    fn relayout_matches_a_fresh_layout() {
+        let document = html::Parser::parse("<div><p>some words to wrap</p></div>".to_string());
+        let stylesheet = css::Parser::parse("div, p { display: block; }".to_string());
+        let ua_stylesheet = ua::stylesheet();
+        let styled = style::style_tree(&document, &[&stylesheet], &ua_stylesheet, 800.0);
+        let mut root = layout_tree(&styled, viewport(800.0, 600.0));
+        let (root_ptr, child_ptr): (*const LayoutBox, *const LayoutBox) =
+            (&root, &root.children[0]);
+        let wide = root.to_rect_tree();
+        relayout(&mut root, viewport(60.0, 600.0));
+        // The boxes are laid out again in place rather than rebuilt
+        assert!(std::ptr::eq(root_ptr, &root));
+        assert!(std::ptr::eq(child_ptr, &root.children[0]));
+        assert_ne!(root.to_rect_tree(), wide);
+        assert_eq!(
+            root.to_rect_tree(),
+            layout_tree(&styled, viewport(60.0, 600.0)).to_rect_tree()
+        );
+    }
+
+    #[test]
+    fn export_clickable_regions_resolves_links() {
+        let css = "div, a { display: block; height: 20px; }";
+        with_layout(r#"<div><a href="/about">About</a></div>"#, css, |root| {
+            let regions = export_clickable_regions(root, "https://example.com/index.html");
+            assert_eq!(regions.len(), 1);
+            assert_eq!(regions[0].1, "https://example.com/about");
+            assert_eq!(regions[0].0.height, 20.0);
+        });
+    }
+
+    #[test]
+    fn hit_test_returns_the_chain_to_the_innermost_box() {
+        let css = "div { display: block; } p { display: block; height: 10px; }";
+        with_layout("<div><p></p><p></p></div>", css, |root| {
+            let chain = root.hit_test(5.0, 15.0);
+            assert_eq!(chain.len(), 2);
+            assert_eq!(chain[1].dimensions.content.y, 10.0);
+            assert!(root.hit_test(5.0, 500.0).is_empty());
+        });
+    }
+
+    #[test]
+    fn to_rect_tree_names_elements() {
+        let css = "div { display: block; width: 100px; padding: 10px; } \
+                   p { display: block; height: 20px; margin: 5px; }";
+        with_layout("<div><p></p></div>", css, |root| {
+            let tree = root.to_rect_tree();
+            assert_eq!(tree.tag_name.as_deref(), Some("div"));
+            let div = tree.border;
+            assert_eq!(
+                (div.x, div.y, div.width, div.height),
+                (0.0, 0.0, 120.0, 50.0)
+            );
+            let p = &tree.children[0];
+            assert_eq!(p.tag_name.as_deref(), Some("p"));
+            let content = p.content;
+            assert_eq!(
+                (content.x, content.y, content.width, content.height),
+                (15.0, 15.0, 90.0, 20.0)
+            );
+        });
+    }
+
+    #[test]
+    fn aspect_ratio_sizes_height_from_width() {
+        let css = "div { display: block; width: 200px; aspect-ratio: 2 / 1; }";
+        with_layout("<div></div>", css, |root| {
+            assert_eq!(root.dimensions.content.height, 100.0);
+        });
+    }
+
+    #[test]
+    fn auto_width_fills_the_container_less_margins() {
+        let css = "div { display: block; margin-left: 30px; margin-right: 20px; padding: 5px; }";
+        with_layout("<div></div>", css, |root| {
+            assert_eq!(root.dimensions.content.width, 800.0 - 30.0 - 20.0 - 10.0);
+            assert_eq!(root.dimensions.content.x, 35.0);
+        });
+    }
+
+    #[test]
+    fn auto_height_fits_the_content() {
+        let css = "div { display: block; } p { display: block; height: 25px; margin-bottom: 5px; }";
+        with_layout("<div><p></p><p></p></div>", css, |root| {
+            assert_eq!(root.dimensions.content.height, 60.0);
+        });
+    }
+
+    /// Width of the first run of text in a layout tree
+    fn text_width(layout_box: &LayoutBox) -> Option<f32> {
+        match layout_box.fragments.first() {
+            Some(fragment) => Some(fragment.rect.width),
+            None => layout_box.children.iter().find_map(text_width),
+        }
+    }
+
+    /// Text and position of every line fragment in a layout tree, in order
+    fn fragment_positions(layout_box: &LayoutBox) -> Vec<(String, f32, f32)> {
+        let mut fragments: Vec<(String, f32, f32)> = layout_box
+            .fragments
+            .iter()
+            .map(|f| (f.text.clone(), f.rect.x, f.rect.y))
+            .collect();
+        fragments.extend(layout_box.children.iter().flat_map(fragment_positions));
+        fragments
+    }
+
+    #[test]
+    fn nbsp_is_not_collapsed_or_broken_at() {
+        let css = "div { display: block; }";
+        with_layout("<div>a&nbsp;&nbsp;b c</div>", css, |root| {
+            let text = "a\u{a0}\u{a0}b c".to_string();
+            assert_eq!(fragment_positions(root), vec![(text, 0.0, 0.0)]);
+        });
+    }
+
+    #[test]
+    fn letter_spacing_goes_between_characters() {
+        let width = |css: &str| {
+            let mut width = None;
+            with_layout("<div>hello</div>", css, |root| width = text_width(root));
+            width.unwrap()
+        };
+        let plain = width("div { display: block; }");
+        let spaced = width("div { display: block; letter-spacing: 2px; }");
+        assert_eq!(spaced - plain, 2.0 * 4.0);
+    }
+
+    #[test]
+    fn letter_and_word_spacing_apply_to_preformatted_text() {
+        let css = "div { display: block; white-space: pre; letter-spacing: 2px; \
+                   word-spacing: 3px; }";
+        with_layout("<div>ab  c</div>", css, |root| {
+            assert_eq!(fragment_positions(root)[0].0, "ab  c");
+            // Five characters, four gaps of letter spacing, and two spaces
+            let expected = 5.0 * CHAR_WIDTH + 4.0 * 2.0 + 2.0 * 3.0;
+            assert_eq!(text_width(root), Some(expected));
+        });
+    }
+
+    #[test]
+    fn vertical_padding_percentages_are_of_the_container_width() {
+        let css = "div { display: block; padding-top: 10%; }";
+        with_layout("<div></div>", css, |root| {
+            assert_eq!(root.dimensions.padding.top, 80.0)
+        });
+    }
+
+    #[test]
+    fn table_cells_are_sized_by_their_content() {
+        assert_eq!(
+            table_cell_widths(&[(10.0, 30.0), (10.0, 10.0)], 80.0),
+            vec![60.0, 20.0]
+        );
+        assert_eq!(
+            table_cell_widths(&[(10.0, 30.0), (10.0, 10.0)], 30.0),
+            vec![20.0, 10.0]
+        );
+        assert_eq!(
+            table_cell_widths(&[(10.0, 30.0), (10.0, 10.0)], 5.0),
+            vec![10.0, 10.0]
+        );
+    }
+
+    #[test]
+    fn vertical_align_middle_centers_a_short_cell_in_a_tall_row() {
+        let css =
+            "td { vertical-align: middle; } .tall { height: 100px; } .short { height: 20px; }";
+        let html = r#"<table><tr><td><div class="tall"></div></td><td><div class="short"></div></td></tr></table>"#;
+        with_layout(html, css, |root| {
+            let row = &root.children[0];
+            assert_eq!(row.dimensions.content.height, 100.0);
+            let short = bounding_rect(root, ".short").unwrap();
+            assert_eq!(short.y - row.dimensions.content.y, 40.0);
+        });
+    }
+
+    #[test]
+    fn scroll_offsets_are_clamped_to_the_content() {
+        let css = "div { display: block; height: 50px; overflow: scroll; } \
+                   p { display: block; height: 200px; }";
+        with_layout("<div><p></p></div>", css, |root| {
+            root.scroll_by(0.0, 1000.0);
+            assert_eq!(root.scroll.unwrap().offset_y, 150.0);
+            root.scroll_by(0.0, -40.0);
+            assert_eq!(root.scroll.unwrap().offset_y, 110.0);
+            root.scroll_by(-10.0, -1000.0);
+            let scroll = root.scroll.unwrap();
+            assert_eq!((scroll.offset_x, scroll.offset_y), (0.0, 0.0));
+        });
+    }
+
+    #[test]
+    fn list_items_are_blocks_with_a_marker_to_their_left() {
+        let css = "ol { display: block; } li { display: list-item; height: 20px; }";
+        with_layout("<ol><li>a</li><li>b</li></ol>", css, |root| {
+            let item = &root.children[1];
+            assert!(item.get_style_node().display() == Display::ListItem);
+            assert_eq!(item.dimensions.content.y, 20.0);
+            let marker = item.marker.as_ref().unwrap();
+            assert_eq!(marker.text, "2.");
+            assert_eq!(marker.rect.x, 40.0 - 3.0 * CHAR_WIDTH);
+            assert_eq!(marker.rect.y, 20.0);
+        });
+    }
+
+    #[test]
+    fn wbr_lets_a_long_word_break_there() {
+        let css = "div { display: block; width: 40px; }";
+        with_layout("<div>aaaa<wbr>bbbb</div>", css, |root| {
+            let lines: Vec<(String, f32)> = fragment_positions(root)
+                .into_iter()
+                .map(|(text, x, _)| (text, x))
+                .collect();
+            assert_eq!(
+                lines,
+                vec![("aaaa".to_string(), 0.0), ("bbbb".to_string(), 0.0)]
+            );
+        });
+    }
+
+    #[test]
+    fn a_word_split_across_elements_wraps_as_a_whole() {
+        let css = "div { display: block; width: 40px; }";
+        with_layout("<div>xx a<b>bc</b></div>", css, |root| {
+            // `abc` doesn't fit after `xx`, so it wraps at the space before
+            // `a` rather than breaking between `a` and `bc`
+            let fragments = fragment_positions(root);
+            assert_eq!(
+                fragments,
+                vec![
+                    ("xx".to_string(), 0.0, 0.0),
+                    ("a".to_string(), 0.0, LINE_HEIGHT),
+                    ("bc".to_string(), CHAR_WIDTH, LINE_HEIGHT)
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn whitespace_at_the_edges_of_a_block_is_dropped() {
+        let css = "div { display: block; padding-left: 10px; }";
+        with_layout("<div>   hello   </div>", css, |root| {
+            let content = root.dimensions.content;
+            let fragments = fragment_positions(root);
+            assert_eq!(fragments, vec![("hello".to_string(), content.x, content.y)]);
+            assert_eq!(text_width(root), Some(5.0 * CHAR_WIDTH));
+        });
+    }
+
+    #[test]
+    fn spaces_around_an_inline_element_collapse_to_one() {
+        let css = "div { display: block; }";
+        with_layout("<div>a <b>b</b> c</div>", css, |root| {
+            assert_eq!(
+                fragment_positions(root),
+                vec![
+                    ("a".to_string(), 0.0, 0.0),
+                    ("b".to_string(), 2.0 * CHAR_WIDTH, 0.0),
+                    ("c".to_string(), 4.0 * CHAR_WIDTH, 0.0)
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn border_width_in_ems_resolves_against_the_font_size() {
+        let css = "div { display: block; font-size: 16px; border-width: 0.5em; }";
+        with_layout("<div></div>", css, |root| {
+            let border = root.dimensions.border;
+            assert_eq!(
+                (border.left, border.right, border.top, border.bottom),
+                (8.0, 8.0, 8.0, 8.0)
+            );
+        });
+    }
+
+    #[test]
+    fn text_inputs_default_to_a_bordered_box() {
+        let css = "div { display: block; }";
+        with_layout(r#"<div><input type="text"></div>"#, css, |root| {
+            // 20 characters by one line, inside 2px of padding and a 1px border
+            let rect = bounding_rect(root, "input").unwrap();
+            assert_eq!((rect.width, rect.height), (166.0, 20.0));
+            let input = query_selector(root, "input").unwrap();
+            assert_eq!(input.dimensions.border.top, 1.0);
+        });
+    }
+
+    #[test]
+    fn top_percentages_are_of_the_containing_block_height() {
+        let css = "div { display: block; position: relative; height: 400px; } \
+                   p { display: block; position: absolute; top: 50%; height: 10px; }";
+        with_layout("<div><p></p></div>", css, |root| {
+            assert_eq!(root.children[0].dimensions.content.y, 200.0);
+        });
+    }
+
+    #[test]
+    fn tabs_in_preformatted_text_advance_to_the_next_stop() {
+        let css = "div { display: block; white-space: pre; } .narrow { tab-size: 4; }";
+        with_layout("<div>a\tb\n12345678\tc</div>", css, |root| {
+            let fragments = fragment_positions(root);
+            assert_eq!(fragments[0].0, format!("a{}b", " ".repeat(7)));
+            assert_eq!(fragments[1].0, format!("12345678{}c", " ".repeat(8)));
+        });
+        with_layout("<div class=narrow>ab\tc</div>", css, |root| {
+            assert_eq!(fragment_positions(root)[0].0, "ab  c");
+            assert_eq!(text_width(root), Some(5.0 * CHAR_WIDTH));
+        });
+    }
+
+    #[test]
+    fn text_transform_uppercase_changes_the_laid_out_text() {
+        let css = "div { display: block; text-transform: uppercase; }";
+        with_layout("<div>hello world</div>", css, |root| {
+            assert_eq!(fragment_positions(root)[0].0, "HELLO WORLD");
+        });
+    }
+
+    #[test]
+    fn break_word_splits_a_word_too_long_for_the_line() {
+        let css = "div { display: block; width: 40px; } .wrap { overflow-wrap: break-word; }";
+        with_layout("<div class=wrap>abcdefghijkl</div>", css, |root| {
+            assert_eq!(
+                fragment_positions(root),
+                vec![
+                    ("abcde".to_string(), 0.0, 0.0),
+                    ("fghij".to_string(), 0.0, LINE_HEIGHT),
+                    ("kl".to_string(), 0.0, 2.0 * LINE_HEIGHT)
+                ]
+            );
+        });
+        // Without it the word overflows the line instead
+        with_layout("<div>abcdefghijkl</div>", css, |root| {
+            assert_eq!(
+                fragment_positions(root),
+                vec![("abcdefghijkl".to_string(), 0.0, 0.0)]
+            );
+        });
+    }
+
+    #[test]
+    fn max_content_width_fits_the_unwrapped_text() {
+        let css = "div { display: block; } .fit { width: max-content; } \
+                   .min { width: min-content; }";
+        with_layout("<div><div class=fit>one three</div></div>", css, |root| {
+            let fit = &root.children[0];
+            assert_eq!(fit.dimensions.content.width, 9.0 * CHAR_WIDTH);
+            assert_eq!(fragment_positions(fit).len(), 1);
+        });
+        with_layout("<div><div class=min>one three</div></div>", css, |root| {
+            assert_eq!(root.children[0].dimensions.content.width, 5.0 * CHAR_WIDTH);
+        });
+    }
+
+    #[test]
+    fn column_flex_items_are_centered_across_the_container() {
+        let css = "div { display: flex; flex-direction: column; align-items: center; \
+                   width: 100px; } p { display: block; width: 20px; height: 10px; } \
+                   .wide { width: 60px; }";
+        with_layout("<div><p></p><p class=wide></p></div>", css, |root| {
+            let items: Vec<(f32, f32)> = root
+                .children
+                .iter()
+                .map(|item| (item.dimensions.content.x, item.dimensions.content.y))
+                .collect();
+            assert_eq!(items, vec![(40.0, 0.0), (20.0, 10.0)]);
+        });
+    }
+
+    #[test]
+    fn text_indent_shifts_only_the_first_line() {
+        let css = "div { display: block; width: 80px; text-indent: 16px; }";
+        with_layout("<div>aaaa bbbb cccc</div>", css, |root| {
+            assert_eq!(
+                fragment_positions(root),
+                vec![
+                    ("aaaa".to_string(), 16.0, 0.0),
+                    ("bbbb cccc".to_string(), 0.0, LINE_HEIGHT)
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn hr_has_a_top_border_between_vertical_margins() {
+        let css = "div { display: block; } p { display: block; height: 10px; }";
+        with_layout("<div><p></p><hr><p></p></div>", css, |root| {
+            let hr = &root.children[1].dimensions;
+            assert_eq!((hr.margin.top, hr.margin.bottom), (8.0, 8.0));
+            assert_eq!(
+                (
+                    hr.border.top,
+                    hr.border.bottom,
+                    hr.border.left,
+                    hr.border.right
+                ),
+                (1.0, 0.0, 0.0, 0.0)
+            );
+            assert_eq!(hr.border_box().y, 18.0);
+            assert_eq!(root.children[2].dimensions.content.y, 27.0);
+        });
+    }
+
+    #[test]
+    fn mixed_font_sizes_share_a_baseline() {
+        let css = "div { display: block; } span { font-size: 32px; }";
+        with_layout("<div>a <span>B</span> c</div>", css, |root| {
+            let tops: Vec<(String, f32)> = fragment_positions(root)
+                .into_iter()
+                .map(|(text, _, y)| (text, y))
+                .collect();
+            // The line's baseline is set by the larger text, so the smaller
+            // text moves down by the difference in their ascents
+            assert_eq!(
+                tops,
+                vec![
+                    ("a".to_string(), BASELINE),
+                    ("B".to_string(), 0.0),
+                    ("c".to_string(), BASELINE)
+                ]
+            );
+            assert_eq!(root.dimensions.content.height, 2.0 * LINE_HEIGHT);
+        });
+    }
+}
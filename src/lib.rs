@@ -0,0 +1,14 @@
+pub mod css;
+pub mod dom;
+pub mod font;
+pub mod golden;
+pub mod gui;
+pub mod html;
+pub mod layout;
+pub mod network;
+pub mod painting;
+pub mod png_output;
+pub mod ppm;
+pub mod style;
+pub mod svg;
+pub mod ua;
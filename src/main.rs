@@ -1,26 +1,114 @@
-mod css;
-mod dom;
-mod gui;
-mod html;
-mod layout;
-mod network;
-mod painting;
-mod style;
-
-use layout::Dimensions;
+use browser_engine::layout::Dimensions;
+use browser_engine::{
+    css, dom, font, golden, gui, html, layout, network, painting, style, svg, ua,
+};
+use std::collections::HashSet;
 use std::env;
 use std::fs;
+use std::io;
+
+/// Pixels scrolled per unit of mouse wheel movement
+const SCROLL_STEP: f32 = 20.0;
+
+/// How far each color channel may drift from a golden image
+const GOLDEN_TOLERANCE: u8 = 2;
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    
-    let (html, css, base_url) = if args.len() > 1 {
+    let mut args: Vec<String> = env::args().collect();
+
+    // Outline every layout box on top of the page
+    let debug_layout = args.iter().any(|arg| arg == "--debug-layout");
+    args.retain(|arg| arg != "--debug-layout");
+
+    // Ask pages (and the default styles) for dark colors
+    let color_scheme = if args.iter().any(|arg| arg == "--dark") {
+        style::ColorScheme::Dark
+    } else {
+        style::ColorScheme::Light
+    };
+    args.retain(|arg| arg != "--dark");
+
+    // A local stylesheet applied as the user origin, above the page's
+    // normal rules but beneath its `!important` ones
+    let mut user_stylesheet = None;
+    if let Some(i) = args.iter().position(|arg| arg == "--user-css") {
+        let Some(path) = args.get(i + 1).cloned() else {
+            eprintln!("--user-css needs a path");
+            std::process::exit(1);
+        };
+        args.drain(i..i + 2);
+        match fs::read_to_string(&path) {
+            Ok(css) => user_stylesheet = Some(css::Parser::parse(css)),
+            Err(e) => eprintln!("Unable to read user stylesheet {}: {}", path, e),
+        }
+    }
+
+    // Previously visited URLs, one per line, for styling `:visited` links
+    let mut visited = HashSet::new();
+    if let Some(i) = args.iter().position(|arg| arg == "--history") {
+        let Some(path) = args.get(i + 1).cloned() else {
+            eprintln!("--history needs a path");
+            std::process::exit(1);
+        };
+        args.drain(i..i + 2);
+        match fs::read_to_string(&path) {
+            Ok(history) => visited.extend(
+                history
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(String::from),
+            ),
+            Err(e) => eprintln!("Unable to read history {}: {}", path, e),
+        }
+    }
+
+    // URL prefixes the browser refuses to load, e.g. `--block ftp:`
+    let mut blocked = Vec::new();
+    while let Some(i) = args.iter().position(|arg| arg == "--block") {
+        let Some(prefix) = args.get(i + 1).cloned() else {
+            eprintln!("--block needs a URL prefix");
+            std::process::exit(1);
+        };
+        args.drain(i..i + 2);
+        blocked.push(prefix);
+    }
+
+    // A font file to paint text with instead of the built-in one
+    let mut font = font::Font::embedded();
+    if let Some(i) = args.iter().position(|arg| arg == "--font") {
+        let Some(path) = args.get(i + 1).cloned() else {
+            eprintln!("--font needs a path");
+            std::process::exit(1);
+        };
+        args.drain(i..i + 2);
+        match font::Font::load(&path) {
+            Ok(loaded) => font = loaded,
+            Err(e) => eprintln!("Unable to load font {}: {}", path, e),
+        }
+    }
+
+    // Regression check: render a fixture and compare it with a golden image
+    if let [_, flag, html_path, css_path, golden_path] = args.as_slice() {
+        if flag == "--check-golden" {
+            match golden::check(html_path, css_path, golden_path, GOLDEN_TOLERANCE) {
+                Ok(()) => println!("✓ {} matches {}", html_path, golden_path),
+                Err(e) => {
+                    eprintln!("Golden image mismatch: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+    }
+
+    let (html, mut stylesheets, base_url) = if args.len() > 1 {
         // URL provided as command-line argument
         let url = &args[1];
         println!("Loading URL: {}", url);
-        
-        match fetch_from_url(url) {
-            Ok((html, css)) => (html, css, Some(url.clone())),
+
+        match fetch_from_url(url, &network::Navigator::blocking(blocked)) {
+            Ok((html, stylesheets, url)) => (html, stylesheets, Some(url)),
             Err(e) => {
                 eprintln!("Error fetching URL: {}", e);
                 eprintln!("Falling back to local examples...");
@@ -30,86 +118,113 @@ fn main() {
     } else {
         // No URL provided, use local files
         println!("No URL provided. Using local example files.");
-        println!("Usage: {} <url>", args.get(0).unwrap_or(&String::from("browser-engine")));
-        println!("Example: {} https://example.com\n", args.get(0).unwrap_or(&String::from("browser-engine")));
+        println!("Usage: {} [--debug-layout] [--dark] [--user-css <path>] [--history <path>] [--block <prefix>]... [--font <path>] <url>", args.first().unwrap_or(&String::from("browser-engine")));
+        println!(
+            "Example: {} https://example.com\n",
+            args.first().unwrap_or(&String::from("browser-engine"))
+        );
         load_local_files()
     };
 
-    let _ = base_url; // Suppress unused warning for now
-
     println!("Parsing HTML...");
     let root_node = html::Parser::parse(html);
     println!("DOM tree:");
-    println!("{:#?}", root_node);
+    print!("{}", root_node.pretty_print());
+
+    // The page being shown counts as visited too
+    visited.extend(base_url.clone());
 
-    println!("\nParsing CSS...");
-    let stylesheet = css::Parser::parse(css);
-    println!("Stylesheet:");
-    println!("{:#?}", stylesheet);
+    // `<style>` elements come after the linked stylesheets
+    stylesheets.extend(network::embedded_stylesheets(
+        &root_node,
+        base_url.as_deref(),
+    ));
+    let author: Vec<&css::Stylesheet> = stylesheets.iter().collect();
+    println!("\nStylesheets:");
+    println!("{:#?}", stylesheets);
 
     println!("\nBuilding style tree...");
-    let style_root = style::style_tree(&root_node, &stylesheet);
+    let ua_stylesheet = ua::stylesheet();
+    let mut viewport = Dimensions::default();
+    viewport.content.width = 800.0;
+    viewport.content.height = 600.0;
+
+    let element_defaults = style::ElementDefaults::default();
+    let cascade = style::CascadeContext {
+        user_agent: &ua_stylesheet,
+        user: user_stylesheet.as_ref(),
+        author: &author,
+        element_defaults: &element_defaults,
+        hovered: None,
+        viewport_width: viewport.content.width,
+        color_scheme,
+        visited: Some(&visited),
+        base_url: base_url.as_deref(),
+    };
+    let style_root = style::style_tree_with_cascade(&root_node, &cascade);
     println!("Style tree:");
     println!("{:#?}", style_root);
 
     println!("\nPerforming layout...");
-    let mut viewport = Dimensions::default();
-    viewport.content.width = 800.0;
-    viewport.content.height = 600.0;
 
     let layout_root = layout::layout_tree(&style_root, viewport);
     println!("Layout tree:");
     println!("{:#?}", layout_root);
 
     println!("\nBuilding display list...");
-    let display_list = painting::build_display_list(&layout_root);
+    let mut display_list = painting::build_display_list(&layout_root);
+    if debug_layout {
+        display_list.extend(painting::build_debug_overlay(&layout_root, true));
+    }
     println!("Display list:");
     println!("{:#?}", display_list);
 
+    println!("\nLoading images...");
+    let images = load_images(&display_list);
+
     println!("\nRendering to canvas...");
     let mut canvas = painting::Canvas::new(800, 600);
+    canvas.set_font(font.clone());
+    add_images(&mut canvas, &images);
     canvas.paint(&display_list);
 
     println!("✓ Rendering complete!");
     println!("Canvas contains {} pixels", canvas.pixels.len());
 
     // Save as PPM image format (simple text-based format) for backup
-    let output = format!(
-        "P3\n{} {}\n255\n{}",
-        canvas.width,
-        canvas.height,
-        canvas
-            .pixels
-            .iter()
-            .map(|color| format!("{} {} {}", color.r, color.g, color.b))
-            .collect::<Vec<_>>()
-            .join(" ")
-    );
-
-    fs::write("output.ppm", output).expect("Unable to write output file");
+    let ppm_file = fs::File::create("output.ppm").expect("Unable to write output file");
+    canvas
+        .write_ppm(io::BufWriter::new(ppm_file))
+        .expect("Unable to write output file");
     println!("Output saved to output.ppm");
 
+    // Also save a vector rendering of the same display list
+    fs::write("output.svg", svg::build_svg(&display_list, 800, 600))
+        .expect("Unable to write output file");
+    println!("Output saved to output.svg");
+
     // Display in GUI window
     println!("\nOpening GUI window...");
     match gui::BrowserWindow::new(800, 600) {
         Ok(mut window) => {
-            println!("✓ GUI window created! Press ESC to close.");
-            
+            println!("✓ GUI window created! Press ESC to close, Ctrl+F to find.");
+
             // Display the canvas
             if let Err(e) = window.display(&canvas) {
                 eprintln!("Error displaying canvas: {:?}", e);
                 return;
             }
 
-            // Keep the window open until the user closes it
-            while window.is_open() {
-                // Update the window (this checks for events and keeps it responsive)
-                if let Err(e) = window.display(&canvas) {
-                    eprintln!("Error updating window: {:?}", e);
-                    break;
-                }
-            }
-            
+            run_window(
+                &mut window,
+                &root_node,
+                &cascade,
+                &font,
+                &images,
+                viewport,
+                debug_layout,
+            );
+
             println!("Window closed.");
         }
         Err(e) => {
@@ -119,8 +234,138 @@ fn main() {
     }
 }
 
+/// Keep the window open and interactive until the user closes it. Styles are
+/// recomputed whenever the pointer moves to another element, so `:hover`
+/// rules apply.
+fn run_window(
+    window: &mut gui::BrowserWindow,
+    root_node: &dom::Node,
+    cascade: &style::CascadeContext,
+    font: &font::Font,
+    images: &[(String, painting::Canvas)],
+    viewport: Dimensions,
+    debug_layout: bool,
+) {
+    let mut hovered: Option<&dom::Node> = None;
+    let mut scroll_states = Vec::new();
+    loop {
+        let cascade = style::CascadeContext {
+            hovered,
+            ..*cascade
+        };
+        let style_root = style::style_tree_with_cascade(root_node, &cascade);
+        let mut layout_root = layout::layout_tree(&style_root, viewport);
+        layout_root.restore_scroll_states(&mut scroll_states.into_iter());
+        let mut canvas = render(
+            &layout_root,
+            window.find_query(),
+            font,
+            images,
+            debug_layout,
+        );
+
+        // Repaint in place until the hovered element changes
+        loop {
+            if !window.is_open() {
+                return;
+            }
+            let mut repaint = window.poll_find();
+
+            // Route wheel events to the scroll container under the cursor
+            if let (Some((dx, dy)), Some((x, y))) = (window.scroll_wheel(), window.mouse_pos()) {
+                if let Some(scroller) = layout_root.scroll_container_at(x, y) {
+                    scroller.scroll_by(-dx * SCROLL_STEP, -dy * SCROLL_STEP);
+                    repaint = true;
+                }
+            }
+
+            if repaint {
+                canvas = render(
+                    &layout_root,
+                    window.find_query(),
+                    font,
+                    images,
+                    debug_layout,
+                );
+            }
+
+            // Update the window (this checks for events and keeps it responsive)
+            if let Err(e) = window.display(&canvas) {
+                eprintln!("Error updating window: {:?}", e);
+                return;
+            }
+
+            // The path from the root to the element under the pointer
+            let pointed = window
+                .mouse_pos()
+                .and_then(|(x, y)| layout_root.element_at(x, y))
+                .and_then(|node| root_node.path_to(node))
+                .unwrap_or_default();
+            let pointed_node = pointed.last().copied();
+            if pointed_node.map(|node| node as *const _) != hovered.map(|node| node as *const _) {
+                hovered = pointed_node;
+                window.show_link(link_href(&pointed));
+                scroll_states = layout_root.scroll_states();
+                break;
+            }
+        }
+    }
+}
+
+/// Paint a layout tree in `font` with the loaded `images`, highlighting
+/// matches for the find-in-page query and optionally outlining its boxes
+fn render(
+    layout_root: &layout::LayoutBox,
+    find_query: Option<&str>,
+    font: &font::Font,
+    images: &[(String, painting::Canvas)],
+    debug_layout: bool,
+) -> painting::Canvas {
+    let mut display_list = painting::build_display_list(layout_root);
+    if let Some(query) = find_query {
+        display_list = painting::highlight_matches(&display_list, query);
+    }
+    if debug_layout {
+        display_list.extend(painting::build_debug_overlay(layout_root, true));
+    }
+    let mut canvas = painting::Canvas::new(800, 600);
+    canvas.set_font(font.clone());
+    add_images(&mut canvas, images);
+    canvas.paint(&display_list);
+    canvas
+}
+
+/// Load the background images a display list paints. Images that can't be
+/// loaded are left out, so their backgrounds aren't painted.
+fn load_images(display_list: &painting::DisplayList) -> Vec<(String, painting::Canvas)> {
+    let mut images = Vec::new();
+    for url in painting::image_urls(display_list) {
+        match network::load_image(&url) {
+            Ok(image) => images.push((url, image)),
+            Err(e) => eprintln!("Warning: Could not load image {}: {}", url, e),
+        }
+    }
+    images
+}
+
+fn add_images(canvas: &mut painting::Canvas, images: &[(String, painting::Canvas)]) {
+    for (url, image) in images {
+        canvas.add_image(url, image.clone());
+    }
+}
+
+/// The `href` of the innermost link on a path of nodes from the root, if any
+fn link_href<'n>(path: &[&'n dom::Node]) -> Option<&'n str> {
+    path.iter().rev().find_map(|node| match node.node_type {
+        dom::NodeType::Element(ref elem) if elem.tag_name == "a" => {
+            elem.get_attribute("href").map(String::as_str)
+        }
+        _ => None,
+    })
+}
+
 /// Load HTML and CSS from local files
-fn load_local_files() -> (String, String, Option<String>) {
+fn load_local_files() -> (String, Vec<css::Stylesheet>, Option<String>) {
     let html = fs::read_to_string("examples/test.html").unwrap_or_else(|_| {
         String::from("<html><body><div class='box'>Hello, Browser!</div></body></html>")
     });
@@ -132,22 +377,41 @@ fn load_local_files() -> (String, String, Option<String>) {
         )
     });
 
-    (html, css, None)
+    // Imports are relative to the stylesheet's own location
+    let file_url = |path: &str| {
+        fs::canonicalize(path)
+            .ok()
+            .and_then(|path| url::Url::from_file_path(path).ok())
+            .map(String::from)
+    };
+    let stylesheet = match file_url("examples/test.css") {
+        Some(base) => network::load_stylesheet(css, &base, &mut network::read_file_url),
+        None => css::Parser::parse(css),
+    };
+
+    (html, vec![stylesheet], file_url("examples/test.html"))
 }
 
-/// Fetch HTML and CSS from a URL
-fn fetch_from_url(url: &str) -> Result<(String, String), Box<dyn std::error::Error>> {
-    let fetcher = network::WebFetcher::new()?;
-    
-    // Fetch the HTML
-    let html = fetcher.fetch_html(url)?;
-    
+/// Navigate to a URL and fetch its HTML and its linked stylesheets, in
+/// document order, returning the URL actually loaded along with them
+fn fetch_from_url(
+    url: &str,
+    navigator: &network::Navigator,
+) -> Result<(String, Vec<css::Stylesheet>, String), Box<dyn std::error::Error>> {
+    // Keep session cookies so stylesheets load like the page did
+    let fetcher = network::WebFetcher::builder().cookie_store(true).build()?;
+
+    // Fetch the HTML, unless the navigator blocks or redirects it
+    let (url, html) = navigator.navigate(url, |url| fetcher.fetch_html(url))?;
+    let url = url.as_str();
+
     // Extract CSS links from the HTML
     let css_links = network::extract_css_links(&html);
-    
-    // Fetch all CSS files and combine them
-    let mut combined_css = String::new();
-    
+
+    // Fetch each CSS file (and its imports) as its own sheet, so a later
+    // sheet wins ties with an earlier one
+    let mut stylesheets = Vec::new();
+
     for css_link in css_links {
         // Resolve relative URLs
         let css_url = if css_link.starts_with("http://") || css_link.starts_with("https://") {
@@ -161,22 +425,24 @@ fn fetch_from_url(url: &str) -> Result<(String, String), Box<dyn std::error::Err
                 }
             }
         };
-        
+
         match fetcher.fetch_css(&css_url) {
             Ok(css) => {
-                combined_css.push_str(&css);
-                combined_css.push('\n');
+                let mut load = |url: &str| fetcher.fetch_css(url).ok();
+                stylesheets.push(network::load_stylesheet(css, &css_url, &mut load));
             }
             Err(e) => {
                 eprintln!("Warning: Could not fetch CSS from {}: {}", css_url, e);
             }
         }
     }
-    
+
     // If no CSS was found, use a basic default
-    if combined_css.is_empty() {
-        combined_css = String::from("body { background: #ffffff; margin: 20px; }");
+    if stylesheets.is_empty() {
+        stylesheets.push(css::Parser::parse(String::from(
+            "body { background: #ffffff; margin: 20px; }",
+        )));
     }
-    
-    Ok((html, combined_css))
+
+    Ok((html, stylesheets, url.to_string()))
 }
@@ -1,3 +1,7 @@
+use crate::css::{self, Declaration, Stylesheet, Value};
+use crate::dom::{Node, NodeType};
+use crate::painting::Canvas;
+use crate::ppm;
 use reqwest::blocking::Client;
 use std::error::Error;
 use url::Url;
@@ -6,24 +10,52 @@ pub struct WebFetcher {
     client: Client,
 }
 
-impl WebFetcher {
-    pub fn new() -> Result<Self, Box<dyn Error>> {
+/// Options for creating a `WebFetcher`
+#[derive(Default)]
+pub struct WebFetcherBuilder {
+    cookie_store: bool,
+}
+
+impl WebFetcherBuilder {
+    /// Keep cookies from `Set-Cookie` responses and send them with later
+    /// requests from the same fetcher
+    pub fn cookie_store(mut self, enabled: bool) -> Self {
+        self.cookie_store = enabled;
+        self
+    }
+
+    pub fn build(self) -> Result<WebFetcher, Box<dyn Error>> {
         let client = Client::builder()
             .user_agent("BrowserEngine/0.1")
             .timeout(std::time::Duration::from_secs(10))
+            .cookie_store(self.cookie_store)
+            // Decode compressed responses rather than relying on defaults
+            .gzip(true)
+            .brotli(true)
             .build()?;
         Ok(WebFetcher { client })
     }
+}
+
+impl WebFetcher {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        Self::builder().build()
+    }
+
+    /// Configure a fetcher, e.g. to keep a cookie jar for the session
+    pub fn builder() -> WebFetcherBuilder {
+        WebFetcherBuilder::default()
+    }
 
     /// Fetch HTML content from a URL
     pub fn fetch_html(&self, url: &str) -> Result<String, Box<dyn Error>> {
         println!("Fetching HTML from: {}", url);
         let response = self.client.get(url).send()?;
-        
+
         if !response.status().is_success() {
             return Err(format!("HTTP error: {}", response.status()).into());
         }
-        
+
         let html = response.text()?;
         Ok(html)
     }
@@ -32,15 +64,26 @@ impl WebFetcher {
     pub fn fetch_css(&self, url: &str) -> Result<String, Box<dyn Error>> {
         println!("Fetching CSS from: {}", url);
         let response = self.client.get(url).send()?;
-        
+
         if !response.status().is_success() {
             return Err(format!("HTTP error: {}", response.status()).into());
         }
-        
+
         let css = response.text()?;
         Ok(css)
     }
 
+    /// Fetch a binary resource, such as an image, as raw (decompressed) bytes
+    pub fn fetch_bytes(&self, url: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+        let response = self.client.get(url).send()?;
+
+        if !response.status().is_success() {
+            return Err(format!("HTTP error: {}", response.status()).into());
+        }
+
+        Ok(response.bytes()?.to_vec())
+    }
+
     /// Resolve a relative URL against a base URL
     pub fn resolve_url(base: &str, relative: &str) -> Result<String, Box<dyn Error>> {
         let base_url = Url::parse(base)?;
@@ -49,15 +92,196 @@ impl WebFetcher {
     }
 }
 
+/// What to do about a request to navigate to a URL
+#[derive(Clone, Debug, PartialEq)]
+pub enum NavigationDecision {
+    Allow,
+    /// Don't load anything
+    Deny,
+    /// Load this URL instead, e.g. to map a custom `app://` scheme
+    Rewrite(String),
+}
+
+/// Loads pages being navigated to, first asking a hook supplied by the
+/// embedder whether and where to go
+pub struct Navigator<'h> {
+    hook: Box<dyn Fn(&str) -> NavigationDecision + 'h>,
+}
+
+impl<'h> Navigator<'h> {
+    pub fn new(hook: impl Fn(&str) -> NavigationDecision + 'h) -> Self {
+        Navigator {
+            hook: Box::new(hook),
+        }
+    }
+
+    /// A navigator that allows every URL
+    pub fn allow_all() -> Self {
+        Navigator::new(|_| NavigationDecision::Allow)
+    }
+
+    /// A navigator that denies URLs starting with any of `prefixes`, such
+    /// as a scheme (`ftp:`) or a site (`https://ads.example/`), and allows
+    /// the rest
+    pub fn blocking(prefixes: Vec<String>) -> Self {
+        Navigator::new(move |url| {
+            if prefixes
+                .iter()
+                .any(|prefix| url.starts_with(prefix.as_str()))
+            {
+                NavigationDecision::Deny
+            } else {
+                NavigationDecision::Allow
+            }
+        })
+    }
+
+    /// Navigate to `url`, loading it with `load` unless the hook denies it.
+    /// Returns the URL actually loaded, which a rewrite may have changed,
+    /// along with its contents.
+    pub fn navigate(
+        &self,
+        url: &str,
+        load: impl FnOnce(&str) -> Result<String, Box<dyn Error>>,
+    ) -> Result<(String, String), Box<dyn Error>> {
+        let url = match (self.hook)(url) {
+            NavigationDecision::Allow => url.to_string(),
+            NavigationDecision::Deny => {
+                return Err(format!("Navigation to {} was blocked", url).into())
+            }
+            NavigationDecision::Rewrite(target) => target,
+        };
+        let contents = load(&url)?;
+        Ok((url, contents))
+    }
+}
+
+/// Parse a stylesheet and the sheets it `@import`s. Each import is resolved
+/// against the URL of the sheet containing it and fetched with `load`; its
+/// rules come before the importing sheet's, so the importer wins ties.
+/// Imports that fail to resolve or load, or that would form a cycle, are
+/// skipped, as is any sheet that fails to parse.
+pub fn load_stylesheet(
+    source: String,
+    base_url: &str,
+    load: &mut dyn FnMut(&str) -> Option<String>,
+) -> Stylesheet {
+    load_stylesheet_from(source, base_url, load, &mut vec![base_url.to_string()])
+}
+
+/// Resolve every relative `url(...)` value in a stylesheet against
+/// `base_url`: the sheet's own URL for an external stylesheet, or the
+/// document's URL for styles embedded in the page. URLs that can't be
+/// resolved are left as written.
+pub fn resolve_urls(stylesheet: &mut Stylesheet, base_url: &str) {
+    for rule in &mut stylesheet.rules {
+        resolve_declaration_urls(&mut rule.declarations, base_url);
+    }
+}
+
+/// `resolve_urls` for a bare list of declarations, such as a `style`
+/// attribute's
+pub fn resolve_declaration_urls(declarations: &mut [Declaration], base_url: &str) {
+    for declaration in declarations {
+        if let Value::Url(ref mut url) = declaration.value {
+            if let Ok(resolved) = WebFetcher::resolve_url(base_url, url) {
+                *url = resolved;
+            }
+        }
+    }
+}
+
+/// Parse a stylesheet, printing any declarations it skipped. A sheet that
+/// fails to parse is reported as coming from `origin` and skipped.
+fn parse_stylesheet(source: String, origin: &str) -> Option<Stylesheet> {
+    match css::Parser::parse_with_warnings(source) {
+        Ok((stylesheet, warnings)) => {
+            for warning in warnings {
+                eprintln!("Warning: {}", warning);
+            }
+            Some(stylesheet)
+        }
+        Err(e) => {
+            eprintln!("Warning: Skipping stylesheet {}: {}", origin, e);
+            None
+        }
+    }
+}
+
+/// `load_stylesheet`, tracking the chain of sheets being imported
+fn load_stylesheet_from(
+    source: String,
+    base_url: &str,
+    load: &mut dyn FnMut(&str) -> Option<String>,
+    importing: &mut Vec<String>,
+) -> Stylesheet {
+    let Some(mut stylesheet) = parse_stylesheet(source, base_url) else {
+        return Stylesheet {
+            rules: Vec::new(),
+            imports: Vec::new(),
+        };
+    };
+    resolve_urls(&mut stylesheet, base_url);
+    let mut rules = Vec::new();
+    for import in &stylesheet.imports {
+        let url = match WebFetcher::resolve_url(base_url, import) {
+            Ok(url) => url,
+            Err(e) => {
+                eprintln!("Warning: Could not resolve @import {}: {}", import, e);
+                continue;
+            }
+        };
+        if importing.contains(&url) {
+            eprintln!("Warning: Skipping circular @import of {}", url);
+            continue;
+        }
+        let Some(source) = load(&url) else {
+            eprintln!("Warning: Could not load @import {}", url);
+            continue;
+        };
+        importing.push(url.clone());
+        rules.extend(load_stylesheet_from(source, &url, load, importing).rules);
+        importing.pop();
+    }
+    rules.append(&mut stylesheet.rules);
+    stylesheet.rules = rules;
+    stylesheet
+}
+
+/// Read a `file:` URL from disk, for loading local stylesheets
+pub fn read_file_url(url: &str) -> Option<String> {
+    let path = Url::parse(url).ok()?.to_file_path().ok()?;
+    std::fs::read_to_string(path).ok()
+}
+
+/// Load an image for painting, from disk for a `file:` URL and fetched
+/// otherwise. Only plain-text PPM images can be decoded.
+pub fn load_image(url: &str) -> Result<Canvas, Box<dyn Error>> {
+    let parsed = Url::parse(url)?;
+    let bytes = if parsed.scheme() == "file" {
+        let path = parsed
+            .to_file_path()
+            .map_err(|_| format!("{} is not a local path", url))?;
+        std::fs::read(path)?
+    } else {
+        WebFetcher::new()?.fetch_bytes(url)?
+    };
+    Ok(ppm::decode(&String::from_utf8(bytes)?)?)
+}
+
 /// Extract CSS links from HTML content
 pub fn extract_css_links(html: &str) -> Vec<String> {
     let mut links = Vec::new();
-    
+
     // Simple extraction - look for <link rel="stylesheet" href="...">
     for line in html.lines() {
         if line.contains("rel=\"stylesheet\"") || line.contains("rel='stylesheet'") {
             if let Some(href_start) = line.find("href=\"").or_else(|| line.find("href='")) {
-                let quote = if line[href_start..].starts_with("href=\"") { '"' } else { '\'' };
+                let quote = if line[href_start..].starts_with("href=\"") {
+                    '"'
+                } else {
+                    '\''
+                };
                 let start = href_start + 6; // length of "href=\"" or "href='"
                 if let Some(href_end) = line[start..].find(quote) {
                     let url = &line[start..start + href_end];
@@ -68,7 +292,253 @@ pub fn extract_css_links(html: &str) -> Vec<String> {
             }
         }
     }
-    
+
     links
 }
 
+/// Parse the document's `<style>` elements, in document order, resolving
+/// their `url(...)` values against the document's URL. Elements whose
+/// contents fail to parse are skipped.
+pub fn embedded_stylesheets(dom: &Node, base_url: Option<&str>) -> Vec<Stylesheet> {
+    let mut sheets = Vec::new();
+    dom.walk(&mut |node, _| {
+        let NodeType::Element(ref elem) = node.node_type else {
+            return;
+        };
+        if elem.tag_name != "style" {
+            return;
+        }
+        let source: String = node
+            .children
+            .iter()
+            .filter_map(|child| match child.node_type {
+                NodeType::Text(ref text) => Some(text.as_str()),
+                NodeType::Element(_) => None,
+            })
+            .collect();
+        let Some(mut stylesheet) = parse_stylesheet(source, "in <style>") else {
+            return;
+        };
+        if let Some(base_url) = base_url {
+            resolve_urls(&mut stylesheet, base_url);
+        }
+        sheets.push(stylesheet);
+    });
+    sheets
+}
+
+/// The resolved URL of every `<a href>` in the document, in document order,
+/// for crawling. Links within the page (`#fragment` or empty) and
+/// `javascript:` URLs are skipped, as are hrefs that can't be resolved
+/// against `base_url`.
+pub fn extract_links(dom: &Node, base_url: &str) -> Vec<String> {
+    let mut links = Vec::new();
+    dom.walk(&mut |node, _| {
+        let NodeType::Element(ref elem) = node.node_type else {
+            return;
+        };
+        let href = match (elem.tag_name.as_str(), elem.get_attribute("href")) {
+            ("a", Some(href)) => href.trim(),
+            _ => return,
+        };
+        let is_script = href
+            .get(..11)
+            .is_some_and(|scheme| scheme.eq_ignore_ascii_case("javascript:"));
+        if href.is_empty() || href.starts_with('#') || is_script {
+            return;
+        }
+        if let Ok(url) = WebFetcher::resolve_url(base_url, href) {
+            links.push(url);
+        }
+    });
+    links
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html;
+
+    #[test]
+    fn extract_links_resolves_hrefs_and_skips_fragments_and_scripts() {
+        let dom = html::Parser::parse(
+            r##"<div><a href="a.html">A</a><a href="#top">Top</a><a href="javascript:go()">Go</a><a href="https://other.org/">O</a></div>"##
+                .to_string(),
+        );
+        let links = extract_links(&dom, "https://example.com/dir/page.html");
+        assert_eq!(
+            links,
+            vec!["https://example.com/dir/a.html", "https://other.org/"]
+        );
+    }
+
+    #[test]
+    fn new_fetcher_builds_with_defaults() {
+        assert!(WebFetcher::new().is_ok());
+    }
+
+    #[test]
+    fn urls_in_external_css_resolve_against_the_sheet() {
+        let css = "div { background-image: url(img/bg.ppm); }".to_string();
+        let stylesheet =
+            load_stylesheet(css, "https://cdn.example.com/css/site.css", &mut |_| None);
+        assert_eq!(
+            stylesheet.rules[0].declarations[0].value,
+            Value::Url("https://cdn.example.com/css/img/bg.ppm".to_string())
+        );
+    }
+
+    #[test]
+    fn imported_rules_come_before_the_importing_sheet() {
+        let css = "@import url(base.css); p { color: #0000ff; }".to_string();
+        let mut requested = Vec::new();
+        let stylesheet = load_stylesheet(css, "https://example.com/site.css", &mut |url| {
+            requested.push(url.to_string());
+            Some("p { color: #ff0000; } h1 { width: 5px; }".to_string())
+        });
+        assert_eq!(requested, vec!["https://example.com/base.css"]);
+        let root = html::Parser::parse("<p></p>".to_string());
+        let ua_stylesheet = crate::ua::stylesheet();
+        let styled = crate::style::style_tree(&root, &[&stylesheet], &ua_stylesheet, 800.0);
+        assert_eq!(styled.computed_values()["color"], "#0000ff");
+        assert!(stylesheet
+            .rules
+            .iter()
+            .any(|rule| rule.declarations.iter().any(|d| d.name == "width")));
+    }
+
+    #[test]
+    fn unparseable_imports_are_skipped() {
+        let css = "@import url(broken.css); p { color: #0000ff; }".to_string();
+        let stylesheet = load_stylesheet(css, "https://example.com/site.css", &mut |_| {
+            Some("p { color: red".to_string())
+        });
+        assert_eq!(stylesheet.rules.len(), 1);
+    }
+
+    #[test]
+    fn unparseable_style_elements_are_skipped() {
+        let dom = html::Parser::parse(
+            "<div><style>p { color: red</style><style>p { color: blue; }</style></div>".to_string(),
+        );
+        let sheets = embedded_stylesheets(&dom, None);
+        assert_eq!(sheets.len(), 1);
+        assert_eq!(sheets[0].rules.len(), 1);
+    }
+
+    #[test]
+    fn urls_in_style_elements_resolve_against_the_document() {
+        let dom = html::Parser::parse(
+            "<html><style>p { background-image: url(bg.ppm); }</style><p></p></html>".to_string(),
+        );
+        let sheets = embedded_stylesheets(&dom, Some("https://example.com/dir/page.html"));
+        assert_eq!(
+            sheets[0].rules[0].declarations[0].value,
+            Value::Url("https://example.com/dir/bg.ppm".to_string())
+        );
+    }
+
+    #[test]
+    fn denied_navigation_never_calls_the_loader() {
+        let navigator = Navigator::blocking(vec!["https://blocked.example/".to_string()]);
+        let loads = std::cell::Cell::new(0);
+        let load = |url: &str| {
+            loads.set(loads.get() + 1);
+            Ok(format!("<p>{}</p>", url))
+        };
+        assert!(navigator
+            .navigate("https://blocked.example/page", load)
+            .is_err());
+        assert_eq!(loads.get(), 0);
+        let (url, _) = navigator.navigate("https://example.com/", load).unwrap();
+        assert_eq!((url.as_str(), loads.get()), ("https://example.com/", 1));
+    }
+
+    /// CRC-32 (as used by gzip) of `data`
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    /// `data` gzipped as a single stored (uncompressed) deflate block
+    fn gzip_stored(data: &[u8]) -> Vec<u8> {
+        let len = data.len() as u16;
+        let mut gzip = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 0xff];
+        gzip.push(1); // final block, stored
+        gzip.extend(len.to_le_bytes());
+        gzip.extend((!len).to_le_bytes());
+        gzip.extend(data);
+        gzip.extend(crc32(data).to_le_bytes());
+        gzip.extend((data.len() as u32).to_le_bytes());
+        gzip
+    }
+
+    #[test]
+    fn fetch_bytes_decodes_gzipped_responses() {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/image.ppm", listener.local_addr().unwrap());
+        let body = b"P3\n1 1\n255\n255 0 0\n";
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).unwrap();
+            let gzip = gzip_stored(body);
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n",
+                gzip.len()
+            );
+            stream.write_all(head.as_bytes()).unwrap();
+            stream.write_all(&gzip).unwrap();
+        });
+        let fetcher = WebFetcher::builder().build().unwrap();
+        assert_eq!(fetcher.fetch_bytes(&url).unwrap(), body);
+        server.join().unwrap();
+    }
+
+    /// Whether a fetcher built with `cookie_store(enabled)` sends back the
+    /// cookie a mock server set on its first response
+    fn cookie_round_trips(enabled: bool) -> bool {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for set_cookie in ["Set-Cookie: session=abc123\r\n", ""] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 4096];
+                let read = stream.read(&mut request).unwrap();
+                requests.push(String::from_utf8_lossy(&request[..read]).to_lowercase());
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\n{}Content-Length: 2\r\nConnection: close\r\n\r\nok",
+                    set_cookie
+                );
+                stream.write_all(head.as_bytes()).unwrap();
+            }
+            requests
+        });
+        let fetcher = WebFetcher::builder().cookie_store(enabled).build().unwrap();
+        fetcher.fetch_html(&url).unwrap();
+        fetcher.fetch_html(&url).unwrap();
+        let requests = server.join().unwrap();
+        assert!(!requests[0].contains("cookie:"));
+        requests[1].contains("cookie: session=abc123")
+    }
+
+    #[test]
+    fn the_cookie_store_sends_cookies_back() {
+        assert!(cookie_round_trips(true));
+        assert!(!cookie_round_trips(false));
+    }
+}
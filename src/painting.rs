@@ -1,48 +1,489 @@
-use crate::css::{Color, Value};
-use crate::dom::NodeType;
-use crate::layout::{BoxType, LayoutBox, Rect};
+use crate::css::{Color, Unit, Value};
+use crate::font::Font;
+use crate::layout::{
+    measure_text, text_scale, BoxType, EdgeSizes, LayoutBox, Rect, TextSpacing, BASELINE,
+};
+use crate::style::{FontFace, Position, Visibility};
+use std::collections::HashMap;
 
 pub type DisplayList = Vec<DisplayCommand>;
 
-#[derive(Debug)]
+/// Distance from the bottom of a line of text up to its underline
+const UNDERLINE_OFFSET: f32 = 2.0;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum DisplayCommand {
     SolidColor(Color, Rect),
-    Text(String, Rect, Color),
+    /// A rect with elliptical corners of the given horizontal/vertical radii
+    RoundedColor(Color, Rect, (f32, f32)),
+    /// Text on one line, with the spacing it was laid out with
+    Text(String, Rect, Color, TextSpacing, FontFace),
+    /// The image at a URL as a background: placed in an area (the padding
+    /// box) by a `background-position`, clipped to it, and tiled along the
+    /// axes that repeat, as `(x, y)`
+    Image(String, Rect, Value, (bool, bool)),
+    /// Restrict painting to a rect until the matching `PopClip`
+    PushClip(Rect),
+    PopClip,
+}
+
+impl DisplayCommand {
+    /// The area this command can affect
+    fn rect(&self) -> Option<Rect> {
+        match self {
+            DisplayCommand::SolidColor(_, rect)
+            | DisplayCommand::RoundedColor(_, rect, _)
+            | DisplayCommand::Text(_, rect, _, _, _)
+            | DisplayCommand::Image(_, rect, _, _)
+            | DisplayCommand::PushClip(rect) => Some(*rect),
+            DisplayCommand::PopClip => None,
+        }
+    }
+}
+
+/// Regions that differ between two frames' display lists, so only those
+/// need repainting. Commands are matched up by a longest common
+/// subsequence, so one inserted or removed command doesn't dirty everything
+/// painted after it. Overlapping regions are merged.
+pub fn diff_display_lists(old: &DisplayList, new: &DisplayList) -> Vec<Rect> {
+    // Commands the frames share at either end need no matching
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let (old, new) = (&old[prefix..], &new[prefix..]);
+    let suffix = old
+        .iter()
+        .rev()
+        .zip(new.iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let (old, new) = (&old[..old.len() - suffix], &new[..new.len() - suffix]);
+
+    // Both where a removed command was and where an added one is need
+    // repainting
+    let (old_kept, new_kept) = common_commands(old, new);
+    let removed = old.iter().zip(old_kept).filter(|&(_, kept)| !kept);
+    let added = new.iter().zip(new_kept).filter(|&(_, kept)| !kept);
+    let mut changed: Vec<Rect> = Vec::new();
+    for rect in removed
+        .chain(added)
+        .filter_map(|(command, _)| command.rect())
+    {
+        add_dirty_rect(&mut changed, rect);
+    }
+    changed
+}
+
+/// Most cells `common_commands` fills in before giving up on matching, to
+/// bound its time and memory on very different frames
+const MAX_DIFF_CELLS: usize = 1 << 22;
+
+/// Which commands of each list are in a longest common subsequence of the
+/// two. Past `MAX_DIFF_CELLS`, none are, so everything is repainted.
+fn common_commands(old: &[DisplayCommand], new: &[DisplayCommand]) -> (Vec<bool>, Vec<bool>) {
+    let mut old_kept = vec![false; old.len()];
+    let mut new_kept = vec![false; new.len()];
+    let width = new.len() + 1;
+    if (old.len() + 1) * width > MAX_DIFF_CELLS {
+        return (old_kept, new_kept);
+    }
+    // The length of the longest common subsequence of `old[i..]` and
+    // `new[j..]`, at `i * width + j`
+    let mut lengths = vec![0u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i * width + j] = if old[i] == new[j] {
+                lengths[(i + 1) * width + j + 1] + 1
+            } else {
+                lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            old_kept[i] = true;
+            new_kept[j] = true;
+            i += 1;
+            j += 1;
+        } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+            i += 1;
+        } else {
+            j += 1;
+        }
+    }
+    (old_kept, new_kept)
+}
+
+/// Add a rect to a set of non-overlapping dirty rects, merging as needed
+fn add_dirty_rect(rects: &mut Vec<Rect>, mut rect: Rect) {
+    while let Some(i) = rects.iter().position(|other| overlaps(other, &rect)) {
+        rect = rect.union(&rects.swap_remove(i));
+    }
+    rects.push(rect);
+}
+
+fn overlaps(a: &Rect, b: &Rect) -> bool {
+    let overlap = a.intersection(b);
+    overlap.width > 0.0 && overlap.height > 0.0
 }
 
 /// Build a display list from a layout tree
 pub fn build_display_list(layout_root: &LayoutBox) -> DisplayList {
     let mut list = Vec::new();
-    render_layout_box(&mut list, layout_root);
+    render_stacking_context(&mut list, layout_root, (0.0, 0.0), (0.0, 0.0));
     list
 }
 
-fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox) {
-    render_background(list, layout_box);
-    render_borders(list, layout_box);
-    render_text(list, layout_box);
+/// Build the display list for one box and its subtree alone, as if it were
+/// the whole page, shifted so `origin` lands at (0, 0)
+pub fn build_subtree_display_list(layout_box: &LayoutBox, origin: (f32, f32)) -> DisplayList {
+    let mut list = Vec::new();
+    // Fixed descendants stay where they are in the viewport, shifted along
+    // with the rest of the page
+    let offset = (-origin.0, -origin.1);
+    render_stacking_context(&mut list, layout_box, offset, offset);
+    list
+}
+
+/// Build a layout inspector overlay to paint after the page: each box's
+/// border box outlined in a color for its box type, and with `show_spacing`
+/// its margin and padding areas tinted too
+pub fn build_debug_overlay(layout_root: &LayoutBox, show_spacing: bool) -> DisplayList {
+    let mut list = Vec::new();
+    render_debug_box(&mut list, layout_root, (0.0, 0.0), show_spacing);
+    list
+}
+
+fn render_debug_box(
+    list: &mut DisplayList,
+    layout_box: &LayoutBox,
+    offset: (f32, f32),
+    show_spacing: bool,
+) {
+    let (dx, dy) = layout_box.translation;
+    let offset = (offset.0 + dx, offset.1 + dy);
+    let d = &layout_box.dimensions;
+    let border_box = d.border_box().translated(offset.0, offset.1);
+
+    if show_spacing {
+        let margin_tint = Color {
+            r: 246,
+            g: 178,
+            b: 107,
+            a: 96,
+        };
+        let padding_tint = Color {
+            r: 147,
+            g: 196,
+            b: 125,
+            a: 96,
+        };
+        let margin_box = d.margin_box().translated(offset.0, offset.1);
+        let padding_box = d.padding_box().translated(offset.0, offset.1);
+        push_frame(list, margin_box, d.margin, margin_tint);
+        push_frame(list, padding_box, d.padding, padding_tint);
+    }
+
+    let outline_color = match layout_box.box_type {
+        BoxType::BlockNode(_) => Color {
+            r: 0,
+            g: 120,
+            b: 215,
+            a: 255,
+        },
+        BoxType::InlineNode(_) => Color {
+            r: 16,
+            g: 160,
+            b: 64,
+            a: 255,
+        },
+        BoxType::InlineBlockNode(_) => Color {
+            r: 230,
+            g: 120,
+            b: 0,
+            a: 255,
+        },
+        BoxType::AnonymousBlock => Color {
+            r: 150,
+            g: 150,
+            b: 150,
+            a: 255,
+        },
+    };
+    let one = EdgeSizes {
+        left: 1.0,
+        right: 1.0,
+        top: 1.0,
+        bottom: 1.0,
+    };
+    push_frame(list, border_box, one, outline_color);
+
+    let child_offset = match layout_box.scroll {
+        Some(scroll) => (offset.0 - scroll.offset_x, offset.1 - scroll.offset_y),
+        None => offset,
+    };
     for child in &layout_box.children {
-        render_layout_box(list, child);
+        render_debug_box(list, child, child_offset, show_spacing);
     }
 }
 
-fn render_background(list: &mut DisplayList, layout_box: &LayoutBox) {
-    get_color(layout_box, "background").map(|color| {
-        list.push(DisplayCommand::SolidColor(
-            color,
-            layout_box.dimensions.border_box(),
-        ))
-    });
+/// Fill the band just inside the edges of `outer`, `edges` thick on each side
+fn push_frame(list: &mut DisplayList, outer: Rect, edges: EdgeSizes, color: Color) {
+    let bands = [
+        (outer.x, outer.y, outer.width, edges.top),
+        (
+            outer.x,
+            outer.y + outer.height - edges.bottom,
+            outer.width,
+            edges.bottom,
+        ),
+        (
+            outer.x,
+            outer.y + edges.top,
+            edges.left,
+            outer.height - edges.top - edges.bottom,
+        ),
+        (
+            outer.x + outer.width - edges.right,
+            outer.y + edges.top,
+            edges.right,
+            outer.height - edges.top - edges.bottom,
+        ),
+    ];
+    for (x, y, width, height) in bands {
+        if width > 0.0 && height > 0.0 {
+            list.push(DisplayCommand::SolidColor(
+                color,
+                Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                },
+            ));
+        }
+    }
+}
+
+/// Highlight every case-insensitive occurrence of `query` in the painted
+/// text, by adding a highlight rect beneath each match. Matches are found
+/// within each line of text, so a query can't span a line break.
+pub fn highlight_matches(display_list: &DisplayList, query: &str) -> DisplayList {
+    let highlight = Color {
+        r: 255,
+        g: 235,
+        b: 59,
+        a: 255,
+    };
+    let mut list = Vec::with_capacity(display_list.len());
+    for item in display_list {
+        if let DisplayCommand::Text(text, rect, _, spacing, face) = item {
+            for (start, end) in find_matches(text, query) {
+                let chars = |from, to| text.chars().skip(from).take(to - from).collect::<String>();
+                // Letter spacing separates the match from the text before it
+                let offset = match start {
+                    0 => 0.0,
+                    _ => measure_text(&chars(0, start), *spacing, face.size) + spacing.letter,
+                };
+                list.push(DisplayCommand::SolidColor(
+                    highlight,
+                    Rect {
+                        x: rect.x + offset,
+                        y: rect.y,
+                        width: measure_text(&chars(start, end), *spacing, face.size),
+                        height: rect.height,
+                    },
+                ));
+            }
+        }
+        list.push(item.clone());
+    }
+    list
+}
+
+/// Character offsets `(start, end)` of each non-overlapping, case-insensitive
+/// occurrence of `query` in `text`
+pub fn find_matches(text: &str, query: &str) -> Vec<(usize, usize)> {
+    let fold = |c: char| c.to_lowercase().collect::<String>();
+    let text: Vec<String> = text.chars().map(fold).collect();
+    let query: Vec<String> = query.chars().map(fold).collect();
+
+    let mut matches = Vec::new();
+    if query.is_empty() {
+        return matches;
+    }
+    let mut start = 0;
+    while start + query.len() <= text.len() {
+        if text[start..start + query.len()] == query[..] {
+            matches.push((start, start + query.len()));
+            start += query.len();
+        } else {
+            start += 1;
+        }
+    }
+    matches
+}
+
+/// A positioned box's commands, deferred to be painted in `z_index` order
+struct Layer {
+    z_index: i32,
+    commands: DisplayList,
+}
+
+/// Paint a box that positions its descendants in z: the root, or any
+/// positioned box. Its positioned descendants are painted after its other
+/// content (or for a negative z-index, right after its own background), in
+/// z-index order and then document order. `viewport` is where the
+/// viewport's origin is painted, which fixed boxes are placed against.
+fn render_stacking_context(
+    list: &mut DisplayList,
+    layout_box: &LayoutBox,
+    offset: (f32, f32),
+    viewport: (f32, f32),
+) {
+    let (dx, dy) = layout_box.translation;
+    let offset = (offset.0 + dx, offset.1 + dy);
+    render_box(list, layout_box, offset);
+
+    let mut content = Vec::new();
+    let mut layers = Vec::new();
+    render_children(
+        &mut content,
+        &mut layers,
+        layout_box,
+        offset,
+        viewport,
+        &mut Vec::new(),
+    );
+
+    // A stable sort keeps document order among equal z-indexes
+    layers.sort_by_key(|layer| layer.z_index);
+    let negative = layers.partition_point(|layer| layer.z_index < 0);
+    let mut layers = layers.into_iter().map(|layer| layer.commands);
+    list.extend(layers.by_ref().take(negative).flatten());
+    list.append(&mut content);
+    list.extend(layers.flatten());
+}
+
+/// Paint a box and its descendants, setting aside positioned ones as
+/// `layers`. `clips` holds the scroll containers it's inside, which still
+/// clip a layer painted outside them.
+fn render_layout_box(
+    list: &mut DisplayList,
+    layers: &mut Vec<Layer>,
+    layout_box: &LayoutBox,
+    offset: (f32, f32),
+    viewport: (f32, f32),
+    clips: &mut Vec<Rect>,
+) {
+    if let Some(z_index) = get_z_index(layout_box) {
+        // A fixed box is placed in the viewport, so it's neither scrolled nor
+        // clipped by the scroll containers it's inside
+        let fixed = layout_box.position() == Position::Fixed;
+        let (offset, clips): (_, &[Rect]) = if fixed {
+            (viewport, &[])
+        } else {
+            (offset, clips)
+        };
+        let mut commands: DisplayList = clips
+            .iter()
+            .map(|clip| DisplayCommand::PushClip(*clip))
+            .collect();
+        render_stacking_context(&mut commands, layout_box, offset, viewport);
+        commands.extend(clips.iter().map(|_| DisplayCommand::PopClip));
+        layers.push(Layer { z_index, commands });
+        return;
+    }
+
+    let (dx, dy) = layout_box.translation;
+    let offset = (offset.0 + dx, offset.1 + dy);
+    render_box(list, layout_box, offset);
+    render_children(list, layers, layout_box, offset, viewport, clips);
+}
+
+/// Paint a box's own background, borders, and text
+fn render_box(list: &mut DisplayList, layout_box: &LayoutBox, offset: (f32, f32)) {
+    // Hidden boxes still paint their children, which may be visible again
+    if is_visible(layout_box) {
+        render_background(list, layout_box, offset);
+        render_borders(list, layout_box, offset);
+        render_text(list, layout_box, offset);
+        render_marker(list, layout_box, offset);
+        render_outline(list, layout_box, offset);
+    }
+}
+
+fn render_children(
+    list: &mut DisplayList,
+    layers: &mut Vec<Layer>,
+    layout_box: &LayoutBox,
+    offset: (f32, f32),
+    viewport: (f32, f32),
+    clips: &mut Vec<Rect>,
+) {
+    match layout_box.scroll {
+        Some(scroll) => {
+            let clip = layout_box
+                .dimensions
+                .padding_box()
+                .translated(offset.0, offset.1);
+            list.push(DisplayCommand::PushClip(clip));
+            clips.push(clip);
+            let child_offset = (offset.0 - scroll.offset_x, offset.1 - scroll.offset_y);
+            for child in &layout_box.children {
+                render_layout_box(list, layers, child, child_offset, viewport, clips);
+            }
+            clips.pop();
+            list.push(DisplayCommand::PopClip);
+        }
+        None => {
+            for child in &layout_box.children {
+                render_layout_box(list, layers, child, offset, viewport, clips);
+            }
+        }
+    }
 }
 
-fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox) {
-    let color = match get_color(layout_box, "border-color") {
-        Some(color) => color,
-        _ => return,
+fn render_background(list: &mut DisplayList, layout_box: &LayoutBox, offset: (f32, f32)) {
+    let rect = layout_box
+        .dimensions
+        .border_box()
+        .translated(offset.0, offset.1);
+    let (rx, ry) = layout_box.border_radius;
+    // Paint the bottom layer first so translucent layers blend over it
+    for color in get_background_layers(layout_box).into_iter().rev() {
+        if rx > 0.0 && ry > 0.0 {
+            list.push(DisplayCommand::RoundedColor(color, rect, (rx, ry)));
+        } else {
+            list.push(DisplayCommand::SolidColor(color, rect));
+        }
+    }
+    if let Some(image) = get_background_image(layout_box) {
+        let area = layout_box
+            .dimensions
+            .padding_box()
+            .translated(offset.0, offset.1);
+        list.push(DisplayCommand::Image(
+            image.url,
+            area,
+            image.position,
+            image.repeat,
+        ));
+    }
+}
+
+fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox, offset: (f32, f32)) {
+    // Without a color of its own, a border takes the text color
+    let Some(color) =
+        get_color(layout_box, "border-color").or_else(|| get_color(layout_box, "color"))
+    else {
+        return;
     };
 
     let d = &layout_box.dimensions;
-    let border_box = d.border_box();
+    if d.border.left + d.border.right + d.border.top + d.border.bottom == 0.0 {
+        return;
+    }
+    let border_box = d.border_box().translated(offset.0, offset.1);
 
     // Left border
     list.push(DisplayCommand::SolidColor(
@@ -89,22 +530,47 @@ fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox) {
     ));
 }
 
-fn render_text(list: &mut DisplayList, layout_box: &LayoutBox) {
+/// Paint `outline-width`/`outline-color` as a frame just outside the border
+/// box. Unlike a border it takes no space, so it may overlap neighbors.
+fn render_outline(list: &mut DisplayList, layout_box: &LayoutBox, offset: (f32, f32)) {
+    let width = layout_box.outline_width;
+    if width <= 0.0 {
+        return;
+    }
+    // Without a color of its own, an outline takes the text color
+    let Some(color) =
+        get_color(layout_box, "outline-color").or_else(|| get_color(layout_box, "color"))
+    else {
+        return;
+    };
+    let edges = EdgeSizes {
+        left: width,
+        right: width,
+        top: width,
+        bottom: width,
+    };
+    let outer = layout_box
+        .dimensions
+        .border_box()
+        .translated(offset.0, offset.1)
+        .expanded_by(edges);
+    push_frame(list, outer, edges, color);
+}
+
+fn render_text(list: &mut DisplayList, layout_box: &LayoutBox, offset: (f32, f32)) {
     // Check if this layout box corresponds to a text node
     let style_node = match layout_box.box_type {
-        BoxType::InlineNode(style) | BoxType::BlockNode(style) => style,
+        BoxType::InlineNode(style)
+        | BoxType::BlockNode(style)
+        | BoxType::InlineBlockNode(style) => style,
         BoxType::AnonymousBlock => return,
     };
 
-    // Extract text content if this is a text node
-    if let NodeType::Text(text) = &style_node.node.node_type {
-        let text = text.trim();
-        if text.is_empty() {
-            return;
-        }
-
+    // Extract text content if this is a text node (or generated content)
+    if style_node.text().is_some() {
         // Get text color from the styled node (which includes inheritance)
-        let color = style_node.value("color")
+        let color = style_node
+            .value("color")
             .and_then(|val| match val {
                 Value::Color(c) => Some(c),
                 _ => None,
@@ -116,23 +582,149 @@ fn render_text(list: &mut DisplayList, layout_box: &LayoutBox) {
                 a: 255,
             });
 
-        // Use the content box for text positioning
-        let content_box = layout_box.dimensions.content;
-        
-        // Only render if there's actual space allocated
-        if content_box.width > 0.0 && content_box.height > 0.0 {
+        let underline =
+            style_node.value("text-decoration") == Some(Value::Keyword("underline".to_string()));
+        let face = style_node.font_face();
+
+        // Each fragment holds the text laid out on one line
+        for fragment in &layout_box.fragments {
+            let rect = fragment.rect.translated(offset.0, offset.1);
             list.push(DisplayCommand::Text(
-                text.to_string(),
-                content_box,
+                fragment.text.clone(),
+                rect,
                 color,
+                layout_box.text_spacing,
+                face,
             ));
+            if underline {
+                // Just below the glyphs, which fill the top of the line
+                list.push(DisplayCommand::SolidColor(
+                    color,
+                    Rect {
+                        x: rect.x,
+                        y: rect.y + rect.height - UNDERLINE_OFFSET,
+                        width: rect.width,
+                        height: 1.0,
+                    },
+                ));
+            }
         }
     }
 }
 
+fn render_marker(list: &mut DisplayList, layout_box: &LayoutBox, offset: (f32, f32)) {
+    if let Some(marker) = &layout_box.marker {
+        let color = get_color(layout_box, "color").unwrap_or(Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 255,
+        });
+        list.push(DisplayCommand::Text(
+            marker.text.clone(),
+            marker.rect.translated(offset.0, offset.1),
+            color,
+            TextSpacing::default(),
+            FontFace::default(),
+        ));
+    }
+}
+
+/// Whether a box paints itself; `visibility` is inherited, so anonymous boxes
+/// defer to their children
+fn is_visible(layout_box: &LayoutBox) -> bool {
+    match layout_box.box_type {
+        BoxType::BlockNode(style)
+        | BoxType::InlineNode(style)
+        | BoxType::InlineBlockNode(style) => style.visibility() == Visibility::Visible,
+        BoxType::AnonymousBlock => true,
+    }
+}
+
+/// The z-index of a positioned box, or `None` for a box painted in place
+fn get_z_index(layout_box: &LayoutBox) -> Option<i32> {
+    match layout_box.box_type {
+        BoxType::BlockNode(style)
+        | BoxType::InlineNode(style)
+        | BoxType::InlineBlockNode(style) => match style.position() {
+            Position::Static => None,
+            Position::Relative | Position::Absolute | Position::Fixed => Some(style.z_index()),
+        },
+        BoxType::AnonymousBlock => None,
+    }
+}
+
+/// Background colors, topmost first
+fn get_background_layers(layout_box: &LayoutBox) -> Vec<Color> {
+    match layout_box.box_type {
+        BoxType::BlockNode(style)
+        | BoxType::InlineNode(style)
+        | BoxType::InlineBlockNode(style) => match style.value("background-color") {
+            Some(Value::Color(color)) if color.a > 0 => vec![color],
+            Some(Value::ColorList(colors)) => colors,
+            _ => Vec::new(),
+        },
+        BoxType::AnonymousBlock => Vec::new(),
+    }
+}
+
+/// A box's `background-image`, with where and how to draw it
+struct BackgroundImage {
+    url: String,
+    position: Value,
+    /// Whether the image tiles along the x and y axes
+    repeat: (bool, bool),
+}
+
+fn get_background_image(layout_box: &LayoutBox) -> Option<BackgroundImage> {
+    let style = match layout_box.box_type {
+        BoxType::BlockNode(style)
+        | BoxType::InlineNode(style)
+        | BoxType::InlineBlockNode(style) => style,
+        BoxType::AnonymousBlock => return None,
+    };
+    let Some(Value::Url(url)) = style.value("background-image") else {
+        return None;
+    };
+    let zero = || Box::new(Value::Length(0.0, Unit::Percent));
+    let position = style
+        .value("background-position")
+        .unwrap_or(Value::Position(zero(), zero()));
+    let repeat = match style.value("background-repeat") {
+        Some(Value::Keyword(keyword)) => match keyword.to_lowercase().as_str() {
+            "no-repeat" => (false, false),
+            "repeat-x" => (true, false),
+            "repeat-y" => (false, true),
+            _ => (true, true),
+        },
+        _ => (true, true),
+    };
+    Some(BackgroundImage {
+        url,
+        position,
+        repeat,
+    })
+}
+
+/// The URL of every background image a display list paints, each once, so
+/// they can be loaded and added to the canvas with `Canvas::add_image`
+pub fn image_urls(list: &DisplayList) -> Vec<String> {
+    let mut urls: Vec<String> = Vec::new();
+    for item in list {
+        if let DisplayCommand::Image(url, ..) = item {
+            if !urls.contains(url) {
+                urls.push(url.clone());
+            }
+        }
+    }
+    urls
+}
+
 fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
     match layout_box.box_type {
-        BoxType::BlockNode(style) | BoxType::InlineNode(style) => match style.value(name) {
+        BoxType::BlockNode(style)
+        | BoxType::InlineNode(style)
+        | BoxType::InlineBlockNode(style) => match style.value(name) {
             Some(Value::Color(color)) => Some(color),
             _ => None,
         },
@@ -140,14 +732,54 @@ fn get_color(layout_box: &LayoutBox, name: &str) -> Option<Color> {
     }
 }
 
+/// Where an image of the given size lands in a background `area`. A
+/// percentage lines up that point of the image with the same point of the
+/// area, so `50% 50%` centers it; a length offsets its top left corner.
+pub fn background_image_rect(area: &Rect, width: f32, height: f32, position: &Value) -> Rect {
+    let offset = |value: &Value, free_space: f32| match *value {
+        Value::Length(n, Unit::Percent) => n / 100.0 * free_space,
+        ref length => length.to_px(),
+    };
+    let (x, y) = match position {
+        Value::Position(x, y) => (
+            offset(x, area.width - width),
+            offset(y, area.height - height),
+        ),
+        _ => (0.0, 0.0),
+    };
+    Rect {
+        x: area.x + x,
+        y: area.y + y,
+        width,
+        height,
+    }
+}
+
+/// How an image is resampled when painted at a size other than its own
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ScaleMode {
+    /// Take the closest source pixel, for crisp but blocky results
+    Nearest,
+    /// Blend the four closest source pixels by distance
+    #[default]
+    Bilinear,
+}
+
 /// Canvas for painting
+#[derive(Clone)]
 pub struct Canvas {
     pub pixels: Vec<Color>,
     pub width: usize,
     pub height: usize,
+    clip_stack: Vec<Rect>,
+    /// The font text is painted in
+    font: Font,
+    /// Decoded images by URL, for `DisplayCommand::Image`
+    images: HashMap<String, Canvas>,
 }
 
 impl Canvas {
+    /// Create a white canvas
     pub fn new(width: usize, height: usize) -> Canvas {
         let white = Color {
             r: 255,
@@ -155,16 +787,47 @@ impl Canvas {
             b: 255,
             a: 255,
         };
+        Canvas::with_background(width, height, white)
+    }
+
+    /// Create a canvas filled with `background`, which the page paints over
+    pub fn with_background(width: usize, height: usize, background: Color) -> Canvas {
         Canvas {
-            pixels: vec![white; width * height],
+            pixels: vec![background; width * height],
             width,
             height,
+            clip_stack: Vec::new(),
+            font: Font::embedded(),
+            images: HashMap::new(),
         }
     }
 
+    /// Paint text in `font` instead of the embedded default
+    pub fn set_font(&mut self, font: Font) {
+        self.font = font;
+    }
+
+    /// Make a decoded image available to background images with this URL.
+    /// Backgrounds whose image was never added aren't painted.
+    pub fn add_image(&mut self, url: &str, image: Canvas) {
+        self.images.insert(url.to_string(), image);
+    }
+
+    /// The area painting is currently restricted to
+    fn clip_rect(&self) -> Rect {
+        let full = Rect {
+            x: 0.0,
+            y: 0.0,
+            width: self.width as f32,
+            height: self.height as f32,
+        };
+        self.clip_stack.last().copied().unwrap_or(full)
+    }
+
     pub fn paint_item(&mut self, item: &DisplayCommand) {
         match item {
             DisplayCommand::SolidColor(color, rect) => {
+                let rect = rect.intersection(&self.clip_rect());
                 let x0 = rect.x.clamp(0.0, self.width as f32) as usize;
                 let y0 = rect.y.clamp(0.0, self.height as f32) as usize;
                 let x1 = (rect.x + rect.width).clamp(0.0, self.width as f32) as usize;
@@ -172,68 +835,260 @@ impl Canvas {
 
                 for y in y0..y1 {
                     for x in x0..x1 {
-                        self.pixels[y * self.width + x] = *color;
+                        self.blend_pixel(x, y, color);
                     }
                 }
             }
-            DisplayCommand::Text(text, rect, color) => {
-                self.paint_text(text, rect, color);
+            DisplayCommand::RoundedColor(color, rect, radius) => {
+                self.paint_rounded_rect(color, rect, *radius);
+            }
+            DisplayCommand::Text(text, rect, color, spacing, face) => {
+                self.paint_text(text, rect, color, *spacing, *face);
+            }
+            DisplayCommand::Image(url, area, position, repeat) => {
+                // Taken out while it's drawn, as drawing borrows the canvas
+                if let Some(image) = self.images.remove(url) {
+                    self.draw_background_image(&image, area, position, *repeat);
+                    self.images.insert(url.clone(), image);
+                }
+            }
+            DisplayCommand::PushClip(rect) => {
+                let clip = rect.intersection(&self.clip_rect());
+                self.clip_stack.push(clip);
+            }
+            DisplayCommand::PopClip => {
+                self.clip_stack.pop();
+            }
+        }
+    }
+
+    /// Paint an image, such as a decoded PPM, stretched to fill `rect`
+    pub fn draw_image(&mut self, image: &Canvas, rect: &Rect, scale_mode: ScaleMode) {
+        if image.pixels.is_empty() || rect.width <= 0.0 || rect.height <= 0.0 {
+            return;
+        }
+        let clipped = rect.intersection(&self.clip_rect());
+        let x0 = clipped.x.clamp(0.0, self.width as f32) as usize;
+        let y0 = clipped.y.clamp(0.0, self.height as f32) as usize;
+        let x1 = (clipped.x + clipped.width).clamp(0.0, self.width as f32) as usize;
+        let y1 = (clipped.y + clipped.height).clamp(0.0, self.height as f32) as usize;
+
+        let scale_x = image.width as f32 / rect.width;
+        let scale_y = image.height as f32 / rect.height;
+        for y in y0..y1 {
+            for x in x0..x1 {
+                // Map pixel centers to pixel centers
+                let u = (x as f32 + 0.5 - rect.x) * scale_x - 0.5;
+                let v = (y as f32 + 0.5 - rect.y) * scale_y - 0.5;
+                let color = image.sample(u, v, scale_mode);
+                self.blend_pixel(x, y, &color);
+            }
+        }
+    }
+
+    /// Paint an image at its own size as the background of `area` (usually
+    /// the padding box), placed by a `background-position`, tiled along the
+    /// axes that `repeat`, and clipped to the area
+    pub fn draw_background_image(
+        &mut self,
+        image: &Canvas,
+        area: &Rect,
+        position: &Value,
+        repeat: (bool, bool),
+    ) {
+        let (width, height) = (image.width as f32, image.height as f32);
+        if width == 0.0 || height == 0.0 {
+            return;
+        }
+        let placed = background_image_rect(area, width, height, position);
+        // Tiles start a whole number of images before the placed one, so
+        // one lands where it was placed and the rest cover the area
+        let tiles = |repeats: bool, start: f32, size: f32, area_start: f32, area_size: f32| {
+            if !repeats {
+                return vec![start];
+            }
+            let first = start - ((start - area_start) / size).ceil() * size;
+            let count = ((area_start + area_size - first) / size).ceil().max(0.0) as usize;
+            (0..count).map(|i| first + i as f32 * size).collect()
+        };
+        let xs = tiles(repeat.0, placed.x, width, area.x, area.width);
+        let ys = tiles(repeat.1, placed.y, height, area.y, area.height);
+
+        self.clip_stack.push(area.intersection(&self.clip_rect()));
+        for &y in &ys {
+            for &x in &xs {
+                let rect = Rect {
+                    x,
+                    y,
+                    width,
+                    height,
+                };
+                self.draw_image(image, &rect, ScaleMode::Nearest);
+            }
+        }
+        self.clip_stack.pop();
+    }
+
+    /// The color at a point between pixel centers, clamped to the edges
+    fn sample(&self, u: f32, v: f32, scale_mode: ScaleMode) -> Color {
+        let max_x = (self.width - 1) as f32;
+        let max_y = (self.height - 1) as f32;
+        let u = u.clamp(0.0, max_x);
+        let v = v.clamp(0.0, max_y);
+        let pixel = |x: f32, y: f32| self.pixels[y as usize * self.width + x as usize];
+        match scale_mode {
+            ScaleMode::Nearest => pixel(u.round(), v.round()),
+            ScaleMode::Bilinear => {
+                let (left, top) = (u.floor(), v.floor());
+                let (right, bottom) = ((left + 1.0).min(max_x), (top + 1.0).min(max_y));
+                let (tx, ty) = (u - left, v - top);
+                let corners = [
+                    (pixel(left, top), (1.0 - tx) * (1.0 - ty)),
+                    (pixel(right, top), tx * (1.0 - ty)),
+                    (pixel(left, bottom), (1.0 - tx) * ty),
+                    (pixel(right, bottom), tx * ty),
+                ];
+                let channel = |get: fn(&Color) -> u8| {
+                    let sum: f32 = corners
+                        .iter()
+                        .map(|(color, weight)| get(color) as f32 * weight)
+                        .sum();
+                    sum.round() as u8
+                };
+                Color {
+                    r: channel(|c| c.r),
+                    g: channel(|c| c.g),
+                    b: channel(|c| c.b),
+                    a: channel(|c| c.a),
+                }
+            }
+        }
+    }
+
+    /// Composite a color over the pixel at (x, y) according to their alphas
+    fn blend_pixel(&mut self, x: usize, y: usize, color: &Color) {
+        let pixel = &mut self.pixels[y * self.width + x];
+        if color.a == 255 {
+            *pixel = *color;
+            return;
+        }
+        let src_alpha = color.a as f32 / 255.0;
+        let dst_alpha = pixel.a as f32 / 255.0 * (1.0 - src_alpha);
+        let alpha = src_alpha + dst_alpha;
+        if alpha == 0.0 {
+            return;
+        }
+        let mix = |src: u8, dst: u8| {
+            ((src as f32 * src_alpha + dst as f32 * dst_alpha) / alpha).round() as u8
+        };
+        *pixel = Color {
+            r: mix(color.r, pixel.r),
+            g: mix(color.g, pixel.g),
+            b: mix(color.b, pixel.b),
+            a: (alpha * 255.0).round() as u8,
+        };
+    }
+
+    /// Fill a rect, leaving out the pixels beyond its elliptical corners
+    fn paint_rounded_rect(&mut self, color: &Color, rect: &Rect, radius: (f32, f32)) {
+        // Corners can't be larger than half the rect
+        let rx = radius.0.min(rect.width / 2.0);
+        let ry = radius.1.min(rect.height / 2.0);
+
+        let clipped = rect.intersection(&self.clip_rect());
+        let x0 = clipped.x as usize;
+        let y0 = clipped.y as usize;
+        let x1 = (clipped.x + clipped.width) as usize;
+        let y1 = (clipped.y + clipped.height) as usize;
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                // Distance from the pixel center to the nearest corner's center
+                let px = x as f32 + 0.5;
+                let py = y as f32 + 0.5;
+                let dx = (rect.x + rx - px)
+                    .max(px - (rect.x + rect.width - rx))
+                    .max(0.0);
+                let dy = (rect.y + ry - py)
+                    .max(py - (rect.y + rect.height - ry))
+                    .max(0.0);
+                if (dx / rx).powi(2) + (dy / ry).powi(2) <= 1.0 {
+                    self.blend_pixel(x, y, color);
+                }
             }
         }
     }
 
     /// Paint text as simple character blocks (placeholder for real text rendering)
-    fn paint_text(&mut self, text: &str, rect: &Rect, color: &Color) {
-        const CHAR_WIDTH: f32 = 8.0;
-        const CHAR_HEIGHT: f32 = 12.0;
-        const LINE_HEIGHT: f32 = 14.0;
+    fn paint_text(
+        &mut self,
+        text: &str,
+        rect: &Rect,
+        color: &Color,
+        spacing: TextSpacing,
+        face: FontFace,
+    ) {
+        // Metrics at the default font size, scaled to the face's
+        let scale = text_scale(face.size);
+        let char_width = 8.0 * scale;
+        let char_height = 12.0 * scale;
+        let line_height = 14.0 * scale;
 
         let x0 = rect.x.clamp(0.0, self.width as f32);
         let y0 = rect.y.clamp(0.0, self.height as f32);
         let max_x = (rect.x + rect.width).clamp(0.0, self.width as f32);
         let max_y = (rect.y + rect.height).clamp(0.0, self.height as f32);
 
+        let clip = self.clip_rect();
+        let font = self.font.clone();
         let mut x = x0;
         let mut y = y0;
 
         for ch in text.chars() {
-            if ch == '\n' || x + CHAR_WIDTH > max_x {
+            if ch == '\n' || x + char_width > max_x {
                 x = x0;
-                y += LINE_HEIGHT;
+                y += line_height;
                 if ch == '\n' {
                     continue;
                 }
-                if y + CHAR_HEIGHT > max_y {
+                if y + char_height > max_y {
                     break;
                 }
             }
 
             if ch.is_whitespace() {
-                x += CHAR_WIDTH;
+                x += char_width + spacing.letter;
+                if ch == ' ' {
+                    x += spacing.word;
+                }
                 continue;
             }
 
-            // Draw a simple rectangle for each character
-            let char_x0 = x as usize;
-            let char_y0 = y as usize;
-            let char_x1 = (x + CHAR_WIDTH).min(max_x) as usize;
-            let char_y1 = (y + CHAR_HEIGHT).min(max_y) as usize;
-
-            // Draw character as a filled rectangle with some pattern
-            for py in char_y0..char_y1 {
-                for px in char_x0..char_x1 {
-                    // Create a simple pattern to distinguish characters
-                    let is_edge = px == char_x0 || px == char_x1 - 1 || 
-                                 py == char_y0 || py == char_y1 - 1;
-                    if is_edge || (px + py) % 3 == 0 {
-                        if py < self.height && px < self.width {
-                            self.pixels[py * self.width + px] = *color;
-                        }
+            // Glyphs sit on the baseline layout lined the text up on. Faux
+            // italic slants them by shifting higher rows right; faux bold
+            // doubles each stroke one pixel over.
+            let strokes = if face.bold { 2 } else { 1 };
+            let (origin_x, baseline) = (x as i32, (y + BASELINE * scale) as i32);
+            let bottom = (y + char_height) as i32;
+            font.draw_glyph(ch, char_height, |gx, gy, coverage| {
+                let py = baseline + gy;
+                let shear = if face.italic { (bottom - py) / 4 } else { 0 };
+                if py < 0 || py as f32 >= max_y {
+                    return;
+                }
+                let color = Color {
+                    a: (color.a as f32 * coverage.min(1.0)).round() as u8,
+                    ..*color
+                };
+                for stroke in 0..strokes {
+                    let px = origin_x + gx + shear + stroke;
+                    if px >= 0 && clip.contains(px as f32, py as f32) {
+                        self.blend_pixel(px as usize, py as usize, &color);
                     }
                 }
-            }
+            });
 
-            x += CHAR_WIDTH;
+            x += char_width + spacing.letter;
         }
     }
 
@@ -242,4 +1097,405 @@ impl Canvas {
             self.paint_item(item);
         }
     }
+
+    /// Render a terminal preview using ANSI 24-bit color escapes. Each
+    /// character cell averages blocks of pixels, drawing two of them with
+    /// the upper half block `▀` (foreground on top, background below).
+    pub fn to_ansi(&self) -> String {
+        const BLOCK_SIZE: usize = 8;
+
+        let columns = self.width.div_ceil(BLOCK_SIZE);
+        let rows = self.height.div_ceil(BLOCK_SIZE);
+
+        let mut output = String::new();
+        for row in (0..rows).step_by(2) {
+            for column in 0..columns {
+                let top = self.average_color(column * BLOCK_SIZE, row * BLOCK_SIZE, BLOCK_SIZE);
+                let bottom = if row + 1 < rows {
+                    self.average_color(column * BLOCK_SIZE, (row + 1) * BLOCK_SIZE, BLOCK_SIZE)
+                } else {
+                    top
+                };
+                output.push_str(&format!(
+                    "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m\u{2580}",
+                    top.r, top.g, top.b, bottom.r, bottom.g, bottom.b
+                ));
+            }
+            output.push_str("\x1b[0m\n");
+        }
+        output
+    }
+
+    /// Average color of the pixels in a square block, clipped to the canvas
+    fn average_color(&self, x0: usize, y0: usize, size: usize) -> Color {
+        let x1 = (x0 + size).min(self.width);
+        let y1 = (y0 + size).min(self.height);
+        let count = ((x1 - x0) * (y1 - y0)).max(1) as u32;
+
+        let (mut r, mut g, mut b) = (0u32, 0u32, 0u32);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let color = self.pixels[y * self.width + x];
+                r += color.r as u32;
+                g += color.g as u32;
+                b += color.b as u32;
+            }
+        }
+        Color {
+            r: (r / count) as u8,
+            g: (g / count) as u8,
+            b: (b / count) as u8,
+            a: 255,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Color = Color {
+        r: 255,
+        g: 0,
+        b: 0,
+        a: 255,
+    };
+
+    /// The display list for `html` styled by `css`, laid out `width` px wide
+    fn display_list(html: &str, css: &str, width: f32) -> DisplayList {
+        let document = crate::html::Parser::parse(html.to_string());
+        let stylesheet = crate::css::Parser::parse(css.to_string());
+        let ua_stylesheet = crate::ua::stylesheet();
+        let styled = crate::style::style_tree(&document, &[&stylesheet], &ua_stylesheet, width);
+        let mut viewport = crate::layout::Dimensions::default();
+        viewport.content.width = width;
+        build_display_list(&crate::layout::layout_tree(&styled, viewport))
+    }
+
+    #[test]
+    fn draw_image_scales_to_fill_the_rect() {
+        let image = Canvas::with_background(2, 2, RED);
+        let mut canvas = Canvas::new(10, 10);
+        let rect = Rect {
+            x: 2.0,
+            y: 2.0,
+            width: 4.0,
+            height: 4.0,
+        };
+        canvas.draw_image(&image, &rect, ScaleMode::Nearest);
+        assert_eq!(canvas.pixels[2 * 10 + 2], RED);
+        assert_eq!(canvas.pixels[5 * 10 + 5], RED);
+        assert_ne!(canvas.pixels[6 * 10 + 6], RED);
+    }
+
+    #[test]
+    fn to_ansi_draws_two_blocks_per_cell() {
+        let canvas = Canvas::with_background(16, 16, RED);
+        let preview = canvas.to_ansi();
+        assert_eq!(preview.lines().count(), 1);
+        assert_eq!(preview.matches('\u{2580}').count(), 2);
+        assert!(preview.starts_with("\x1b[38;2;255;0;0m\x1b[48;2;255;0;0m"));
+    }
+
+    #[test]
+    fn stacked_translucent_backgrounds_composite_top_first() {
+        let css = "div { display: block; height: 10px; \
+                   background-color: rgba(255, 0, 0, 0.5), rgba(0, 0, 255, 0.5); }";
+        let canvas = crate::golden::render("<div></div>", css, 10, 10);
+        // Blue at half strength over white, then red at half over that
+        let pixel = canvas.pixels[0];
+        assert_eq!((pixel.r, pixel.g, pixel.b, pixel.a), (191, 63, 127, 255));
+    }
+
+    #[test]
+    fn background_image_is_painted_from_the_canvas_images() {
+        let css = "div { display: block; height: 4px; background: url(dot.ppm) no-repeat; }";
+        let list = display_list("<div></div>", css, 10.0);
+        assert_eq!(image_urls(&list), vec!["dot.ppm"]);
+
+        let mut canvas = Canvas::new(10, 10);
+        canvas.add_image("dot.ppm", Canvas::with_background(2, 2, RED));
+        canvas.paint(&list);
+        assert_eq!(canvas.pixels[10 + 1], RED);
+        assert_ne!(canvas.pixels[2], RED);
+    }
+
+    #[test]
+    fn background_position_center_centers_a_smaller_image() {
+        let css = "div { display: block; height: 10px; background: url(dot.ppm) no-repeat; \
+                   background-position: center; }";
+        let mut canvas = Canvas::new(10, 10);
+        canvas.add_image("dot.ppm", Canvas::with_background(2, 2, RED));
+        canvas.paint(&display_list("<div></div>", css, 10.0));
+        let red: Vec<(usize, usize)> = (0..100)
+            .filter(|&i| canvas.pixels[i] == RED)
+            .map(|i| (i % 10, i / 10))
+            .collect();
+        assert_eq!(red, vec![(4, 4), (5, 4), (4, 5), (5, 5)]);
+    }
+
+    /// The union of every solid `color` rect in `list`
+    fn solid_bounds(list: &DisplayList, color: Color) -> Option<Rect> {
+        list.iter()
+            .filter_map(|command| match command {
+                DisplayCommand::SolidColor(c, rect) if *c == color => Some(*rect),
+                _ => None,
+            })
+            .reduce(|a, b| a.union(&b))
+    }
+
+    #[test]
+    fn outline_width_in_ems_sits_outside_the_border_box() {
+        let css = "div { display: block; margin: 5px; width: 10px; height: 10px; \
+                   font-size: 10px; outline-width: 0.2em; outline-color: #ff0000; }";
+        let list = display_list("<div></div>", css, 40.0);
+        let outline = solid_bounds(&list, RED).unwrap();
+        assert_eq!(
+            outline,
+            Rect {
+                x: 3.0,
+                y: 3.0,
+                width: 14.0,
+                height: 14.0
+            }
+        );
+    }
+
+    #[test]
+    fn translate_percentages_are_of_the_border_box() {
+        let css = "div { display: block; width: 10px; height: 20px; background: #ff0000; \
+                   transform: translate(50%, 1em); font-size: 4px; }";
+        let list = display_list("<div></div>", css, 40.0);
+        let background = solid_bounds(&list, RED).unwrap();
+        assert_eq!((background.x, background.y), (5.0, 4.0));
+    }
+
+    #[test]
+    fn translate_lengths_offset_the_background() {
+        let css = "div { display: block; width: 10px; height: 20px; background: #ff0000; \
+                   transform: translate(10px, 20px); }";
+        let list = display_list("<div></div>", css, 40.0);
+        let background = solid_bounds(&list, RED).unwrap();
+        assert_eq!(
+            background,
+            Rect {
+                x: 10.0,
+                y: 20.0,
+                width: 10.0,
+                height: 20.0
+            }
+        );
+    }
+
+    #[test]
+    fn recoloring_one_box_dirties_only_that_box() {
+        let css = "div { display: block; height: 10px; background: #00ff00; } \
+                   #b { background: #0000ff; }";
+        let old = display_list("<div><div id=a></div><div></div></div>", css, 40.0);
+        let new = display_list("<div><div id=b></div><div></div></div>", css, 40.0);
+        let dirty = diff_display_lists(&old, &new);
+        assert_eq!(
+            dirty,
+            vec![Rect {
+                x: 0.0,
+                y: 0.0,
+                width: 40.0,
+                height: 10.0
+            }]
+        );
+    }
+
+    #[test]
+    fn inserting_a_command_dirties_only_its_area() {
+        let css = "p { display: block; position: absolute; top: 0px; width: 10px; \
+                   height: 10px; background: #ff0000; } \
+                   #a { left: 0px; } #b { left: 20px; } #c { left: 40px; }";
+        let old = display_list("<div><p id=a></p><p id=c></p></div>", css, 60.0);
+        let new = display_list("<div><p id=a></p><p id=b></p><p id=c></p></div>", css, 60.0);
+        let dirty = diff_display_lists(&old, &new);
+        assert_eq!(
+            dirty,
+            vec![Rect {
+                x: 20.0,
+                y: 0.0,
+                width: 10.0,
+                height: 10.0
+            }]
+        );
+    }
+
+    /// The colors of `list`'s solid rects, in paint order
+    fn solid_colors(list: &DisplayList) -> Vec<Color> {
+        list.iter()
+            .filter_map(|command| match command {
+                DisplayCommand::SolidColor(color, _) => Some(*color),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn positioned_boxes_paint_in_z_index_order() {
+        let css = "div { display: block; height: 10px; } \
+                   .z { position: relative; } \
+                   #a { z-index: 2; background: #ff0000; } \
+                   #b { z-index: 1; background: #0000ff; } \
+                   #c { z-index: -1; background: #00ff00; } \
+                   #d { background: #ffff00; }";
+        let html = "<div><div class=z id=a></div><div class=z id=b></div>\
+                    <div class=z id=c></div><div id=d></div></div>";
+        let colors = solid_colors(&display_list(html, css, 40.0));
+        let color = |r, g, b| Color { r, g, b, a: 255 };
+        assert_eq!(
+            colors,
+            vec![
+                color(0, 255, 0),
+                color(255, 255, 0),
+                color(0, 0, 255),
+                color(255, 0, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn fixed_boxes_ignore_the_scroll_and_clip_of_their_container() {
+        let css = "div { display: block; margin-top: 20px; height: 50px; overflow: scroll; } \
+                   p { display: block; height: 200px; } \
+                   #f { position: fixed; top: 0px; left: 5px; width: 10px; height: 10px; \
+                   background: #ff0000; }";
+        let document = crate::html::Parser::parse("<div><p></p><p id=f></p></div>".to_string());
+        let stylesheet = crate::css::Parser::parse(css.to_string());
+        let ua_stylesheet = crate::ua::stylesheet();
+        let styled = crate::style::style_tree(&document, &[&stylesheet], &ua_stylesheet, 40.0);
+        let mut viewport = crate::layout::Dimensions::default();
+        viewport.content.width = 40.0;
+        let mut root = crate::layout::layout_tree(&styled, viewport);
+        root.scroll_by(0.0, 30.0);
+        assert_eq!(root.scroll.unwrap().offset_y, 30.0);
+
+        let list = build_display_list(&root);
+        let fixed = solid_bounds(&list, RED).unwrap();
+        assert_eq!((fixed.x, fixed.y), (5.0, 0.0));
+        let mut canvas = Canvas::new(40, 80);
+        canvas.paint(&list);
+        assert_eq!(canvas.pixels[5], RED);
+    }
+
+    #[test]
+    fn a_border_without_a_color_is_painted_in_the_text_color() {
+        let css = "div { display: block; width: 10px; height: 10px; \
+                   border-width: 2px; color: #ff0000; }";
+        let list = display_list("<div></div>", css, 40.0);
+        let border = solid_bounds(&list, RED).unwrap();
+        let current_color = display_list(
+            "<div></div>",
+            &format!("{} div {{ border-color: currentColor; }}", css),
+            40.0,
+        );
+        assert_eq!(solid_bounds(&current_color, RED), Some(border));
+        assert_eq!(
+            border,
+            Rect {
+                x: 0.0,
+                y: 0.0,
+                width: 14.0,
+                height: 14.0
+            }
+        );
+    }
+
+    #[test]
+    fn the_title_is_not_painted() {
+        let html = "<html><head><title>Hi</title></head><body></body></html>";
+        assert!(display_list(html, "", 40.0).is_empty());
+        let html = "<html><head><title>Hi</title></head><body><p>x</p></body></html>";
+        let texts: Vec<String> = display_list(html, "", 40.0)
+            .into_iter()
+            .filter_map(|command| match command {
+                DisplayCommand::Text(text, ..) => Some(text),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts, vec!["x"]);
+    }
+
+    #[test]
+    fn find_matches_is_case_insensitive_and_does_not_overlap() {
+        assert_eq!(find_matches("Hello hello", "hello"), vec![(0, 5), (6, 11)]);
+        assert_eq!(find_matches("aaaa", "aa"), vec![(0, 2), (2, 4)]);
+        assert_eq!(find_matches("aaa", "AA"), vec![(0, 2)]);
+        // Offsets count characters, not bytes
+        assert_eq!(
+            find_matches("caf\u{e9} CAF\u{c9}", "caf\u{e9}"),
+            vec![(0, 4), (5, 9)]
+        );
+        assert!(find_matches("text", "").is_empty());
+        assert!(find_matches("text", "xyz").is_empty());
+    }
+
+    #[test]
+    fn with_background_fills_every_pixel() {
+        let canvas = Canvas::with_background(7, 3, RED);
+        assert_eq!((canvas.width, canvas.height), (7, 3));
+        assert_eq!(canvas.pixels.len(), 21);
+        assert!(canvas.pixels.iter().all(|&pixel| pixel == RED));
+    }
+
+    #[test]
+    fn debug_overlay_outlines_boxes_in_their_type_color() {
+        let document = crate::html::Parser::parse("<div><span>hi</span></div>".to_string());
+        let stylesheet = crate::css::Parser::parse("div { display: block; }".to_string());
+        let ua_stylesheet = crate::ua::stylesheet();
+        let styled = crate::style::style_tree(&document, &[&stylesheet], &ua_stylesheet, 40.0);
+        let mut viewport = crate::layout::Dimensions::default();
+        viewport.content.width = 40.0;
+        let root = crate::layout::layout_tree(&styled, viewport);
+        let list = build_debug_overlay(&root, false);
+
+        let block = Color {
+            r: 0,
+            g: 120,
+            b: 215,
+            a: 255,
+        };
+        let inline = Color {
+            r: 16,
+            g: 160,
+            b: 64,
+            a: 255,
+        };
+        // Four one-pixel bands around the div, and around the span and its text
+        assert_eq!(
+            solid_colors(&list).iter().filter(|&&c| c == block).count(),
+            4
+        );
+        assert_eq!(
+            solid_colors(&list).iter().filter(|&&c| c == inline).count(),
+            8
+        );
+        let line = Rect {
+            x: 0.0,
+            y: 0.0,
+            width: 40.0,
+            height: 14.0,
+        };
+        assert_eq!(solid_bounds(&list, block), Some(line));
+        assert_eq!(
+            solid_bounds(&list, inline),
+            Some(Rect {
+                width: 16.0,
+                ..line
+            })
+        );
+    }
+
+    #[test]
+    fn a_visible_child_of_a_hidden_box_still_paints() {
+        let css = "div { display: block; height: 10px; } \
+                   .hidden { visibility: hidden; background: #0000ff; height: 20px; } \
+                   .shown { visibility: visible; background: #ff0000; }";
+        let list = display_list("<div class=hidden><div class=shown></div></div>", css, 40.0);
+        assert_eq!(solid_colors(&list), vec![RED]);
+        assert_eq!(solid_bounds(&list, RED).map(|rect| rect.height), Some(10.0));
+    }
 }
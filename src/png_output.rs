@@ -0,0 +1,69 @@
+use crate::painting::Canvas;
+use png::{BitDepth, ColorType, Encoder, EncodingError};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+impl Canvas {
+    /// Write the canvas as an 8-bit RGBA PNG image. Alpha is kept as it is,
+    /// so a canvas that started out transparent stays transparent wherever
+    /// nothing was painted.
+    pub fn write_png<W: Write>(&self, w: W) -> Result<(), EncodingError> {
+        let mut encoder = Encoder::new(w, self.width as u32, self.height as u32);
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        let data: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|color| [color.r, color.g, color.b, color.a])
+            .collect();
+        writer.write_image_data(&data)?;
+        writer.finish()
+    }
+
+    /// Save the canvas to a PNG file at `path`
+    pub fn save_png(&self, path: &str) -> Result<(), EncodingError> {
+        self.write_png(BufWriter::new(File::create(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::css::Color;
+    use crate::layout::Rect;
+    use crate::painting::{Canvas, DisplayCommand};
+
+    #[test]
+    fn transparent_background_stays_transparent_in_the_png() {
+        let clear = Color {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 0,
+        };
+        let red = Color {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 255,
+        };
+        let mut canvas = Canvas::with_background(4, 4, clear);
+        let rect = Rect {
+            x: 1.0,
+            y: 1.0,
+            width: 2.0,
+            height: 2.0,
+        };
+        canvas.paint(&vec![DisplayCommand::SolidColor(red, rect)]);
+        let mut bytes = Vec::new();
+        canvas.write_png(&mut bytes).unwrap();
+
+        let mut reader = png::Decoder::new(bytes.as_slice()).read_info().unwrap();
+        let mut data = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut data).unwrap();
+        let pixel = |x: usize, y: usize| &data[(y * 4 + x) * 4..][..4];
+        assert_eq!(pixel(0, 0)[3], 0);
+        assert_eq!(pixel(3, 3)[3], 0);
+        assert_eq!(pixel(1, 1), [255, 0, 0, 255]);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::css::Color;
+use crate::painting::Canvas;
+use std::io::{self, Write};
+
+impl Canvas {
+    /// Write the canvas as a plain-text (P3) PPM image, one row per line,
+    /// without building the whole image in memory first
+    pub fn write_ppm<W: Write>(&self, mut w: W) -> io::Result<()> {
+        writeln!(w, "P3\n{} {}\n255", self.width, self.height)?;
+        if self.width == 0 {
+            return w.flush();
+        }
+        for row in self.pixels.chunks(self.width) {
+            for (i, color) in row.iter().enumerate() {
+                let separator = if i == 0 { "" } else { " " };
+                write!(w, "{}{} {} {}", separator, color.r, color.g, color.b)?;
+            }
+            writeln!(w)?;
+        }
+        w.flush()
+    }
+}
+
+/// Decode a plain-text (P3) PPM image with a max value of 255. `#` comments
+/// are skipped.
+pub fn decode(source: &str) -> Result<Canvas, String> {
+    let mut tokens = source
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(str::split_whitespace);
+
+    if tokens.next() != Some("P3") {
+        return Err("not a plain-text (P3) PPM image".to_string());
+    }
+    let mut number = |what: &str| -> Result<usize, String> {
+        let token = tokens.next().ok_or(format!("missing {}", what))?;
+        token
+            .parse()
+            .map_err(|_| format!("invalid {} {:?}", what, token))
+    };
+
+    let width = number("width")?;
+    let height = number("height")?;
+    if number("max value")? != 255 {
+        return Err("only a max value of 255 is supported".to_string());
+    }
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for _ in 0..width * height {
+        let mut channel = || -> Result<u8, String> {
+            u8::try_from(number("channel")?).map_err(|_| "channel out of range".to_string())
+        };
+        pixels.push(Color {
+            r: channel()?,
+            g: channel()?,
+            b: channel()?,
+            a: 255,
+        });
+    }
+
+    let mut canvas = Canvas::new(width, height);
+    canvas.pixels = pixels;
+    Ok(canvas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_ppm_streams_a_header_and_rows() {
+        let mut canvas = Canvas::new(3, 2);
+        canvas.pixels[4] = Color {
+            r: 255,
+            g: 0,
+            b: 128,
+            a: 255,
+        };
+        let mut buffer = Vec::new();
+        canvas.write_ppm(&mut buffer).unwrap();
+
+        let output = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(&lines[..3], ["P3", "3 2", "255"]);
+        let second_row: Vec<&str> = lines[4].split(' ').collect();
+        assert_eq!(&second_row[3..6], ["255", "0", "128"]);
+        assert_eq!(decode(&output).unwrap().pixels, canvas.pixels);
+    }
+}
@@ -1,24 +1,257 @@
-use crate::css::{Selector, SimpleSelector, Specificity, Stylesheet, Value};
+use crate::css::{
+    self, AttributeOperator, AttributeSelector, Color, Declaration, MediaCondition, MediaQuery,
+    PseudoElement, Selector, SimpleSelector, Specificity, Stylesheet, Unit, Value,
+};
 use crate::dom::{ElementData, Node, NodeType};
-use std::collections::HashMap;
+use crate::network::{self, WebFetcher};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 /// Map from CSS property names to values
 pub type PropertyMap = HashMap<String, Value>;
 
+/// Font size of the root element when the stylesheet doesn't set one
+pub const DEFAULT_FONT_SIZE: f32 = 16.0;
+
+/// Advance of every character, in ems: text is monospaced, so this is also
+/// the size of the `ch` unit
+pub const CHAR_ADVANCE: f32 = 0.5;
+
+/// Width of a tab in spaces when `tab-size` isn't set
+pub const DEFAULT_TAB_SIZE: f32 = 8.0;
+
+/// Properties that children take from their parent unless set themselves
+const INHERITED_PROPERTIES: &[&str] = &[
+    "color",
+    "white-space",
+    "tab-size",
+    "list-style-type",
+    "text-transform",
+    "text-decoration",
+    "font-family",
+    "visibility",
+    "overflow-wrap",
+    "word-break",
+    "text-indent",
+    "letter-spacing",
+    "word-spacing",
+    "font-weight",
+    "font-style",
+];
+
+/// Every property the engine reads; declarations of others have no effect
+const SUPPORTED_PROPERTIES: &[&str] = &[
+    "align-items",
+    "aspect-ratio",
+    "background-color",
+    "background-image",
+    "background-position",
+    "background-repeat",
+    "border-bottom-width",
+    "border-color",
+    "border-left-width",
+    "border-radius",
+    "border-right-width",
+    "border-top-width",
+    "border-width",
+    "bottom",
+    "color",
+    "content",
+    "display",
+    "flex-direction",
+    "font-family",
+    "font-size",
+    "font-style",
+    "font-weight",
+    "height",
+    "left",
+    "letter-spacing",
+    "list-style-type",
+    "margin",
+    "margin-bottom",
+    "margin-left",
+    "margin-right",
+    "margin-top",
+    "outline-color",
+    "outline-width",
+    "overflow",
+    "overflow-wrap",
+    "padding",
+    "padding-bottom",
+    "padding-left",
+    "padding-right",
+    "padding-top",
+    "position",
+    "right",
+    "tab-size",
+    "text-decoration",
+    "text-indent",
+    "text-transform",
+    "top",
+    "transform",
+    "vertical-align",
+    "visibility",
+    "white-space",
+    "width",
+    "word-break",
+    "word-spacing",
+    "z-index",
+];
+
+/// Whether the engine does anything with a property
+pub fn is_supported_property(name: &str) -> bool {
+    SUPPORTED_PROPERTIES.contains(&name)
+}
+
 /// A node with associated style data
 #[derive(Debug)]
 pub struct StyledNode<'a> {
-    #[allow(dead_code)]
     pub node: &'a Node,
     pub specified_values: PropertyMap,
     pub children: Vec<StyledNode<'a>>,
+    /// Set for a `::before`/`::after` box, whose `node` is then the element
+    /// it was generated for
+    pub generated_text: Option<String>,
 }
 
 #[derive(PartialEq)]
 pub enum Display {
     Inline,
     Block,
+    InlineBlock,
+    /// A block with a marker, like `<li>`
+    ListItem,
+    /// A block whose children are laid out in a row or column
+    Flex,
+    /// A row of table cells, side by side and as tall as the tallest
+    TableRow,
+    /// A block that fills its share of a table row
+    TableCell,
+    None,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FlexDirection {
+    Row,
+    Column,
+}
+
+/// Where a table cell's content sits within its row. There are no font
+/// metrics to find a baseline with, so `baseline` is the same as `top`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VerticalAlign {
+    Top,
+    Middle,
+    Bottom,
+}
+
+/// Where flex items sit across the flex container's main axis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlignItems {
+    /// Fill the container's cross size, unless the item's size is set
+    Stretch,
+    FlexStart,
+    Center,
+    FlexEnd,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Position {
+    Static,
+    Relative,
+    Absolute,
+    /// Positioned against the viewport, so it stays put when content scrolls
+    Fixed,
+}
+
+impl Position {
+    /// Whether the box is taken out of normal flow
+    pub fn is_out_of_flow(self) -> bool {
+        matches!(self, Position::Absolute | Position::Fixed)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WhiteSpace {
+    Normal,
+    /// Keep every space, tab, and newline, and don't wrap
+    Pre,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OverflowWrap {
+    Normal,
+    /// Split words too long for a line rather than letting them overflow
+    BreakWord,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextTransform {
     None,
+    Uppercase,
+    Lowercase,
+    /// Uppercase the first letter of each word
+    Capitalize,
+}
+
+impl TextTransform {
+    /// Change the case of some text, leaving its whitespace untouched
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            TextTransform::None => text.to_string(),
+            TextTransform::Uppercase => text.to_uppercase(),
+            TextTransform::Lowercase => text.to_lowercase(),
+            TextTransform::Capitalize => {
+                let mut result = String::with_capacity(text.len());
+                let mut word_start = true;
+                for c in text.chars() {
+                    if word_start && c.is_alphanumeric() {
+                        result.extend(c.to_uppercase());
+                    } else {
+                        result.push(c);
+                    }
+                    word_start = c.is_whitespace();
+                }
+                result
+            }
+        }
+    }
+}
+
+/// How a run of text's glyphs are drawn, from `font-size`, `font-weight`,
+/// and `font-style`. There's a single font, so bold and italic are faked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FontFace {
+    /// In px
+    pub size: f32,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+impl Default for FontFace {
+    fn default() -> FontFace {
+        FontFace {
+            size: DEFAULT_FONT_SIZE,
+            bold: false,
+            italic: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Visibility {
+    Visible,
+    /// Laid out as usual but not painted
+    Hidden,
+    /// Like `Hidden`, as there are no table rows or columns to collapse
+    Collapse,
+}
+
+#[derive(PartialEq)]
+pub enum Overflow {
+    Visible,
+    Hidden,
+    Scroll,
+    Auto,
 }
 
 impl<'a> StyledNode<'a> {
@@ -27,29 +260,239 @@ impl<'a> StyledNode<'a> {
         self.specified_values.get(name).cloned()
     }
 
+    /// The text this node renders: a text node's own, or the `content` of a
+    /// `::before`/`::after` box
+    pub fn text(&self) -> Option<&str> {
+        match (&self.generated_text, &self.node.node_type) {
+            (Some(text), _) | (None, NodeType::Text(text)) => Some(text),
+            (None, NodeType::Element(_)) => None,
+        }
+    }
+
     /// Get the display property value
     pub fn display(&self) -> Display {
+        let display = self.specified_display();
+        // Absolutely positioned (and fixed) boxes are always block-level
+        match display {
+            Display::Inline | Display::InlineBlock if self.position().is_out_of_flow() => {
+                Display::Block
+            }
+            display => display,
+        }
+    }
+
+    fn specified_display(&self) -> Display {
         match self.value("display") {
             Some(Value::Keyword(s)) => match s.as_str() {
                 "block" => Display::Block,
+                "inline-block" => Display::InlineBlock,
+                "list-item" => Display::ListItem,
+                "flex" => Display::Flex,
+                // Rows are all a table lays out, so tables and row groups
+                // just stack them
+                "table" | "table-row-group" | "table-header-group" | "table-footer-group" => {
+                    Display::Block
+                }
+                "table-row" => Display::TableRow,
+                "table-cell" => Display::TableCell,
                 "none" => Display::None,
                 _ => Display::Inline,
             },
-            _ => {
-                // Default block-level elements to display: block
-                match self.node.node_type {
-                    NodeType::Element(ref elem) => match elem.tag_name.as_str() {
-                        "html" | "body" | "div" | "p" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" 
-                        | "ul" | "ol" | "li" | "header" | "footer" | "section" | "article" 
-                        | "nav" | "aside" | "main" => Display::Block,
-                        _ => Display::Inline,
-                    },
-                    NodeType::Text(_) => Display::Inline,
-                }
-            }
+            // Elements' default displays come from `ElementDefaults`
+            _ => Display::Inline,
+        }
+    }
+
+    /// Get the flex-direction property value
+    pub fn flex_direction(&self) -> FlexDirection {
+        match self.value("flex-direction") {
+            Some(Value::Keyword(s)) if s == "column" => FlexDirection::Column,
+            _ => FlexDirection::Row,
+        }
+    }
+
+    /// Get the align-items property value
+    pub fn align_items(&self) -> AlignItems {
+        match self.value("align-items") {
+            Some(Value::Keyword(s)) => match s.as_str() {
+                "flex-start" | "start" => AlignItems::FlexStart,
+                "center" => AlignItems::Center,
+                "flex-end" | "end" => AlignItems::FlexEnd,
+                _ => AlignItems::Stretch,
+            },
+            _ => AlignItems::Stretch,
+        }
+    }
+
+    /// Get the vertical-align property value
+    pub fn vertical_align(&self) -> VerticalAlign {
+        match self.value("vertical-align") {
+            Some(Value::Keyword(s)) => match s.as_str() {
+                "middle" => VerticalAlign::Middle,
+                "bottom" => VerticalAlign::Bottom,
+                _ => VerticalAlign::Top,
+            },
+            _ => VerticalAlign::Top,
+        }
+    }
+
+    /// The `z-index` of a positioned box, with `auto` as 0
+    pub fn z_index(&self) -> i32 {
+        match self.value("z-index") {
+            Some(Value::Number(n)) => n as i32,
+            _ => 0,
+        }
+    }
+
+    /// Get the position property value
+    pub fn position(&self) -> Position {
+        match self.value("position") {
+            Some(Value::Keyword(s)) => match s.as_str() {
+                "relative" => Position::Relative,
+                "absolute" => Position::Absolute,
+                "fixed" => Position::Fixed,
+                _ => Position::Static,
+            },
+            _ => Position::Static,
+        }
+    }
+
+    /// Get the overflow property value
+    pub fn overflow(&self) -> Overflow {
+        match self.value("overflow") {
+            Some(Value::Keyword(s)) => match s.as_str() {
+                "hidden" => Overflow::Hidden,
+                "scroll" => Overflow::Scroll,
+                "auto" => Overflow::Auto,
+                _ => Overflow::Visible,
+            },
+            _ => Overflow::Visible,
+        }
+    }
+
+    /// Get the white-space property value
+    pub fn white_space(&self) -> WhiteSpace {
+        if self.node.whitespace_significant {
+            return WhiteSpace::Pre;
+        }
+        match self.value("white-space") {
+            Some(Value::Keyword(s)) if s == "pre" => WhiteSpace::Pre,
+            _ => WhiteSpace::Normal,
+        }
+    }
+
+    /// Get the visibility property value
+    pub fn visibility(&self) -> Visibility {
+        match self.value("visibility") {
+            Some(Value::Keyword(s)) => match s.as_str() {
+                "hidden" => Visibility::Hidden,
+                "collapse" => Visibility::Collapse,
+                _ => Visibility::Visible,
+            },
+            _ => Visibility::Visible,
+        }
+    }
+
+    /// Get the overflow-wrap property value, also honoring the older
+    /// `word-break: break-word` spelling
+    pub fn overflow_wrap(&self) -> OverflowWrap {
+        let keyword = |name| match self.value(name) {
+            Some(Value::Keyword(s)) => s,
+            _ => String::new(),
+        };
+        match (
+            keyword("overflow-wrap").as_str(),
+            keyword("word-break").as_str(),
+        ) {
+            ("break-word" | "anywhere", _) | (_, "break-word") => OverflowWrap::BreakWord,
+            _ => OverflowWrap::Normal,
+        }
+    }
+
+    /// The face to draw this node's text in. Weights of 600 and up count
+    /// as bold, and `oblique` as italic.
+    pub fn font_face(&self) -> FontFace {
+        let bold = match self.value("font-weight") {
+            Some(Value::Keyword(s)) => s == "bold" || s == "bolder",
+            Some(Value::Number(weight)) => weight >= 600.0,
+            _ => false,
+        };
+        let italic = matches!(
+            self.value("font-style"),
+            Some(Value::Keyword(s)) if s == "italic" || s == "oblique"
+        );
+        FontFace {
+            size: self.font_size(),
+            bold,
+            italic,
+        }
+    }
+
+    /// Get the text-transform property value
+    pub fn text_transform(&self) -> TextTransform {
+        match self.value("text-transform") {
+            Some(Value::Keyword(s)) => match s.as_str() {
+                "uppercase" => TextTransform::Uppercase,
+                "lowercase" => TextTransform::Lowercase,
+                "capitalize" => TextTransform::Capitalize,
+                _ => TextTransform::None,
+            },
+            _ => TextTransform::None,
+        }
+    }
+
+    /// Width of a tab character, in spaces
+    pub fn tab_size(&self) -> f32 {
+        match self.value("tab-size") {
+            Some(Value::Number(n)) if n >= 0.0 => n,
+            _ => DEFAULT_TAB_SIZE,
+        }
+    }
+
+    /// The text of this list item's marker, given its position among its
+    /// siblings (from 1), or `None` for `list-style-type: none`
+    pub fn marker_text(&self, ordinal: usize) -> Option<String> {
+        let style_type = match self.value("list-style-type") {
+            Some(Value::Keyword(s)) => s,
+            _ => "disc".to_string(),
+        };
+        match style_type.as_str() {
+            "none" => None,
+            "decimal" => Some(format!("{}.", ordinal)),
+            "circle" => Some("◦".to_string()),
+            "square" => Some("▪".to_string()),
+            _ => Some("•".to_string()),
         }
     }
 
+    /// Computed font size in px
+    pub fn font_size(&self) -> f32 {
+        match self.value("font-size") {
+            Some(Value::Length(size, Unit::Px)) => size,
+            _ => DEFAULT_FONT_SIZE,
+        }
+    }
+
+    /// Iterate over every descendant of this node (not the node itself) in
+    /// document order
+    pub fn descendants(&self) -> Descendants<'_, 'a> {
+        Descendants {
+            stack: self.children.iter().rev().collect(),
+        }
+    }
+
+    /// This node's final property values as CSS text, sorted by property
+    /// name: colors as `#rrggbb` (or `rgba()` when translucent) and lengths
+    /// as `Npx`. Lengths that depend on the root element, the viewport, or
+    /// layout (`rem`, `vw`, `vh`, and percentages) keep their own units.
+    pub fn computed_values(&self) -> BTreeMap<String, String> {
+        let font_size = self.font_size();
+        self.specified_values
+            .iter()
+            .map(|(name, value)| (name.clone(), computed_value_text(value, font_size)))
+            .collect()
+    }
+
     /// Look up a value or return a default
     pub fn lookup(&self, name: &str, fallback_name: &str, default: &Value) -> Value {
         self.value(name)
@@ -58,128 +501,853 @@ impl<'a> StyledNode<'a> {
     }
 }
 
-/// Apply a stylesheet to a DOM tree, creating a styled tree
-pub fn style_tree<'a>(root: &'a Node, stylesheet: &'a Stylesheet) -> StyledNode<'a> {
-    style_tree_with_parent(root, stylesheet, None)
+/// Serialize a value, resolving font-relative lengths against `font_size`
+fn computed_value_text(value: &Value, font_size: f32) -> String {
+    match value {
+        Value::Keyword(keyword) => keyword.clone(),
+        Value::Str(text) => format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\"")),
+        Value::Url(url) => format!(
+            "url(\"{}\")",
+            url.replace('\\', "\\\\").replace('"', "\\\"")
+        ),
+        Value::Length(n, Unit::Px) => format!("{}px", n),
+        Value::Length(n, Unit::Em) => format!("{}px", n * font_size),
+        Value::Length(n, Unit::Ex) => format!("{}px", n * font_size / 2.0),
+        Value::Length(n, Unit::Ch) => format!("{}px", n * CHAR_ADVANCE * font_size),
+        Value::Length(n, Unit::Pt) => format!("{}px", n * 96.0 / 72.0),
+        // The root's font size isn't known to a single styled node
+        Value::Length(n, Unit::Rem) => format!("{}rem", n),
+        // What a percentage is of depends on the property and the layout
+        Value::Length(n, Unit::Percent) => format!("{}%", n),
+        // The viewport can be resized after styling, so these resolve in layout
+        Value::Length(n, Unit::Vw) => format!("{}vw", n),
+        Value::Length(n, Unit::Vh) => format!("{}vh", n),
+        Value::Number(n) => n.to_string(),
+        Value::Color(color) if color.a == 255 => color.to_string(),
+        Value::Color(color) => format!(
+            "rgba({}, {}, {}, {})",
+            color.r,
+            color.g,
+            color.b,
+            (color.a as f32 / 255.0 * 1000.0).round() / 1000.0
+        ),
+        Value::ColorList(colors) => colors
+            .iter()
+            .map(|color| computed_value_text(&Value::Color(*color), font_size))
+            .collect::<Vec<_>>()
+            .join(", "),
+        Value::Translate(x, y) => format!(
+            "translate({}, {})",
+            computed_value_text(x, font_size),
+            computed_value_text(y, font_size)
+        ),
+        Value::Ratio(width, height) => format!("{} / {}", width, height),
+        Value::Position(x, y) => format!(
+            "{} {}",
+            computed_value_text(x, font_size),
+            computed_value_text(y, font_size)
+        ),
+    }
+}
+
+/// Pre-order iterator over a styled node's descendants
+pub struct Descendants<'s, 'a> {
+    /// Nodes still to visit, next on top
+    stack: Vec<&'s StyledNode<'a>>,
+}
+
+impl<'s, 'a> Iterator for Descendants<'s, 'a> {
+    type Item = &'s StyledNode<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.stack.extend(node.children.iter().rev());
+        Some(node)
+    }
+}
+
+/// Where a stylesheet comes from, in increasing order of precedence for
+/// normal declarations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Origin {
+    UserAgent,
+    Author,
+    /// Local overrides, like `--user-css`, which beat the page's own rules
+    User,
+}
+
+impl Origin {
+    /// Position in the cascade. `!important` declarations rank above every
+    /// normal one, with the origin order reversed: the page's own, then the
+    /// user's, then the UA's.
+    fn cascade_level(self, important: bool) -> u8 {
+        match (self, important) {
+            (Origin::UserAgent, false) => 0,
+            (Origin::Author, false) => 1,
+            (Origin::User, false) => 2,
+            (Origin::Author, true) => 3,
+            (Origin::User, true) => 4,
+            (Origin::UserAgent, true) => 5,
+        }
+    }
+}
+
+/// What relative lengths are resolved against
+#[derive(Clone, Copy, Debug)]
+pub struct LengthContext {
+    pub font_size: f32,
+    pub root_font_size: f32,
+    pub viewport_width: f32,
+    pub viewport_height: f32,
+    /// The size percentages are relative to
+    pub percent_base: f32,
+}
+
+impl LengthContext {
+    /// Convert a length in any unit to an absolute px length, leaving other
+    /// values (like `auto`) unchanged
+    pub fn absolute(&self, value: Value) -> Value {
+        match value {
+            Value::Length(..) => Value::Length(resolve_length(&value, self), Unit::Px),
+            other => other,
+        }
+    }
+
+    /// Resolve a `width` or `height` to px, or `None` if it's `auto`. A
+    /// missing value is `auto`, and so is anything else that isn't a length
+    /// (like `none`), as an invalid declaration would be ignored. A unitless
+    /// `0` is a length.
+    pub fn size(&self, value: Option<Value>) -> Option<f32> {
+        match value {
+            Some(length @ Value::Length(..)) => Some(resolve_length(&length, self)),
+            Some(Value::Number(0.0)) => Some(0.0),
+            _ => None,
+        }
+    }
+}
+
+/// Resolve a length in any unit to px. Font-relative units use the simple
+/// text metrics: `ex` is half an em, and `ch` is a character's advance.
+pub fn resolve_length(value: &Value, context: &LengthContext) -> f32 {
+    match *value {
+        Value::Length(n, Unit::Px) => n,
+        Value::Length(n, Unit::Em) => n * context.font_size,
+        Value::Length(n, Unit::Rem) => n * context.root_font_size,
+        Value::Length(n, Unit::Ex) => n * context.font_size / 2.0,
+        Value::Length(n, Unit::Ch) => n * CHAR_ADVANCE * context.font_size,
+        Value::Length(n, Unit::Pt) => n * 96.0 / 72.0,
+        Value::Length(n, Unit::Percent) => n / 100.0 * context.percent_base,
+        Value::Length(n, Unit::Vw) => n / 100.0 * context.viewport_width,
+        Value::Length(n, Unit::Vh) => n / 100.0 * context.viewport_height,
+        _ => 0.0,
+    }
+}
+
+/// Default styles for elements by tag name, beneath every stylesheet. The
+/// `Default` set covers the HTML elements this engine knows; embedders can
+/// `register` others, like custom elements, which are otherwise inline.
+#[derive(Clone, Debug)]
+pub struct ElementDefaults {
+    styles: HashMap<String, PropertyMap>,
+}
+
+impl ElementDefaults {
+    /// Give elements named `tag_name` a default `display` (a keyword such
+    /// as `block`) and other property values, replacing any registered
+    /// before
+    pub fn register(&mut self, tag_name: &str, display: &str, mut properties: PropertyMap) {
+        properties.insert("display".to_string(), Value::Keyword(display.to_string()));
+        self.styles.insert(tag_name.to_lowercase(), properties);
+    }
+}
+
+impl Default for ElementDefaults {
+    fn default() -> Self {
+        let mut defaults = ElementDefaults {
+            styles: HashMap::new(),
+        };
+        let blocks = [
+            "html", "body", "div", "p", "h2", "h3", "h4", "h5", "h6", "ul", "ol", "header",
+            "footer", "section", "article", "nav", "aside", "main", "table", "thead", "tbody",
+            "tfoot",
+        ];
+        for tag_name in blocks {
+            defaults.register(tag_name, "block", PropertyMap::new());
+        }
+        let color = |r, g, b| {
+            let color = Value::Color(Color { r, g, b, a: 255 });
+            PropertyMap::from([("color".to_string(), color)])
+        };
+        defaults.register("h1", "block", color(0, 0, 0));
+        defaults.register("li", "list-item", PropertyMap::new());
+        defaults.register("tr", "table-row", PropertyMap::new());
+        defaults.register("td", "table-cell", PropertyMap::new());
+        defaults.register("th", "table-cell", PropertyMap::new());
+        // Links default to blue
+        defaults.register("a", "inline", color(0, 0, 238));
+        defaults
+    }
+}
+
+/// Which colors the user prefers pages to use, for `prefers-color-scheme`
+/// queries
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ColorScheme {
+    #[default]
+    Light,
+    Dark,
+}
+
+/// The stylesheets of each cascade origin, and the document state they're
+/// applied in
+pub struct CascadeContext<'s> {
+    pub user_agent: &'s Stylesheet,
+    pub user: Option<&'s Stylesheet>,
+    /// The page's stylesheets, in document order; a later sheet's rules
+    /// win ties with an earlier one's
+    pub author: &'s [&'s Stylesheet],
+    /// Styles beneath all the stylesheets, by tag name
+    pub element_defaults: &'s ElementDefaults,
+    /// The element under the pointer; it and its ancestors match `:hover`
+    pub hovered: Option<&'s Node>,
+    /// Width of the viewport in px, for `@media` queries
+    pub viewport_width: f32,
+    /// The user's preferred color scheme, for `@media` queries; the UA
+    /// stylesheet has dark defaults for `ColorScheme::Dark`
+    pub color_scheme: ColorScheme,
+    /// URLs visited so far; links to them match `:visited`
+    pub visited: Option<&'s HashSet<String>>,
+    /// URL of the document, which link `href`s are resolved against
+    pub base_url: Option<&'s str>,
+}
+
+/// What selectors can test about an element beyond its own data
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ElementState {
+    /// The element is the root of the document tree
+    pub is_root: bool,
+    /// The pointer is over the element or one of its descendants
+    pub hovered: bool,
+    /// The element is a link to a visited URL
+    pub visited: bool,
+    /// Styles are being computed for this pseudo-element of the element
+    pub pseudo_element: Option<PseudoElement>,
+}
+
+impl<'s> CascadeContext<'s> {
+    /// Whether a link's `href` (resolved against the document's URL) has
+    /// been visited
+    fn is_visited(&self, href: &str) -> bool {
+        let Some(visited) = self.visited else {
+            return false;
+        };
+        let url = self
+            .base_url
+            .and_then(|base| WebFetcher::resolve_url(base, href).ok())
+            .unwrap_or_else(|| href.to_string());
+        visited.contains(&url)
+    }
+
+    /// Stylesheets paired with their origin, lowest precedence first
+    fn sheets(&self) -> Vec<(Origin, &'s Stylesheet)> {
+        let mut sheets = vec![(Origin::UserAgent, self.user_agent)];
+        sheets.extend(self.author.iter().map(|&sheet| (Origin::Author, sheet)));
+        sheets.extend(self.user.map(|sheet| (Origin::User, sheet)));
+        sheets
+    }
+}
+
+/// Apply stylesheets to a DOM tree, creating a styled tree. The sheets
+/// cascade in the order given, so at equal specificity a later sheet wins.
+/// `ua_stylesheet` supplies the defaults beneath the author's rules;
+/// `ua::stylesheet()` is the built-in one. `@media` rules are matched
+/// against `viewport_width`.
+pub fn style_tree<'a>(
+    root: &'a Node,
+    stylesheets: &[&Stylesheet],
+    ua_stylesheet: &Stylesheet,
+    viewport_width: f32,
+) -> StyledNode<'a> {
+    let element_defaults = ElementDefaults::default();
+    let cascade = CascadeContext {
+        user_agent: ua_stylesheet,
+        user: None,
+        author: stylesheets,
+        element_defaults: &element_defaults,
+        hovered: None,
+        viewport_width,
+        color_scheme: ColorScheme::Light,
+        visited: None,
+        base_url: None,
+    };
+    style_tree_with_cascade(root, &cascade)
+}
+
+/// Apply the stylesheets of every cascade origin to a DOM tree
+pub fn style_tree_with_cascade<'a>(root: &'a Node, cascade: &CascadeContext) -> StyledNode<'a> {
+    let hover_path = cascade
+        .hovered
+        .and_then(|hovered| root.path_to(hovered))
+        .unwrap_or_default();
+    style_tree_with_parent(root, cascade, &hover_path, None, DEFAULT_FONT_SIZE)
 }
 
+/// Style a subtree. `root_font_size` is the root element's computed
+/// font-size, which `rem` lengths refer to; the root itself resolves `rem`
+/// against the default size. `hover_path` runs from the document root to the
+/// hovered element.
 fn style_tree_with_parent<'a>(
     node: &'a Node,
-    stylesheet: &'a Stylesheet,
+    cascade: &CascadeContext,
+    hover_path: &[&Node],
     parent_values: Option<&PropertyMap>,
+    root_font_size: f32,
 ) -> StyledNode<'a> {
+    // Only the root has no parent to inherit from
+    let is_root = parent_values.is_none();
+    let state = ElementState {
+        is_root,
+        hovered: hover_path
+            .iter()
+            .any(|hovered| std::ptr::eq(*hovered, node)),
+        visited: match node.node_type {
+            NodeType::Element(ref elem) => {
+                link_href(elem).is_some_and(|href| cascade.is_visited(href))
+            }
+            NodeType::Text(_) => false,
+        },
+        pseudo_element: None,
+    };
     let mut specified_values = match node.node_type {
-        NodeType::Element(ref elem) => specified_values(elem, stylesheet),
+        NodeType::Element(ref elem) => specified_values(elem, state, cascade),
         NodeType::Text(_) => HashMap::new(),
     };
+    compute_values(&mut specified_values, parent_values, root_font_size);
+
+    let root_font_size = match (is_root, specified_values.get("font-size")) {
+        (true, Some(Value::Length(size, Unit::Px))) => *size,
+        _ => root_font_size,
+    };
+    let generate = |pseudo_element| match node.node_type {
+        NodeType::Element(ref elem) => {
+            let state = ElementState {
+                pseudo_element: Some(pseudo_element),
+                ..state
+            };
+            generated_box(
+                node,
+                elem,
+                state,
+                cascade,
+                &specified_values,
+                root_font_size,
+            )
+        }
+        NodeType::Text(_) => None,
+    };
+    let mut children: Vec<StyledNode> = generate(PseudoElement::Before).into_iter().collect();
+    children.extend(node.children.iter().map(|child| {
+        style_tree_with_parent(
+            child,
+            cascade,
+            hover_path,
+            Some(&specified_values),
+            root_font_size,
+        )
+    }));
+    children.extend(generate(PseudoElement::After));
 
-    // Inherit color from parent
+    StyledNode {
+        node,
+        specified_values,
+        children,
+        generated_text: None,
+    }
+}
+
+/// Turn the values the cascade picked for a node into the ones it uses:
+/// expand `all`, inherit from the parent, and resolve CSS-wide keywords,
+/// `currentColor`, and font sizes
+fn compute_values(
+    values: &mut PropertyMap,
+    parent_values: Option<&PropertyMap>,
+    root_font_size: f32,
+) {
+    expand_all_shorthand(values, parent_values);
+
+    // Inherit from parent
     if let Some(parent) = parent_values {
-        if let Some(parent_color) = parent.get("color") {
-            specified_values.entry("color".to_string()).or_insert(parent_color.clone());
+        for &name in INHERITED_PROPERTIES {
+            if let Some(value) = parent.get(name) {
+                values.entry(name.to_string()).or_insert(value.clone());
+            }
         }
     }
 
-    let children = node
-        .children
+    resolve_css_wide_keywords(values, parent_values);
+    resolve_current_color(values, parent_values);
+    resolve_font_size(values, parent_values, root_font_size);
+}
+
+/// The inline box an element's `::before` or `::after` rules generate (as
+/// `state.pseudo_element` says), if they give it a string `content`
+fn generated_box<'a>(
+    node: &'a Node,
+    elem: &ElementData,
+    state: ElementState,
+    cascade: &CascadeContext,
+    element_values: &PropertyMap,
+    root_font_size: f32,
+) -> Option<StyledNode<'a>> {
+    let mut values = specified_values(elem, state, cascade);
+    let text = match values.get("content") {
+        Some(Value::Str(text)) => text.clone(),
+        _ => return None,
+    };
+    compute_values(&mut values, Some(element_values), root_font_size);
+    // Otherwise the element's tag would decide its default display
+    values
+        .entry("display".to_string())
+        .or_insert_with(|| Value::Keyword("inline".to_string()));
+    Some(StyledNode {
+        node,
+        specified_values: values,
+        children: Vec::new(),
+        generated_text: Some(text),
+    })
+}
+
+/// Whether a property takes its parent's value when not set. Font sizes are
+/// inherited too, though `resolve_font_size` handles that.
+fn is_inherited(name: &str) -> bool {
+    name == "font-size" || INHERITED_PROPERTIES.contains(&name)
+}
+
+/// Spread `all: <keyword>` over every property the element doesn't set
+/// itself. Only properties that would otherwise be inherited need it, or
+/// with `inherit`, any property the parent has.
+fn expand_all_shorthand(values: &mut PropertyMap, parent_values: Option<&PropertyMap>) {
+    let keyword = match values.remove("all") {
+        Some(Value::Keyword(keyword)) => keyword,
+        _ => return,
+    };
+    let mut names: Vec<String> = INHERITED_PROPERTIES
         .iter()
-        .map(|child| style_tree_with_parent(child, stylesheet, Some(&specified_values)))
+        .chain(&["font-size"])
+        .map(|name| name.to_string())
         .collect();
+    if keyword == "inherit" {
+        names.extend(
+            parent_values
+                .into_iter()
+                .flat_map(|parent| parent.keys().cloned()),
+        );
+    }
+    for name in names {
+        values
+            .entry(name)
+            .or_insert_with(|| Value::Keyword(keyword.clone()));
+    }
+}
 
-    StyledNode {
-        node,
-        specified_values,
-        children,
+/// Apply `inherit`, which takes the parent's value for any property,
+/// `initial`, which resets a property to its default, and `unset`, which
+/// acts as `inherit` for inherited properties and `initial` otherwise.
+/// Defaults are what the engine assumes for a missing value, so `initial`
+/// mostly removes it.
+fn resolve_css_wide_keywords(values: &mut PropertyMap, parent_values: Option<&PropertyMap>) {
+    let keyword = |name: &str, value: &Value| match value {
+        Value::Keyword(keyword) if keyword == "unset" && is_inherited(name) => {
+            Some("inherit".to_string())
+        }
+        Value::Keyword(keyword) if keyword == "unset" => Some("initial".to_string()),
+        Value::Keyword(keyword) if keyword == "inherit" || keyword == "initial" => {
+            Some(keyword.clone())
+        }
+        _ => None,
+    };
+    let names: Vec<(String, String)> = values
+        .iter()
+        .filter_map(|(name, value)| Some((name.clone(), keyword(name, value)?)))
+        .collect();
+
+    for (name, keyword) in names {
+        let inherited = match keyword.as_str() {
+            "inherit" => parent_values.and_then(|parent| parent.get(&name)).cloned(),
+            // Otherwise font-size would fall back to the parent's size
+            _ if name == "font-size" => Some(Value::Length(DEFAULT_FONT_SIZE, Unit::Px)),
+            _ => None,
+        };
+        match inherited {
+            Some(value) => values.insert(name, value),
+            None => values.remove(&name),
+        };
+    }
+}
+
+/// Replace `currentColor` with the element's own `color`. On `color` itself
+/// it means the parent's color.
+fn resolve_current_color(values: &mut PropertyMap, parent_values: Option<&PropertyMap>) {
+    let current_color = Value::Keyword("currentColor".to_string());
+    if values.get("color") == Some(&current_color) {
+        match parent_values.and_then(|parent| parent.get("color")) {
+            Some(parent_color) => values.insert("color".to_string(), parent_color.clone()),
+            None => values.remove("color"),
+        };
+    }
+
+    let color = values.get("color").cloned().unwrap_or(Value::Color(Color {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 255,
+    }));
+    for value in values.values_mut() {
+        if *value == current_color {
+            *value = color.clone();
+        }
     }
 }
 
+/// Compute font-size in px from the parent's (or the root's, for `rem`), so
+/// that descendants inherit an absolute size and em lengths can be resolved
+/// against it
+fn resolve_font_size(
+    values: &mut PropertyMap,
+    parent_values: Option<&PropertyMap>,
+    root_font_size: f32,
+) {
+    let parent_size = match parent_values.and_then(|parent| parent.get("font-size")) {
+        Some(Value::Length(size, Unit::Px)) => *size,
+        _ => DEFAULT_FONT_SIZE,
+    };
+    // Font-relative units (and percentages) refer to the parent's font
+    let context = LengthContext {
+        font_size: parent_size,
+        root_font_size,
+        viewport_width: 0.0,
+        viewport_height: 0.0,
+        percent_base: parent_size,
+    };
+    let size = match values.get("font-size") {
+        // The viewport's size isn't known until layout
+        Some(Value::Length(_, Unit::Vw | Unit::Vh)) => parent_size,
+        Some(length @ Value::Length(..)) => resolve_length(length, &context),
+        _ => parent_size,
+    };
+    values.insert("font-size".to_string(), Value::Length(size, Unit::Px));
+}
+
 /// Get the specified values for a single element
-fn specified_values(elem: &ElementData, stylesheet: &Stylesheet) -> PropertyMap {
+fn specified_values(
+    elem: &ElementData,
+    state: ElementState,
+    cascade: &CascadeContext,
+) -> PropertyMap {
     let mut values = HashMap::new();
-    
-    // Apply default styles for certain elements
-    apply_default_styles(elem, &mut values);
-    
-    let mut rules = matching_rules(elem, stylesheet);
 
-    // Sort by specificity (higher specificity last)
-    rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+    // Apply default styles for certain elements (but not their
+    // pseudo-elements)
+    if state.pseudo_element.is_none() {
+        apply_default_styles(elem, cascade.element_defaults, &mut values);
+    }
 
-    for (_, rule) in rules {
-        for declaration in &rule.declarations {
-            values.insert(declaration.name.clone(), declaration.value.clone());
+    // A `style` attribute styles the element itself, not its pseudo-elements
+    let inline = match (state.pseudo_element, elem.get_attribute("style")) {
+        (None, Some(style)) => inline_declarations(style, cascade.base_url),
+        _ => Vec::new(),
+    };
+
+    let mut declarations = Vec::new();
+    for (origin, sheet) in cascade.sheets() {
+        for (specificity, rule) in matching_rules(elem, state, sheet, Some(cascade)) {
+            for declaration in &rule.declarations {
+                let level = origin.cascade_level(declaration.important);
+                declarations.push((level, specificity, declaration));
+            }
         }
     }
+
+    // Inline declarations are author rules that outrank any selector
+    for declaration in &inline {
+        let level = Origin::Author.cascade_level(declaration.important);
+        declarations.push((level, (usize::MAX, 0, 0), declaration));
+    }
+
+    // Sort by cascade level, then specificity (winners last). The sort is
+    // stable, so later rules still win ties.
+    declarations.sort_by_key(|&(level, specificity, _)| (level, specificity));
+
+    for (_, _, declaration) in declarations {
+        // `all` overrides everything before it
+        if declaration.name == "all" {
+            values.clear();
+        }
+        // A shorthand overrides its longhands from earlier declarations
+        for longhand in longhands(&declaration.name) {
+            values.remove(&longhand);
+        }
+        values.insert(declaration.name.clone(), declaration.value.clone());
+    }
     values
 }
 
+/// The declarations in a `style` attribute, with `url(...)` values resolved
+/// against the document's URL. A malformed attribute is ignored.
+fn inline_declarations(style: &str, base_url: Option<&str>) -> Vec<Declaration> {
+    let mut declarations = match css::parse_declarations(style) {
+        Ok(declarations) => declarations,
+        Err(e) => {
+            eprintln!("Warning: Ignoring style attribute: {}", e);
+            return Vec::new();
+        }
+    };
+    if let Some(base_url) = base_url {
+        network::resolve_declaration_urls(&mut declarations, base_url);
+    }
+    declarations
+}
+
+/// The per-side properties a box shorthand like `padding` sets
+fn longhands(name: &str) -> Vec<String> {
+    let sides = ["top", "right", "bottom", "left"];
+    match name {
+        "margin" | "padding" => sides
+            .iter()
+            .map(|side| format!("{}-{}", name, side))
+            .collect(),
+        "border-width" => sides
+            .iter()
+            .map(|side| format!("border-{}-width", side))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
 /// Apply default browser styles for certain elements
-fn apply_default_styles(elem: &ElementData, values: &mut PropertyMap) {
-    use crate::css::{Color, Value};
-    
-    match elem.tag_name.as_str() {
-        "a" => {
-            // Links default to blue color
-            if !values.contains_key("color") {
-                values.insert(
-                    "color".to_string(),
-                    Value::Color(Color {
-                        r: 0,
-                        g: 0,
-                        b: 238,  // Blue
-                        a: 255,
-                    }),
-                );
-            }
+fn apply_default_styles(elem: &ElementData, defaults: &ElementDefaults, values: &mut PropertyMap) {
+    if let Some(properties) = defaults.styles.get(&elem.tag_name) {
+        values.extend(properties.clone());
+    }
+    if elem.tag_name == "input" {
+        apply_input_default_styles(elem, values);
+    }
+}
+
+/// Size form fields by their type, with a thin gray border so they show up
+/// as boxes even without author styles
+fn apply_input_default_styles(elem: &ElementData, values: &mut PropertyMap) {
+    let px = |n: f32| Value::Length(n, Unit::Px);
+    let display = match elem.get_attribute("type").map(String::as_str) {
+        Some("hidden") => "none",
+        _ => "inline-block",
+    };
+    values.insert("display".to_string(), Value::Keyword(display.to_string()));
+    let (width, height) = match elem.get_attribute("type").map(String::as_str) {
+        Some("checkbox") | Some("radio") => (px(13.0), px(13.0)),
+        _ => {
+            // `size` is the visible width in characters (default 20)
+            let size = elem
+                .get_attribute("size")
+                .and_then(|size| size.parse::<f32>().ok())
+                .filter(|size| *size > 0.0)
+                .unwrap_or(20.0);
+            values.insert("padding".to_string(), px(2.0));
+            (px(size * 8.0), px(14.0))
         }
-        "h1" => {
-            if !values.contains_key("color") {
-                values.insert(
-                    "color".to_string(),
-                    Value::Color(Color {
-                        r: 0,
-                        g: 0,
-                        b: 0,
-                        a: 255,
-                    }),
-                );
-            }
+    };
+    values.insert("width".to_string(), width);
+    values.insert("height".to_string(), height);
+    values.insert("border-width".to_string(), px(1.0));
+    values.insert(
+        "border-color".to_string(),
+        Value::Color(Color {
+            r: 118,
+            g: 118,
+            b: 118,
+            a: 255,
+        }),
+    );
+    values.insert(
+        "background-color".to_string(),
+        Value::Color(Color {
+            r: 255,
+            g: 255,
+            b: 255,
+            a: 255,
+        }),
+    );
+}
+
+/// The rules of a stylesheet that no element of the document matches. A
+/// rule counts as used if some element matches it in any hover or visited
+/// state, or has the pseudo-element it selects; `@media` conditions are
+/// ignored, since a rule for another viewport isn't dead.
+pub fn unused_rules<'a>(root: &Node, stylesheet: &'a Stylesheet) -> Vec<&'a crate::css::Rule> {
+    let mut used: HashSet<*const crate::css::Rule> = HashSet::new();
+    root.walk(&mut |node, depth| {
+        let NodeType::Element(ref elem) = node.node_type else {
+            return;
+        };
+        for state in possible_states(depth == 0) {
+            let rules = matching_rules(elem, state, stylesheet, None);
+            used.extend(rules.into_iter().map(|(_, rule)| rule as *const _));
         }
-        _ => {}
-    }
+    });
+    stylesheet
+        .rules
+        .iter()
+        .filter(|&rule| !used.contains(&(rule as *const _)))
+        .collect()
 }
 
-/// Find all rules that match an element
+/// Every state selectors can see an element in
+fn possible_states(is_root: bool) -> impl Iterator<Item = ElementState> {
+    let pseudo_elements = [
+        None,
+        Some(PseudoElement::Before),
+        Some(PseudoElement::After),
+    ];
+    pseudo_elements.into_iter().flat_map(move |pseudo_element| {
+        [(false, false), (true, false), (false, true), (true, true)]
+            .into_iter()
+            .map(move |(hovered, visited)| ElementState {
+                is_root,
+                hovered,
+                visited,
+                pseudo_element,
+            })
+    })
+}
+
+/// Find all rules that match an element, leaving out `@media` rules whose
+/// queries don't hold in `cascade` (or keeping them all without one)
 fn matching_rules<'a>(
     elem: &ElementData,
+    state: ElementState,
     stylesheet: &'a Stylesheet,
+    cascade: Option<&CascadeContext>,
 ) -> Vec<(Specificity, &'a crate::css::Rule)> {
     stylesheet
         .rules
         .iter()
-        .filter_map(|rule| match_rule(elem, rule))
+        .filter(|rule| match (&rule.media, cascade) {
+            (Some(queries), Some(cascade)) => media_matches(queries, cascade),
+            _ => true,
+        })
+        .filter_map(|rule| match_rule(elem, state, rule))
         .collect()
 }
 
+/// Whether any query of an `@media` list holds for the cascade's viewport
+/// width and color scheme
+fn media_matches(queries: &[MediaQuery], cascade: &CascadeContext) -> bool {
+    let viewport_width = cascade.viewport_width;
+    queries.iter().any(|query| {
+        query.iter().all(|condition| match condition {
+            MediaCondition::Type(media_type) => matches!(media_type.as_str(), "all" | "screen"),
+            MediaCondition::MinWidth(width) => {
+                media_length(width).is_some_and(|width| viewport_width >= width)
+            }
+            MediaCondition::MaxWidth(width) => {
+                media_length(width).is_some_and(|width| viewport_width <= width)
+            }
+            MediaCondition::PrefersColorScheme(scheme) => match cascade.color_scheme {
+                ColorScheme::Light => scheme == "light",
+                ColorScheme::Dark => scheme == "dark",
+            },
+            MediaCondition::Unsupported => false,
+        })
+    })
+}
+
+/// A media feature length in px; font-relative units use the default font
+/// size, since queries don't depend on the document's styles
+fn media_length(value: &Value) -> Option<f32> {
+    let context = LengthContext {
+        font_size: DEFAULT_FONT_SIZE,
+        root_font_size: DEFAULT_FONT_SIZE,
+        viewport_width: 0.0,
+        viewport_height: 0.0,
+        percent_base: 0.0,
+    };
+    match *value {
+        Value::Length(_, Unit::Percent | Unit::Vw | Unit::Vh) => None,
+        Value::Length(..) => Some(resolve_length(value, &context)),
+        Value::Number(0.0) => Some(0.0),
+        _ => None,
+    }
+}
+
 /// Check if a rule matches an element, return specificity if it does
 fn match_rule<'a>(
     elem: &ElementData,
+    state: ElementState,
     rule: &'a crate::css::Rule,
 ) -> Option<(Specificity, &'a crate::css::Rule)> {
     rule.selectors
         .iter()
-        .find(|selector| matches(elem, *selector))
+        .find(|selector| matches(elem, state, selector))
         .map(|selector| (selector.specificity(), rule))
 }
 
-/// Check if a selector matches an element
-fn matches(elem: &ElementData, selector: &Selector) -> bool {
+/// Check if a selector matches an element in the given state
+pub fn matches(elem: &ElementData, state: ElementState, selector: &Selector) -> bool {
     match *selector {
-        Selector::Simple(ref simple_selector) => matches_simple_selector(elem, simple_selector),
+        Selector::Simple(ref simple_selector) => {
+            simple_selector.pseudo_element == state.pseudo_element
+                && matches_simple_selector(elem, state, simple_selector)
+        }
     }
 }
 
-fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> bool {
+/// The `href` of an element that's a hyperlink
+fn link_href(elem: &ElementData) -> Option<&str> {
+    match elem.tag_name.as_str() {
+        "a" | "area" => elem.get_attribute("href").map(String::as_str),
+        _ => None,
+    }
+}
+
+fn matches_attribute(elem: &ElementData, selector: &AttributeSelector) -> bool {
+    let Some(value) = elem.get_attribute(&selector.name) else {
+        return false;
+    };
+    // An empty prefix, suffix, or substring matches nothing
+    match selector.operator {
+        AttributeOperator::Exists => true,
+        AttributeOperator::Equals(ref expected) => value == expected,
+        AttributeOperator::Includes(ref word) => value.split_whitespace().any(|w| w == word),
+        AttributeOperator::Prefix(ref prefix) => !prefix.is_empty() && value.starts_with(prefix),
+        AttributeOperator::Suffix(ref suffix) => !suffix.is_empty() && value.ends_with(suffix),
+        AttributeOperator::Substring(ref part) => !part.is_empty() && value.contains(part),
+    }
+}
+
+fn matches_simple_selector(
+    elem: &ElementData,
+    state: ElementState,
+    selector: &SimpleSelector,
+) -> bool {
+    // Check :root
+    if selector.root && !state.is_root {
+        return false;
+    }
+
+    // Check :hover
+    if selector.hover && !state.hovered {
+        return false;
+    }
+
+    // Check :link and :visited
+    let is_link = link_href(elem).is_some();
+    if selector.link && (!is_link || state.visited) {
+        return false;
+    }
+    if selector.visited && (!is_link || !state.visited) {
+        return false;
+    }
+
     // Check tag name
     if selector.tag_name.iter().any(|name| elem.tag_name != *name) {
         return false;
@@ -200,5 +1368,565 @@ fn matches_simple_selector(elem: &ElementData, selector: &SimpleSelector) -> boo
         return false;
     }
 
+    // Check attributes
+    if !selector
+        .attributes
+        .iter()
+        .all(|attribute| matches_attribute(elem, attribute))
+    {
+        return false;
+    }
+
+    // Check :not()
+    if selector
+        .negations
+        .iter()
+        .flatten()
+        .any(|negation| matches_simple_selector(elem, state, negation))
+    {
+        return false;
+    }
+
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{css, html, ua};
+
+    /// Style `html` with `css` in an 800px viewport, and hand the root to
+    /// `check`
+    fn with_styles(html: &str, css: &str, check: impl FnOnce(&StyledNode)) {
+        let document = html::Parser::parse(html.to_string());
+        let stylesheet = css::Parser::parse(css.to_string());
+        let ua_stylesheet = ua::stylesheet();
+        check(&style_tree(
+            &document,
+            &[&stylesheet],
+            &ua_stylesheet,
+            800.0,
+        ));
+    }
+
+    fn tag_name(node: &StyledNode) -> String {
+        match node.node.node_type {
+            NodeType::Element(ref elem) => elem.tag_name.clone(),
+            NodeType::Text(_) => "#text".to_string(),
+        }
+    }
+
+    #[test]
+    fn descendants_are_in_document_order() {
+        with_styles("<div><p><b></b></p><i></i></div>", "", |root| {
+            let tags: Vec<String> = root.descendants().map(tag_name).collect();
+            assert_eq!(tags, vec!["p", "b", "i"]);
+        });
+    }
+
+    #[test]
+    fn descendants_can_be_filtered_for_hidden_nodes() {
+        let css = ".gone { display: none; }";
+        let html = "<div><p class=gone></p><p><b class=gone></b><i></i></p></div>";
+        with_styles(html, css, |root| {
+            let hidden = root
+                .descendants()
+                .filter(|node| node.display() == Display::None)
+                .count();
+            assert_eq!(hidden, 2);
+        });
+    }
+
+    #[test]
+    fn computed_values_resolve_em_lengths() {
+        let css = "div { font-size: 20px; margin-top: 2em; color: #00ff00; }";
+        with_styles("<div></div>", css, |root| {
+            let values = root.computed_values();
+            assert_eq!(values["margin-top"], "40px");
+            assert_eq!(values["color"], "#00ff00");
+        });
+    }
+
+    #[test]
+    fn computed_values_are_sorted_by_name() {
+        let css = "span { width: 100px; color: red; }";
+        with_styles("<span></span>", css, |root| {
+            let values: Vec<(String, String)> = root.computed_values().into_iter().collect();
+            assert_eq!(
+                values,
+                vec![
+                    ("color".to_string(), "red".to_string()),
+                    ("font-size".to_string(), "16px".to_string()),
+                    ("width".to_string(), "100px".to_string())
+                ]
+            );
+        });
+    }
+
+    #[test]
+    fn computed_values_resolve_font_relative_units() {
+        let css = "div { font-size: 20px; width: 3ch; height: 2ex; margin-top: 6pt; \
+                   margin-left: 2rem; padding-left: 10%; padding-top: 5vw; padding-bottom: 5vh; }";
+        with_styles("<div></div>", css, |root| {
+            let values = root.computed_values();
+            assert_eq!(values["width"], "30px");
+            assert_eq!(values["height"], "20px");
+            assert_eq!(values["margin-top"], "8px");
+            assert_eq!(values["margin-left"], "2rem");
+            assert_eq!(values["padding-left"], "10%");
+            assert_eq!(values["padding-top"], "5vw");
+            assert_eq!(values["padding-bottom"], "5vh");
+        });
+    }
+
+    /// Computed `color` of a `<p>` styled by UA, author, and user sheets
+    fn cascaded_color(ua_css: &str, author_css: &str, user_css: &str) -> String {
+        let document = html::Parser::parse("<p></p>".to_string());
+        let ua_stylesheet = css::Parser::parse(ua_css.to_string());
+        let author = css::Parser::parse(author_css.to_string());
+        let user = css::Parser::parse(user_css.to_string());
+        let element_defaults = ElementDefaults::default();
+        let cascade = CascadeContext {
+            user_agent: &ua_stylesheet,
+            user: Some(&user),
+            author: &[&author],
+            element_defaults: &element_defaults,
+            hovered: None,
+            viewport_width: 800.0,
+            color_scheme: ColorScheme::Light,
+            visited: None,
+            base_url: None,
+        };
+        style_tree_with_cascade(&document, &cascade).computed_values()["color"].clone()
+    }
+
+    #[test]
+    fn cascade_orders_origins_and_importance() {
+        let (red, blue) = ("p { color: #ff0000; }", "p { color: #0000ff; }");
+        let (red_important, blue_important) = (
+            "p { color: #ff0000 !important; }",
+            "p { color: #0000ff !important; }",
+        );
+        // Author normal beats UA normal
+        assert_eq!(cascaded_color(red, blue, ""), "#0000ff");
+        // UA !important beats author !important
+        assert_eq!(cascaded_color(red_important, blue_important, ""), "#ff0000");
+        // User normal beats author normal
+        assert_eq!(cascaded_color("", red, blue), "#0000ff");
+        // User !important beats author !important
+        assert_eq!(cascaded_color("", blue_important, red_important), "#ff0000");
+    }
+
+    #[test]
+    fn user_rules_beat_author_rules_of_equal_specificity() {
+        let document = html::Parser::parse("<p></p>".to_string());
+        let ua_stylesheet = ua::stylesheet();
+        let author = css::Parser::parse(
+            "p { color: #ff0000; width: 10px !important; height: 10px !important; }".to_string(),
+        );
+        let user = css::Parser::parse(
+            "p { color: #0000ff; width: 20px; height: 20px !important; }".to_string(),
+        );
+        let element_defaults = ElementDefaults::default();
+        let cascade = CascadeContext {
+            user_agent: &ua_stylesheet,
+            user: Some(&user),
+            author: &[&author],
+            element_defaults: &element_defaults,
+            hovered: None,
+            viewport_width: 800.0,
+            color_scheme: ColorScheme::Light,
+            visited: None,
+            base_url: None,
+        };
+        let values = style_tree_with_cascade(&document, &cascade).computed_values();
+        assert_eq!(values["color"], "#0000ff");
+        // The author's `!important` still wins over a normal user rule
+        assert_eq!(values["width"], "10px");
+        // ...but not over the user's own `!important`
+        assert_eq!(values["height"], "20px");
+    }
+
+    #[test]
+    fn resolve_length_handles_every_unit() {
+        let context = LengthContext {
+            font_size: 16.0,
+            root_font_size: 20.0,
+            viewport_width: 800.0,
+            viewport_height: 600.0,
+            percent_base: 200.0,
+        };
+        let cases = [
+            (Unit::Px, 10.0),
+            (Unit::Em, 160.0),
+            (Unit::Rem, 200.0),
+            (Unit::Ex, 80.0),
+            (Unit::Ch, 80.0),
+            (Unit::Pt, 10.0 * 96.0 / 72.0),
+            (Unit::Percent, 20.0),
+            (Unit::Vw, 80.0),
+            (Unit::Vh, 60.0),
+        ];
+        for (unit, px) in cases {
+            let length = Value::Length(10.0, unit);
+            assert_eq!(resolve_length(&length, &context), px, "{:?}", length);
+        }
+    }
+
+    #[test]
+    fn style_attributes_outrank_selectors_and_resolve_urls_against_the_document() {
+        let document = html::Parser::parse(
+            "<p id=\"a\" style=\"color: #0000ff; background-image: url(bg.ppm)\"></p>".to_string(),
+        );
+        let ua_stylesheet = ua::stylesheet();
+        let author = css::Parser::parse("#a { color: #ff0000; }".to_string());
+        let element_defaults = ElementDefaults::default();
+        let cascade = CascadeContext {
+            user_agent: &ua_stylesheet,
+            user: None,
+            author: &[&author],
+            element_defaults: &element_defaults,
+            hovered: None,
+            viewport_width: 800.0,
+            color_scheme: ColorScheme::Light,
+            visited: None,
+            base_url: Some("https://example.com/dir/page.html"),
+        };
+        let styled = style_tree_with_cascade(&document, &cascade);
+        assert_eq!(styled.computed_values()["color"], "#0000ff");
+        assert_eq!(
+            styled.value("background-image"),
+            Some(Value::Url("https://example.com/dir/bg.ppm".to_string()))
+        );
+    }
+
+    #[test]
+    fn links_to_visited_urls_match_visited() {
+        let document = html::Parser::parse(
+            "<div><a href=\"seen.html\"></a><a href=\"new.html\"></a></div>".to_string(),
+        );
+        let ua_stylesheet = ua::stylesheet();
+        let author = css::Parser::parse(
+            "a:link { color: #0000ff; } a:visited { color: #800080; }".to_string(),
+        );
+        let element_defaults = ElementDefaults::default();
+        let visited = HashSet::from(["https://example.com/seen.html".to_string()]);
+        let cascade = CascadeContext {
+            user_agent: &ua_stylesheet,
+            user: None,
+            author: &[&author],
+            element_defaults: &element_defaults,
+            hovered: None,
+            viewport_width: 800.0,
+            color_scheme: ColorScheme::Light,
+            visited: Some(&visited),
+            base_url: Some("https://example.com/index.html"),
+        };
+        let styled = style_tree_with_cascade(&document, &cascade);
+        let colors: Vec<String> = styled
+            .children
+            .iter()
+            .map(|link| link.computed_values()["color"].clone())
+            .collect();
+        assert_eq!(colors, vec!["#800080", "#0000ff"]);
+    }
+
+    #[test]
+    fn later_stylesheets_win_ties() {
+        let document = html::Parser::parse("<p></p>".to_string());
+        let first = css::Parser::parse("p { color: #ff0000; }".to_string());
+        let second = css::Parser::parse("p { color: #0000ff; }".to_string());
+        let ua_stylesheet = ua::stylesheet();
+        let styled = style_tree(&document, &[&first, &second], &ua_stylesheet, 800.0);
+        assert_eq!(styled.computed_values()["color"], "#0000ff");
+        let styled = style_tree(&document, &[&second, &first], &ua_stylesheet, 800.0);
+        assert_eq!(styled.computed_values()["color"], "#ff0000");
+    }
+
+    #[test]
+    fn shorthands_override_earlier_longhands() {
+        let css = "p { padding-left: 5px; margin: 1px; } p { padding: 2px; margin-top: 3px; }";
+        with_styles("<p></p>", css, |root| {
+            assert_eq!(root.value("padding-left"), None);
+            assert_eq!(root.value("padding"), Some(Value::Length(2.0, Unit::Px)));
+            // A longhand after its shorthand still applies
+            assert_eq!(root.value("margin-top"), Some(Value::Length(3.0, Unit::Px)));
+        });
+    }
+
+    #[test]
+    fn unused_rules_are_those_matching_no_element() {
+        let document = html::Parser::parse("<div><p class=\"a\"></p></div>".to_string());
+        let stylesheet = css::Parser::parse(
+            "p.a { color: #ff0000; } p.b { color: #0000ff; } \
+             a:visited { color: #800080; } @media (max-width: 10px) { div { color: #000000; } }"
+                .to_string(),
+        );
+        let unused = unused_rules(&document, &stylesheet);
+        assert_eq!(unused.len(), 2);
+        assert!(std::ptr::eq(unused[0], &stylesheet.rules[1]));
+        assert!(std::ptr::eq(unused[1], &stylesheet.rules[2]));
+    }
+
+    /// Whether each child of a `<div>` made of `children` matches `selector`
+    fn children_matching(children: &str, selector: &str) -> Vec<bool> {
+        let mut matched = Vec::new();
+        let css = format!("{} {{ outline-color: #ff0000; }}", selector);
+        with_styles(&format!("<div>{}</div>", children), &css, |root| {
+            matched = root
+                .children
+                .iter()
+                .map(|child| child.value("outline-color").is_some())
+                .collect();
+        });
+        matched
+    }
+
+    #[test]
+    fn attribute_operators_match_words_prefixes_suffixes_and_substrings() {
+        let links = "<a class=\"big foo\" href=\"https://x.org/a.pdf\"></a>\
+                     <a class=\"foobar\" href=\"http://sub.x.org/a.html\"></a>";
+        assert_eq!(children_matching(links, "[class~=foo]"), vec![true, false]);
+        assert_eq!(
+            children_matching(links, "[href^=\"https\"]"),
+            vec![true, false]
+        );
+        assert_eq!(
+            children_matching(links, "[href$=\".pdf\"]"),
+            vec![true, false]
+        );
+        assert_eq!(
+            children_matching(links, "[href*=\"sub\"]"),
+            vec![false, true]
+        );
+    }
+    #[test]
+    fn before_content_prepends_a_text_box() {
+        let css = ".x::before { content: \">\"; }";
+        with_styles("<div><p class=\"x\">a</p><p>b</p></div>", css, |root| {
+            let texts = |node: &StyledNode| -> Vec<String> {
+                node.children
+                    .iter()
+                    .filter_map(|child| child.text().map(String::from))
+                    .collect()
+            };
+            assert_eq!(texts(&root.children[0]), vec![">", "a"]);
+            assert_eq!(texts(&root.children[1]), vec!["b"]);
+        });
+    }
+
+    #[test]
+    fn root_matches_only_the_document_element_with_id_specificity() {
+        // `:root` outranks a later tag selector, as an id would
+        let css = ":root { color: red; width: 10px; } html { color: blue; }";
+        with_styles("<html><body><p></p></body></html>", css, |root| {
+            assert_eq!(root.value("color"), Some(Value::Keyword("red".to_string())));
+            assert_eq!(root.value("width"), Some(Value::Length(10.0, Unit::Px)));
+            assert!(root.descendants().all(|node| node.value("width").is_none()));
+        });
+        assert_eq!(css::selector_specificity(":root"), Ok((1, 0, 0)));
+    }
+
+    #[test]
+    fn a_custom_user_agent_stylesheet_supplies_defaults() {
+        let document = html::Parser::parse("<html><body></body></html>".to_string());
+        let ua_stylesheet =
+            css::Parser::parse("body { background-color: #000000; color: #000000; }".to_string());
+        let author = css::Parser::parse("html { color: #ff0000; }".to_string());
+        let styled = style_tree(&document, &[&author], &ua_stylesheet, 800.0);
+        let body = &styled.children[0].computed_values();
+        assert_eq!(body["background-color"], "#000000");
+        assert_eq!(body["color"], "#000000");
+
+        // An author rule for the element itself still wins
+        let author = css::Parser::parse("body { background-color: #ffffff; }".to_string());
+        let styled = style_tree(&document, &[&author], &ua_stylesheet, 800.0);
+        assert_eq!(
+            styled.children[0].computed_values()["background-color"],
+            "#ffffff"
+        );
+    }
+
+    #[test]
+    fn not_matches_elements_matching_none_of_its_arguments() {
+        let children = "<p class=\"a\"></p><p class=\"b\"></p><p></p><span></span>";
+        assert_eq!(
+            children_matching(children, "p:not(.a)"),
+            vec![false, true, true, false]
+        );
+        assert_eq!(
+            children_matching(children, ":not(.a, .b)"),
+            vec![false, false, true, true]
+        );
+        assert_eq!(
+            children_matching(children, "p:not(.a):not(span)"),
+            vec![false, true, true, false]
+        );
+    }
+
+    #[test]
+    fn rem_font_sizes_are_relative_to_the_root_element() {
+        let css = "html { font-size: 20px; } div { font-size: 10px; } p { font-size: 2rem; }";
+        with_styles(
+            "<html><body><div><p></p></div></body></html>",
+            css,
+            |root| {
+                let p = root
+                    .descendants()
+                    .find(|node| tag_name(node) == "p")
+                    .unwrap();
+                assert_eq!(p.font_size(), 40.0);
+            },
+        );
+    }
+
+    #[test]
+    fn hovered_links_are_underlined_by_the_user_agent_sheet() {
+        let document = html::Parser::parse(
+            "<div><a href=\"a.html\"></a><a href=\"b.html\"></a></div>".to_string(),
+        );
+        let ua_stylesheet = ua::stylesheet();
+        let author = css::Parser::parse(String::new());
+        let element_defaults = ElementDefaults::default();
+        let decorations = |hovered: Option<&Node>| -> Vec<Option<Value>> {
+            let cascade = CascadeContext {
+                user_agent: &ua_stylesheet,
+                user: None,
+                author: &[&author],
+                element_defaults: &element_defaults,
+                hovered,
+                viewport_width: 800.0,
+                color_scheme: ColorScheme::Light,
+                visited: None,
+                base_url: None,
+            };
+            style_tree_with_cascade(&document, &cascade)
+                .children
+                .iter()
+                .map(|link| link.value("text-decoration"))
+                .collect()
+        };
+        let underline = Some(Value::Keyword("underline".to_string()));
+        assert_eq!(decorations(None), vec![None, None]);
+        assert_eq!(
+            decorations(Some(&document.children[1])),
+            vec![None, underline]
+        );
+    }
+
+    #[test]
+    fn min_width_media_rules_apply_only_to_wide_viewports() {
+        let document = html::Parser::parse("<p></p>".to_string());
+        let stylesheet = css::Parser::parse(
+            "p { color: #ff0000; } @media (min-width: 600px) { p { color: #0000ff; } }".to_string(),
+        );
+        let ua_stylesheet = ua::stylesheet();
+        let color = |viewport_width| {
+            style_tree(&document, &[&stylesheet], &ua_stylesheet, viewport_width).computed_values()
+                ["color"]
+                .clone()
+        };
+        assert_eq!(color(500.0), "#ff0000");
+        assert_eq!(color(800.0), "#0000ff");
+    }
+
+    #[test]
+    fn pre_computes_to_white_space_pre_and_keeps_its_spaces() {
+        with_styles("<pre>a   b\n  c</pre>", "", |root| {
+            assert_eq!(root.computed_values()["white-space"], "pre");
+            assert!(root.white_space() == WhiteSpace::Pre);
+
+            let mut viewport = crate::layout::Dimensions::default();
+            viewport.content.width = 800.0;
+            let layout_root = crate::layout::layout_tree(root, viewport);
+            let mut lines = Vec::new();
+            let mut stack = vec![&layout_root];
+            while let Some(layout_box) = stack.pop() {
+                lines.extend(layout_box.fragments.iter().map(|f| f.text.clone()));
+                stack.extend(layout_box.children.iter().rev());
+            }
+            assert_eq!(lines, vec!["a   b", "  c"]);
+        });
+    }
+
+    #[test]
+    fn percentage_font_sizes_are_of_the_parent_font_size() {
+        let css = "div { font-size: 16px; } p { font-size: 150%; }";
+        with_styles("<div><p></p></div>", css, |root| {
+            assert_eq!(root.children[0].font_size(), 24.0);
+            assert_eq!(root.children[0].computed_values()["font-size"], "24px");
+        });
+    }
+
+    #[test]
+    fn inherit_takes_the_parent_value_of_any_property() {
+        // `border-width` isn't inherited unless asked for
+        let css = "div { border-width: 3px; color: #00ff00; } \
+                   .a { border-width: inherit; } .b { color: inherit; }";
+        with_styles(
+            "<div><p class=a></p><p></p><p class=b></p></div>",
+            css,
+            |root| {
+                let values: Vec<_> = root.children.iter().map(|p| p.computed_values()).collect();
+                assert_eq!(values[0]["border-width"], "3px");
+                assert_eq!(values[1].get("border-width"), None);
+                assert_eq!(values[2]["color"], "#00ff00");
+            },
+        );
+    }
+
+    #[test]
+    fn all_initial_resets_inherited_properties() {
+        let css = "div { color: #00ff00; font-size: 30px; } \
+                   .reset { all: initial; width: 10px; }";
+        with_styles("<div><p class=reset></p><p></p></div>", css, |root| {
+            let reset = root.children[0].computed_values();
+            assert_eq!(reset.get("color"), None);
+            assert_eq!(reset["font-size"], "16px");
+            // Declarations of its own still apply
+            assert_eq!(reset["width"], "10px");
+            assert_eq!(root.children[1].computed_values()["color"], "#00ff00");
+        });
+    }
+
+    #[test]
+    fn the_dark_scheme_gives_the_body_light_text_on_dark() {
+        let document =
+            html::Parser::parse("<html><body><a href=\"x.html\"></a></body></html>".to_string());
+        let ua_stylesheet = ua::stylesheet();
+        let author = css::Parser::parse(String::new());
+        let element_defaults = ElementDefaults::default();
+        let body_and_link = |color_scheme| {
+            let cascade = CascadeContext {
+                user_agent: &ua_stylesheet,
+                user: None,
+                author: &[&author],
+                element_defaults: &element_defaults,
+                hovered: None,
+                viewport_width: 800.0,
+                color_scheme,
+                visited: None,
+                base_url: None,
+            };
+            let styled = style_tree_with_cascade(&document, &cascade);
+            let body = &styled.children[0];
+            (body.computed_values(), body.children[0].computed_values())
+        };
+        let (body, link) = body_and_link(ColorScheme::Dark);
+        assert_eq!(body["background-color"], "#121212");
+        assert_eq!(body["color"], "#e8e8e8");
+        assert_eq!(link["color"], "#8ab4f8");
+        let (body, _) = body_and_link(ColorScheme::Light);
+        assert_eq!(body.get("background-color"), None);
+        assert_eq!(body.get("color"), None);
+    }
+
+    #[test]
+    fn em_is_italic_by_default() {
+        with_styles("<p><em>a</em><span>b</span></p>", "", |root| {
+            let style = |node: &StyledNode| node.computed_values().get("font-style").cloned();
+            assert_eq!(style(&root.children[0]), Some("italic".to_string()));
+            assert_eq!(style(&root.children[1]), None);
+        });
+    }
+}
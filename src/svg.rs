@@ -0,0 +1,167 @@
+use crate::css::Color;
+use crate::layout::{text_scale, Rect, TextSpacing, BASELINE};
+use crate::painting::{DisplayCommand, DisplayList};
+use crate::style::FontFace;
+use std::fmt::Write;
+
+/// Font size for text at the default size, matching the glyph height the
+/// canvas paints
+const FONT_SIZE: f32 = 12.0;
+
+/// Render a display list as an SVG document of the given size. Like the
+/// canvas, the page starts out white.
+pub fn build_svg(display_list: &DisplayList, width: usize, height: usize) -> String {
+    let mut svg = String::new();
+    writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}">"#,
+        width, height
+    )
+    .unwrap();
+    writeln!(
+        svg,
+        r##"<rect width="100%" height="100%" fill="#ffffff"/>"##
+    )
+    .unwrap();
+
+    let mut clip_id = 0;
+    for item in display_list {
+        match item {
+            DisplayCommand::SolidColor(color, rect) => {
+                writeln!(svg, "<rect {}{}/>", rect_attributes(rect), fill(color)).unwrap();
+            }
+            DisplayCommand::RoundedColor(color, rect, (rx, ry)) => {
+                writeln!(
+                    svg,
+                    r#"<rect {} rx="{}" ry="{}"{}/>"#,
+                    rect_attributes(rect),
+                    rx,
+                    ry,
+                    fill(color)
+                )
+                .unwrap();
+            }
+            // Placed on the baseline layout lined the text up on
+            DisplayCommand::Text(text, rect, color, spacing, face) => {
+                let scale = text_scale(face.size);
+                writeln!(
+                    svg,
+                    r#"<text x="{}" y="{}" font-family="monospace" font-size="{}"{}{}{}>{}</text>"#,
+                    rect.x,
+                    rect.y + BASELINE * scale,
+                    FONT_SIZE * scale,
+                    spacing_attributes(spacing),
+                    face_attributes(face),
+                    fill(color),
+                    escape(text)
+                )
+                .unwrap();
+            }
+            // Where a background image lands depends on its size, which
+            // only the canvas that decoded it knows
+            DisplayCommand::Image(..) => {}
+            // Each clip becomes a group that stays open until the matching pop
+            DisplayCommand::PushClip(rect) => {
+                clip_id += 1;
+                writeln!(
+                    svg,
+                    r#"<clipPath id="clip{0}"><rect {1}/></clipPath><g clip-path="url(#clip{0})">"#,
+                    clip_id,
+                    rect_attributes(rect)
+                )
+                .unwrap();
+            }
+            DisplayCommand::PopClip => svg.push_str("</g>\n"),
+        }
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn rect_attributes(rect: &Rect) -> String {
+    format!(
+        r#"x="{}" y="{}" width="{}" height="{}""#,
+        rect.x, rect.y, rect.width, rect.height
+    )
+}
+
+/// `letter-spacing` and `word-spacing` attributes, if either is set
+fn spacing_attributes(spacing: &TextSpacing) -> String {
+    let mut attributes = String::new();
+    if spacing.letter != 0.0 {
+        write!(attributes, r#" letter-spacing="{}""#, spacing.letter).unwrap();
+    }
+    if spacing.word != 0.0 {
+        write!(attributes, r#" word-spacing="{}""#, spacing.word).unwrap();
+    }
+    attributes
+}
+
+/// `font-weight`/`font-style` attributes for a bold or italic face
+fn face_attributes(face: &FontFace) -> String {
+    let mut attributes = String::new();
+    if face.bold {
+        attributes.push_str(r#" font-weight="bold""#);
+    }
+    if face.italic {
+        attributes.push_str(r#" font-style="italic""#);
+    }
+    attributes
+}
+
+/// The `fill` attribute for a color, with its alpha as `fill-opacity`
+fn fill(color: &Color) -> String {
+    if color.a == 255 {
+        format!(r#" fill="{}""#, color)
+    } else {
+        format!(
+            r#" fill="{}" fill-opacity="{:.3}""#,
+            color,
+            color.a as f32 / 255.0
+        )
+    }
+}
+
+/// Escape characters that are special in XML text
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::painting::build_display_list;
+    use crate::{css, html, layout, style, ua};
+
+    #[test]
+    fn a_solid_box_becomes_a_rect() {
+        let document = html::Parser::parse("<div></div>".to_string());
+        let stylesheet = css::Parser::parse(
+            "div { display: block; margin-left: 5px; width: 20px; height: 10px; \
+             background: #ff0000; }"
+                .to_string(),
+        );
+        let ua_stylesheet = ua::stylesheet();
+        let styled = style::style_tree(&document, &[&stylesheet], &ua_stylesheet, 40.0);
+        let mut viewport = layout::Dimensions::default();
+        viewport.content.width = 40.0;
+        let display_list = build_display_list(&layout::layout_tree(&styled, viewport));
+
+        let svg = build_svg(&display_list, 40, 30);
+        assert!(
+            svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="40" height="30""#)
+        );
+        assert!(svg.contains(r##"<rect x="5" y="0" width="20" height="10" fill="#ff0000"/>"##));
+        assert!(svg.ends_with("</svg>\n"));
+    }
+}
@@ -0,0 +1,28 @@
+use crate::css::{self, Stylesheet};
+
+/// Built-in styles every document starts from, applied before (and
+/// overridden by) the page's own stylesheet
+const UA_CSS: &str = "
+head, title, meta, link, script, style { display: none; }
+pre { display: block; white-space: pre; }
+code, pre, kbd, samp, tt { font-family: monospace; }
+ul, ol { padding-left: 40px; }
+ol { list-style-type: decimal; }
+hr { display: block; margin-top: 8px; margin-bottom: 8px; border-top-width: 1px; border-color: #808080; }
+b, strong { font-weight: bold; }
+i, em { font-style: italic; }
+u { text-decoration: underline; }
+a:visited { color: #551a8b; }
+a:hover { text-decoration: underline; }
+@media (prefers-color-scheme: dark) {
+    body { background: #121212; color: #e8e8e8; }
+    a { color: #8ab4f8; }
+    a:visited { color: #c58af9; }
+}
+";
+
+/// Parse the default user-agent stylesheet. Embedders can pass their own
+/// stylesheet to `style::style_tree` instead to customize the defaults.
+pub fn stylesheet() -> Stylesheet {
+    css::Parser::parse(UA_CSS.to_string())
+}